@@ -0,0 +1,224 @@
+use fallible_iterator::FallibleIterator;
+use pdb::{IdFinder, IdIndex};
+use pyo3::exceptions::*;
+use pyo3::{PyErr, PyResult};
+use crate::bytes;
+
+/*
+ *
+ * COMPILER AND BUILD INFORMATION
+ *
+ * Every module stream starts with records describing how it was built:
+ *      S_COMPILE2 / S_COMPILE3     language, target machine, compiler versions and flags
+ *      S_ENVBLOCK                  key / value pairs (cwd, cl, cmd, src, pdb), mostly written by
+ *                                  the linker for its own "* Linker *" module
+ *      S_BUILDINFO                 points at a LF_BUILDINFO id holding the directory, tool,
+ *                                  source file, PDB and command line the object was built with
+ *
+ */
+
+const S_ENVBLOCK: u16 = 0x113d;
+
+// Flag bits of S_COMPILE2 / S_COMPILE3 (the low byte is the language)
+const COMPILE_FLAGS: &[(u32, &str)] = &[
+    (1 << 8, "edit_and_continue"),
+    (1 << 9, "no_debug_info"),
+    (1 << 10, "ltcg"),
+    (1 << 11, "no_data_align"),
+    (1 << 12, "managed"),
+    (1 << 13, "security_checks"),
+    (1 << 14, "hot_patch"),
+    (1 << 15, "cvtcil"),
+    (1 << 16, "msil_module"),
+    (1 << 17, "sdl"),
+    (1 << 18, "pgo"),
+    (1 << 19, "exp_module"),
+];
+
+// CV_CFL_LANG values newer than what the pdb crate knows (it reports those as Masm)
+const LANGUAGES: &[(u8, &str)] = &[
+    (0x11, "ObjC"),
+    (0x12, "ObjCpp"),
+    (0x13, "Swift"),
+    (0x14, "AliasObj"),
+    (0x15, "Rust"),
+    (0x16, "Go"),
+];
+
+// LF_BUILDINFO argument order (CV_BuildInfo_e)
+const BUILD_INFO_ARGUMENTS: &[&str] = &["cwd", "tool", "source", "pdb", "args"];
+
+#[derive(Debug, Clone)]
+pub struct CompilerInfo {
+    pub language: String,
+    pub machine: String,
+    pub frontend_version: String,
+    pub backend_version: String,
+    pub version_string: String,
+    pub flags: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModuleBuildInfo {
+    pub module: String,
+    pub compiler: Option<CompilerInfo>,
+    // S_ENVBLOCK pairs, in order
+    pub environment: Vec<(String, String)>,
+    // LF_BUILDINFO arguments (cwd, tool, source, pdb, args), only the ones present
+    pub build_info: Vec<(&'static str, String)>,
+}
+
+fn pdb_error(e: pdb::Error) -> PyErr {
+    Exception::py_err(e.to_string())
+}
+
+fn version_string(version: &pdb::CompilerVersion) -> String {
+    match version.qfe {
+        Some(qfe) => format!("{}.{}.{}.{}", version.major, version.minor, version.build, qfe),
+        None => format!("{}.{}.{}", version.major, version.minor, version.build)
+    }
+}
+
+/*
+Reads the NUL separated strings of a S_ENVBLOCK record (after its flags byte) as key / value pairs.
+*/
+fn environment_pairs(data: &[u8]) -> Vec<(String, String)> {
+    let strings: Vec<String> = data.get(3..).unwrap_or(&[])
+                                   .split(|&c| c == 0)
+                                   .take_while(|string| !string.is_empty())
+                                   .map(|string| String::from_utf8_lossy(string).into_owned())
+                                   .collect();
+
+    strings.chunks(2)
+           .map(|pair| (pair[0].clone(), pair.get(1).cloned().unwrap_or_default()))
+           .collect()
+}
+
+/*
+Resolves a LF_STRING_ID, including the substrings long strings (command lines) are split into.
+*/
+fn id_string(id_finder: &IdFinder, index: IdIndex) -> Option<String> {
+    let string = match id_finder.find(index).and_then(|id| id.parse()).ok()? {
+        pdb::IdData::String(string) => string,
+        _ => return None
+    };
+
+    let mut result = String::new();
+    if let Some(substrings) = string.substrings {
+        if let Ok(pdb::IdData::StringList(list)) = id_finder.find(substrings).and_then(|id| id.parse()) {
+            for substring in list.substrings {
+                result.push_str(&id_string(id_finder, IdIndex(substring.0)).unwrap_or_default());
+            }
+        }
+    }
+    result.push_str(&string.name.to_string());
+    Some(result)
+}
+
+/*
+Resolves the arguments of a LF_BUILDINFO id.
+*/
+fn build_info_arguments(id_finder: &IdFinder, index: IdIndex) -> Vec<(&'static str, String)> {
+    let arguments = match id_finder.find(index).and_then(|id| id.parse()) {
+        Ok(pdb::IdData::BuildInfo(build_info)) => build_info.arguments,
+        _ => return Vec::new()
+    };
+
+    arguments.iter()
+             .zip(BUILD_INFO_ARGUMENTS)
+             .filter(|(argument, _)| argument.0 != 0)
+             .filter_map(|(&argument, &name)| id_string(id_finder, argument).map(|value| (name, value)))
+             .collect()
+}
+
+/*
+Collects the compiler and build information of every module.
+
+@pdb_name => Path to the PDB file.
+
+RETURN
+returns one entry per DBI module, in module order.
+*/
+pub fn collect_build_info(pdb_name: &str) -> PyResult<Vec<ModuleBuildInfo>> {
+    let mut pdb = crate::open_pdb_and_parse(pdb_name)?;
+
+    let id_information = pdb.id_information().map_err(pdb_error)?;
+    let id_finder = crate::fill_item_finder(&id_information)?;
+    let debug_information = pdb.debug_information().map_err(pdb_error)?;
+
+    let mut modules_info = Vec::new();
+    let mut modules = debug_information.modules().map_err(pdb_error)?;
+    while let Some(module) = modules.next().map_err(pdb_error)? {
+        let mut module_build_info = ModuleBuildInfo {
+            module: module.module_name().into_owned(),
+            compiler: None,
+            environment: Vec::new(),
+            build_info: Vec::new(),
+        };
+
+        if let Some(module_info) = pdb.module_info(&module).map_err(pdb_error)? {
+            let mut symbols = module_info.symbols().map_err(pdb_error)?;
+            while let Some(symbol) = symbols.next().map_err(pdb_error)? {
+                if symbol.raw_kind() == S_ENVBLOCK {
+                    module_build_info.environment.extend(environment_pairs(symbol.raw_bytes()));
+                    continue;
+                }
+
+                match symbol.parse() {
+                    Ok(pdb::SymbolData::CompileFlags(compile)) => {
+                        // The pdb crate keeps the parsed flags private
+                        let raw_flags = bytes::read_u32(symbol.raw_bytes(), 2).unwrap_or(0);
+
+                        let language = match LANGUAGES.iter().find(|&&(value, _)| value == raw_flags as u8) {
+                            Some(&(_, name)) => String::from(name),
+                            None if raw_flags as u8 > 0x10 && raw_flags as u8 != 0x44 => format!("{:#x}", raw_flags as u8),
+                            None => compile.language.to_string()
+                        };
+
+                        module_build_info.compiler = Some(CompilerInfo {
+                            language,
+                            machine: compile.cpu_type.to_string(),
+                            frontend_version: version_string(&compile.frontend_version),
+                            backend_version: version_string(&compile.backend_version),
+                            version_string: compile.version_string.to_string().into_owned(),
+                            flags: COMPILE_FLAGS.iter().filter(|(bit, _)| raw_flags & bit != 0).map(|&(_, name)| name).collect(),
+                        });
+                    },
+                    Ok(pdb::SymbolData::BuildInfo(build_info)) => {
+                        module_build_info.build_info = build_info_arguments(&id_finder, build_info.id);
+                    },
+                    _ => {}
+                }
+            }
+        }
+        modules_info.push(module_build_info);
+    }
+    Ok(modules_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fixture;
+
+    #[test]
+    fn reads_compiler_and_linker_information() {
+        let modules = collect_build_info(&fixture("symbols.pdb")).unwrap();
+        assert_eq!(modules.len(), 2);
+
+        let compiler = modules[0].compiler.as_ref().unwrap();
+        // S_COMPILE3 language 0x15, newer than the pdb crate's language list
+        assert_eq!((compiler.language.as_str(), compiler.machine.as_str()), ("Rust", "X64"));
+        assert!(compiler.version_string.contains("rustc"));
+        assert!(compiler.flags.is_empty());
+        let arguments: Vec<&str> = modules[0].build_info.iter().map(|(name, _)| *name).collect();
+        assert_eq!(arguments, BUILD_INFO_ARGUMENTS.to_vec());
+        assert!(modules[0].build_info[2].1.starts_with("symbols.rs"));
+
+        assert_eq!(modules[1].module, "* Linker *");
+        assert_eq!(modules[1].compiler.as_ref().unwrap().language, "Link");
+        let environment: Vec<&str> = modules[1].environment.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(environment, vec!["cwd", "exe", "pdb", "cmd"]);
+        assert!(modules[1].environment[3].1.contains("/pdb:symbols.pdb"));
+    }
+}
@@ -0,0 +1,18 @@
+use std::convert::TryInto;
+
+/*
+ *
+ * LITTLE ENDIAN READERS
+ *
+ * Shared by the cabinet, PE, build info and symbol record parsers. A read past the end is None,
+ * callers turn it into their own error.
+ *
+ */
+
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset.checked_add(2)?)?.try_into().ok()?))
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset.checked_add(4)?)?.try_into().ok()?))
+}
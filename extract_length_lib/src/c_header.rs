@@ -0,0 +1,497 @@
+use std::collections::HashSet;
+use pdb::{PrimitiveKind, TypeIndex};
+use crate::codegen::{self, UniqueNames};
+use crate::layout::{self, Field, Member, TypeLayouts, TypeRef, Udt, UdtKind};
+
+/*
+ *
+ * C HEADER GENERATION
+ *
+ * Emits a self contained header for a set of root types: every struct / union / enum they
+ * reference, defined before the types embedding them, with forward declarations for the ones
+ * only pointed at, #pragma pack where the layout needs it and static asserts on every offset.
+ *
+ * Integers are written as <stdint.h> types of the PDB's sizes (ULONG is uint32_t, WCHAR is
+ * uint16_t), so the header describes the target's layout on any compiler for the same
+ * pointer size. Unnamed structs / unions are written inline, where the field using them is.
+ *
+ * Bitfields are the exception, other compilers share storage units MSVC wouldn't. Every unit
+ * is written out whole (unused bits as unnamed bitfields) with explicit padding in front, and
+ * as offsetof can't take a bitfield, the asserts check the padding ends where the unit starts.
+ *
+ */
+
+fn c_primitive(kind: PrimitiveKind) -> Option<&'static str> {
+    match kind {
+        PrimitiveKind::NoType | PrimitiveKind::Void => Some("void"),
+        PrimitiveKind::Char | PrimitiveKind::RChar => Some("char"),
+        PrimitiveKind::UChar => Some("unsigned char"),
+        PrimitiveKind::I8 => Some("int8_t"),
+        PrimitiveKind::U8 | PrimitiveKind::Bool8 => Some("uint8_t"),
+        PrimitiveKind::Short | PrimitiveKind::I16 => Some("int16_t"),
+        PrimitiveKind::UShort | PrimitiveKind::U16 | PrimitiveKind::WChar | PrimitiveKind::RChar16 |
+        PrimitiveKind::Bool16 | PrimitiveKind::F16 => Some("uint16_t"),
+        PrimitiveKind::Long | PrimitiveKind::I32 | PrimitiveKind::Bool32 | PrimitiveKind::HRESULT => Some("int32_t"),
+        PrimitiveKind::ULong | PrimitiveKind::U32 | PrimitiveKind::RChar32 => Some("uint32_t"),
+        PrimitiveKind::Quad | PrimitiveKind::I64 => Some("int64_t"),
+        PrimitiveKind::UQuad | PrimitiveKind::U64 | PrimitiveKind::Bool64 => Some("uint64_t"),
+        PrimitiveKind::F32 | PrimitiveKind::F32PP => Some("float"),
+        PrimitiveKind::F64 => Some("double"),
+        _ => None
+    }
+}
+
+/*
+Turns a type / member name into a C identifier (templates and scopes become underscores).
+*/
+pub fn c_identifier(name: &str) -> String {
+    let mut identifier: String = name.chars()
+                                     .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+                                     .collect();
+    if identifier.is_empty() || identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        identifier.insert(0, '_');
+    }
+    identifier
+}
+
+fn join(base: &str, declarator: &str) -> String {
+    match declarator {
+        "" => String::from(base),
+        declarator => format!("{} {}", base, declarator)
+    }
+}
+
+struct HeaderWriter<'l> {
+    layouts: &'l TypeLayouts,
+    out: String,
+    // Padding member names already used by the struct being written
+    paddings: UniqueNames,
+    // Member path prefix and offset of the inline struct being written
+    scope: String,
+    base: u64,
+    // Paddings in front of bitfield storage units (which offsetof can't take), their size and the
+    // offset of the unit
+    unit_asserts: Vec<(String, u64, u64)>,
+}
+
+impl<'l> HeaderWriter<'l> {
+    fn line(&mut self, indent: usize, text: &str) {
+        codegen::line(&mut self.out, indent, text);
+    }
+
+    fn type_identifier(&self, index: TypeIndex) -> String {
+        codegen::type_identifier(self.layouts, index, c_identifier)
+    }
+
+    fn tag(&self, index: TypeIndex) -> String {
+        match self.layouts.udt(index) {
+            Some(udt) if udt.kind == UdtKind::Union => format!("union {}", self.type_identifier(index)),
+            Some(_) => format!("struct {}", self.type_identifier(index)),
+            None => format!("enum {}", self.type_identifier(index))
+        }
+    }
+
+    /*
+    Unnamed struct / union a field is declared with, which gets written inline.
+    */
+    fn inlined(&self, typ: &TypeRef) -> Option<&'l Udt> {
+        match typ {
+            TypeRef::Udt(index) => self.layouts.udt(*index).filter(|udt| layout::is_unnamed(&udt.name)),
+            _ => None
+        }
+    }
+
+    fn declaration(&self, typ: &TypeRef, declarator: &str) -> String {
+        match typ {
+            TypeRef::Primitive(kind) => match c_primitive(*kind) {
+                Some(name) => join(name, declarator),
+                None => self.declaration(&codegen::primitive_bytes(*kind), declarator)
+            },
+            TypeRef::Udt(index) => join(&self.tag(*index), declarator),
+            TypeRef::Enum(index) => match self.layouts.enumeration(*index) {
+                // C enums are int sized, others are written as their underlying integer
+                Some(enumeration) if layout::primitive_size(enumeration.underlying) != 4 => {
+                    self.declaration(&TypeRef::Primitive(enumeration.underlying), declarator)
+                },
+                _ => join(&self.tag(*index), declarator)
+            },
+            TypeRef::Declared { kind, name } => {
+                let keyword = match kind {
+                    UdtKind::Union => "union",
+                    _ => "struct"
+                };
+                join(&format!("{} {}", keyword, c_identifier(name)), declarator)
+            },
+            TypeRef::Pointer { pointee, .. } => match pointee.as_ref() {
+                TypeRef::Function => join("void", &format!("*{}", declarator)),
+                TypeRef::Array { .. } => self.declaration(pointee, &format!("(*{})", declarator)),
+                pointee => self.declaration(pointee, &format!("*{}", declarator))
+            },
+            TypeRef::Array { element, count } => self.declaration(element, &format!("{}[{}]", declarator, count)),
+            TypeRef::Modified { inner, constant, volatile } => {
+                let mut qualifiers = String::new();
+                if *constant {
+                    qualifiers.push_str("const ");
+                }
+                if *volatile {
+                    qualifiers.push_str("volatile ");
+                }
+
+                match inner.as_ref() {
+                    TypeRef::Pointer { .. } => self.declaration(inner, &format!("{}{}", qualifiers, declarator)),
+                    inner => format!("{}{}", qualifiers, self.declaration(inner, declarator))
+                }
+            },
+            TypeRef::Function | TypeRef::Unknown => join("void", declarator)
+        }
+    }
+
+    fn padding_name(&mut self, offset: u64) -> String {
+        self.paddings.unique(format!("_padding_{:#x}", offset))
+    }
+
+    /*
+    Writes the padding needed in front of a member at offset.
+
+    RETURN
+    returns the name of the padding member, if one was needed.
+    */
+    fn pad(&mut self, indent: usize, offset: u64, alignment: u64, cursor: &mut u64) -> Option<String> {
+        let size = layout::padding(*cursor, offset, alignment)?;
+        let name = self.padding_name(*cursor);
+        self.line(indent, &format!("unsigned char {}[{:#x}];", name, size));
+        *cursor = offset;
+        Some(name)
+    }
+
+    /*
+    Unnamed bitfield covering bits of a storage unit no field uses, so that compilers which
+    pack bitfields tighter than MSVC still give the unit all of its bytes.
+    */
+    fn skip_bits(&mut self, indent: usize, field: &Field, from: u64, to: u64) {
+        if to <= from {
+            return;
+        }
+        let unit = match self.layouts.size_of(&field.typ) {
+            1 => "uint8_t",
+            2 => "uint16_t",
+            4 => "uint32_t",
+            _ => "uint64_t"
+        };
+        self.line(indent, &format!("{} : {};", unit, to - from));
+    }
+
+    fn write_field(&mut self, indent: usize, field: &Field) {
+        let name = c_identifier(&field.name);
+
+        if let (Some(udt), None) = (self.inlined(&field.typ), field.bitfield) {
+            let keyword = match udt.kind {
+                UdtKind::Union => "union",
+                _ => "struct"
+            };
+            self.line(indent, &format!("{} {{", keyword));
+            let (scope, base) = (self.scope.clone(), self.base);
+            self.scope = format!("{}{}.", scope, name);
+            self.base += field.offset;
+            self.write_body(indent + 1, udt);
+            self.scope = scope;
+            self.base = base;
+            self.line(indent, &format!("}} {};", name));
+            return;
+        }
+
+        let declaration = self.declaration(&field.typ, &name);
+        match field.bitfield {
+            Some(bitfield) => self.line(indent, &format!("{} : {};", declaration, bitfield.length)),
+            None => self.line(indent, &format!("{};", declaration))
+        }
+    }
+
+    fn write_sequence(&mut self, indent: usize, members: &[Member], cursor: &mut u64, packing: Option<u64>) {
+        let mut previous: Option<&Field> = None;
+        // Bits of the current bitfield storage unit written so far
+        let mut unit_bits = 0;
+
+        for (position, member) in members.iter().enumerate() {
+            match member {
+                Member::Field(field) => {
+                    if !previous.is_some_and(|previous| layout::is_bitfield_continuation(previous, field)) {
+                        let alignment = self.layouts.padding_alignment(field, packing);
+                        let padding_start = *cursor;
+                        let padding = self.pad(indent, field.offset, alignment, cursor);
+                        if let (Some(name), Some(_)) = (padding, field.bitfield) {
+                            let path = format!("{}{}", self.scope, name);
+                            self.unit_asserts.push((path, field.offset - padding_start, self.base + field.offset));
+                        }
+                        unit_bits = 0;
+                    }
+
+                    match field.bitfield {
+                        Some(bitfield) => {
+                            let (start, end) = (u64::from(bitfield.position), u64::from(bitfield.position) + u64::from(bitfield.length));
+                            self.skip_bits(indent, field, unit_bits, start);
+                            self.write_field(indent, field);
+                            unit_bits = end;
+
+                            let unit_continues = match members.get(position + 1) {
+                                Some(Member::Field(next)) => layout::is_bitfield_continuation(field, next),
+                                _ => false
+                            };
+                            if !unit_continues {
+                                self.skip_bits(indent, field, unit_bits, self.layouts.size_of(&field.typ) * 8);
+                            }
+                        },
+                        None => self.write_field(indent, field)
+                    }
+                    *cursor = (*cursor).max(self.layouts.field_end(field));
+                    previous = Some(field);
+                },
+                Member::Union(alternatives) => {
+                    let start = layout::members_start(member_slice(member));
+                    let alignment = self.layouts.members_alignment(member_slice(member), packing);
+                    self.pad(indent, start, alignment, cursor);
+
+                    self.line(indent, "union {");
+                    let mut end = start;
+                    for alternative in alternatives {
+                        let mut alternative_cursor = start;
+                        if alternative.len() == 1 {
+                            self.write_sequence(indent + 1, alternative, &mut alternative_cursor, packing);
+                        } else {
+                            self.line(indent + 1, "struct {");
+                            self.write_sequence(indent + 2, alternative, &mut alternative_cursor, packing);
+                            self.line(indent + 1, "};");
+                        }
+                        end = end.max(alternative_cursor);
+                    }
+                    self.line(indent, "};");
+                    *cursor = (*cursor).max(end);
+                    previous = None;
+                }
+            }
+        }
+    }
+
+    fn write_body(&mut self, indent: usize, udt: &Udt) {
+        let fields = self.layouts.all_fields(udt);
+        let packing = self.layouts.packing(udt);
+        let alignment = self.layouts.alignment(udt);
+
+        let mut end = 0;
+        if udt.kind == UdtKind::Union {
+            for alternative in self.layouts.union_alternatives(&fields) {
+                let mut cursor = 0;
+                if alternative.len() == 1 {
+                    self.write_sequence(indent, &alternative, &mut cursor, packing);
+                } else {
+                    self.line(indent, "struct {");
+                    self.write_sequence(indent + 1, &alternative, &mut cursor, packing);
+                    self.line(indent, "};");
+                }
+                end = end.max(cursor);
+            }
+            if layout::padding(end, udt.size, alignment).is_some() {
+                let name = self.padding_name(0);
+                self.line(indent, &format!("unsigned char {}[{:#x}];", name, udt.size));
+            }
+        } else {
+            let members = self.layouts.group_members(&fields);
+            self.write_sequence(indent, &members, &mut end, packing);
+            if let Some(size) = layout::padding(end, udt.size, alignment) {
+                let name = self.padding_name(end);
+                self.line(indent, &format!("unsigned char {}[{:#x}];", name, size));
+            }
+        }
+    }
+
+    /*
+    Member paths (through inline unnamed types) whose offset can be asserted.
+    */
+    fn assertable_fields(&self, udt: &Udt, prefix: &str, base: u64, paths: &mut Vec<(String, u64)>) {
+        for field in self.layouts.all_fields(udt) {
+            if field.bitfield.is_some() {
+                continue;
+            }
+
+            let path = format!("{}{}", prefix, c_identifier(&field.name));
+            paths.push((path.clone(), base + field.offset));
+            if let Some(nested) = self.inlined(&field.typ) {
+                self.assertable_fields(nested, &format!("{}.", path), base + field.offset, paths);
+            }
+        }
+    }
+
+    fn write_udt(&mut self, udt: &Udt) {
+        let tag = self.tag(udt.index);
+        let packing = self.layouts.packing(udt);
+        self.paddings.clear();
+        self.unit_asserts.clear();
+
+        if let Some(packing) = packing {
+            self.line(0, &format!("#pragma pack(push, {})", packing));
+        }
+        self.line(0, &format!("{} {{", tag));
+        self.write_body(1, udt);
+        self.line(0, "};");
+        if packing.is_some() {
+            self.line(0, "#pragma pack(pop)");
+        }
+
+        let mut paths = Vec::new();
+        self.assertable_fields(udt, "", 0, &mut paths);
+        for (path, offset) in paths {
+            self.line(0, &format!("C_ASSERT(offsetof({}, {}) == {:#x});", tag, path, offset));
+        }
+        for (padding, size, offset) in std::mem::take(&mut self.unit_asserts) {
+            self.line(0, &format!("C_ASSERT(offsetof({}, {}) + {:#x} == {:#x});", tag, padding, size, offset));
+        }
+        self.line(0, &format!("C_ASSERT(sizeof({}) == {:#x});", tag, udt.size));
+        self.line(0, "");
+    }
+
+    fn write_enum(&mut self, index: TypeIndex) {
+        let enumeration = match self.layouts.enumeration(index) {
+            Some(enumeration) => enumeration,
+            None => return
+        };
+
+        let tag = self.tag(index);
+        self.line(0, &format!("{} {{", tag));
+        for (name, value) in &enumeration.values {
+            self.line(1, &format!("{} = {},", c_identifier(name), layout::underlying_value(*value, enumeration.underlying)));
+        }
+        self.line(0, "};");
+        self.line(0, "");
+    }
+}
+
+fn member_slice<'a, 'm>(member: &'m Member<'a>) -> &'m [Member<'a>] {
+    std::slice::from_ref(member)
+}
+
+/*
+Generates a C header for a set of types.
+
+@layouts         => Type layouts of the PDB.
+@roots           => Types to generate (and everything they reference).
+@follow_pointers => Also define the types that are only pointed at. Otherwise those are only
+                    forward declared.
+@pdb_name        => PDB the types come from (for the header comment).
+
+RETURN
+returns the header text.
+*/
+pub fn generate(layouts: &TypeLayouts, roots: &[TypeIndex], follow_pointers: bool, pdb_name: &str) -> String {
+    let mut writer = HeaderWriter {
+        layouts,
+        out: String::new(),
+        paddings: UniqueNames::default(),
+        scope: String::new(),
+        base: 0,
+        unit_asserts: Vec::new(),
+    };
+
+    let closure = layouts.type_closure(roots, follow_pointers, true);
+
+    writer.line(0, "/*");
+    writer.line(0, &format!(" * Generated by pymspdb from {}", pdb_name));
+    writer.line(0, " */");
+    writer.line(0, "");
+    writer.line(0, "#pragma once");
+    writer.line(0, "");
+    writer.line(0, "#include <stddef.h>");
+    writer.line(0, "#include <stdint.h>");
+    writer.line(0, "");
+    writer.line(0, "#ifndef C_ASSERT");
+    writer.line(0, "#ifdef __cplusplus");
+    writer.line(0, "#define C_ASSERT(e) static_assert(e, #e)");
+    writer.line(0, "#else");
+    writer.line(0, "#define C_ASSERT(e) _Static_assert(e, #e)");
+    writer.line(0, "#endif");
+    writer.line(0, "#endif");
+    writer.line(0, "");
+    writer.line(0, &format!("C_ASSERT(sizeof(void *) == {});", layouts.pointer_size()));
+    writer.line(0, "");
+
+    // Forward declarations for everything pointed at
+    let mut declared = HashSet::new();
+    for typ in &closure.pointed {
+        let declaration = writer.declaration(typ, "");
+        if declared.insert(declaration.clone()) {
+            writer.line(0, &format!("{};", declaration));
+        }
+    }
+    if !declared.is_empty() {
+        writer.line(0, "");
+    }
+
+    // Definitions, each one after the types it embeds
+    for &index in &closure.definitions {
+        match layouts.udt(index) {
+            Some(udt) => writer.write_udt(udt),
+            None => writer.write_enum(index)
+        }
+    }
+
+    writer.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{kernel_layouts, kernel_types};
+
+    fn header(roots: &[&str], follow_pointers: bool) -> String {
+        let layouts = kernel_layouts();
+        let roots = kernel_types(&layouts, roots);
+        generate(&layouts, &roots, follow_pointers, "kernel.pdb")
+    }
+
+    fn definition(header: &str, tag: &str) -> String {
+        let start = header.find(&format!("{} {{", tag)).unwrap();
+        let end = start + header[start..].find("\n\n").unwrap();
+        String::from(&header[start..end])
+    }
+
+    #[test]
+    fn defines_embedded_types_first() {
+        let header = header(&["_EPROCESS"], false);
+        assert!(header.contains("C_ASSERT(sizeof(void *) == 8);"));
+        assert!(header.contains("struct _ETHREAD;\n"));
+        assert!(!header.contains("struct _ETHREAD {"));
+        assert!(header.find("struct _KPROCESS {").unwrap() < header.find("struct _EPROCESS {").unwrap());
+        assert!(header.find("struct _LIST_ENTRY {").unwrap() < header.find("struct _KPROCESS {").unwrap());
+
+        let eprocess = definition(&header, "struct _EPROCESS");
+        assert!(eprocess.contains("    const uint16_t Matrix[2][3];\n"));
+        assert!(eprocess.contains("C_ASSERT(offsetof(struct _EPROCESS, ExitTime) == 0x90);"));
+        assert!(eprocess.contains("C_ASSERT(sizeof(struct _EPROCESS) == 0x98);"));
+    }
+
+    #[test]
+    fn follows_pointers_when_asked() {
+        assert!(header(&["_EPROCESS"], true).contains("struct _ETHREAD {"));
+    }
+
+    #[test]
+    fn keeps_packing() {
+        let header = header(&["_PACKED_HEADER"], false);
+        let packed = definition(&header, "struct _PACKED_HEADER");
+        assert!(packed.contains("#pragma pack(pop)\nC_ASSERT(offsetof(struct _PACKED_HEADER, Type) == 0x0);"));
+        assert!(packed.contains("C_ASSERT(offsetof(struct _PACKED_HEADER, Length) == 0x1);"));
+        assert!(header.contains("#pragma pack(push, 1)\nstruct _PACKED_HEADER {"));
+    }
+
+    #[test]
+    fn writes_whole_bitfield_units() {
+        let mix = definition(&header(&["_MIX"], false), "struct _MIX");
+        assert_eq!(mix, "struct _MIX {\n    unsigned char a : 1;\n    uint8_t : 7;\n    unsigned char _padding_0x1[0x3];\n\
+                         \x20   uint32_t b : 1;\n    uint32_t : 31;\n    unsigned char c : 1;\n    uint8_t : 7;\n};\n\
+                         C_ASSERT(offsetof(struct _MIX, _padding_0x1) + 0x3 == 0x4);\nC_ASSERT(sizeof(struct _MIX) == 0xc);");
+    }
+
+    #[test]
+    fn writes_enum_values_as_the_underlying_type() {
+        assert!(header(&["_PS_STATE"], false).contains("    PsTerminated = -2147483648,\n"));
+    }
+}
@@ -0,0 +1,329 @@
+use flate2::{Decompress, FlushDecompress, Status};
+use pyo3::exceptions::*;
+use pyo3::{PyErr, PyResult};
+use crate::bytes;
+
+/*
+ *
+ * Minimal cabinet (.pd_ / .dl_ / .ex_) reader used by the symbol server client.
+ * Only single cabinet sets are supported, with either stored or MSZIP folders.
+ *
+ */
+
+const CAB_SIGNATURE: &[u8] = b"MSCF";
+const CFHEADER_SIZE: usize = 36;
+
+const FLAG_PREV_CABINET: u16 = 0x0001;
+const FLAG_NEXT_CABINET: u16 = 0x0002;
+const FLAG_RESERVE_PRESENT: u16 = 0x0004;
+
+const COMPRESSION_NONE: u16 = 0;
+const COMPRESSION_MSZIP: u16 = 1;
+const COMPRESSION_QUANTUM: u16 = 2;
+const COMPRESSION_LZX: u16 = 3;
+
+// Deflate window size, every MSZIP block may refer back this far into the previous ones
+const MSZIP_WINDOW: usize = 32 * 1024;
+
+struct Folder {
+    data_offset: usize,
+    data_blocks: u16,
+    compression: u16,
+}
+
+struct CabFile {
+    name: String,
+    size: usize,
+    folder_offset: usize,
+    folder: u16,
+}
+
+fn cab_error(message: &str) -> PyErr {
+    IOError::py_err(format!("[pymspdb] Invalid cabinet: {}", message))
+}
+
+fn read_u8(data: &[u8], offset: usize) -> PyResult<u8> {
+    data.get(offset).copied().ok_or_else(|| cab_error("unexpected end of file"))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> PyResult<u16> {
+    bytes::read_u16(data, offset).ok_or_else(|| cab_error("unexpected end of file"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> PyResult<u32> {
+    bytes::read_u32(data, offset).ok_or_else(|| cab_error("unexpected end of file"))
+}
+
+/*
+Reads a NUL terminated string.
+
+@data   => Cabinet contents.
+@offset => Offset of the first character.
+
+RETURN
+returns the string and the offset right after its terminator.
+*/
+fn read_cstring(data: &[u8], offset: usize) -> PyResult<(String, usize)> {
+    let rest = data.get(offset..).ok_or_else(|| cab_error("unexpected end of file"))?;
+    let length = rest.iter().position(|&c| c == 0).ok_or_else(|| cab_error("unterminated string"))?;
+
+    Ok((String::from_utf8_lossy(&rest[..length]).into_owned(), offset + length + 1))
+}
+
+/*
+Checks whether a buffer starts with the cabinet signature.
+
+@data => Buffer to check.
+
+RETURN
+returns true if data looks like a cabinet.
+*/
+pub fn is_cabinet(data: &[u8]) -> bool {
+    data.starts_with(CAB_SIGNATURE)
+}
+
+/*
+Inflates a single MSZIP block. Every block is a complete deflate stream, but it can reference
+the output of the blocks before it, so the previous window is fed in first as a stored block.
+
+@history  => Everything decompressed from the folder so far.
+@block    => Block payload (without the "CK" signature).
+@expected => Uncompressed size of the block.
+
+RETURN
+returns the uncompressed block.
+*/
+fn inflate_mszip_block(history: &[u8], block: &[u8], expected: usize) -> PyResult<Vec<u8>> {
+    let window = &history[history.len().saturating_sub(MSZIP_WINDOW)..];
+    let mut input = Vec::with_capacity(5 + window.len() + block.len());
+
+    if !window.is_empty() {
+        // Non final stored block holding the window
+        let window_length = window.len() as u16;
+        input.push(0);
+        input.extend_from_slice(&window_length.to_le_bytes());
+        input.extend_from_slice(&(!window_length).to_le_bytes());
+        input.extend_from_slice(window);
+    }
+    input.extend_from_slice(block);
+
+    let mut output = Vec::with_capacity(window.len() + expected);
+    let mut inflater = Decompress::new(false);
+    match inflater.decompress_vec(&input, &mut output, FlushDecompress::Finish) {
+        Ok(Status::StreamEnd) => {},
+        Ok(_) => return Err(cab_error("truncated MSZIP block")),
+        Err(e) => return Err(cab_error(&e.to_string()))
+    }
+
+    if output.len() != window.len() + expected {
+        return Err(cab_error("MSZIP block has the wrong size"));
+    }
+    Ok(output.split_off(window.len()))
+}
+
+/*
+Decompresses every data block of a folder.
+
+@data          => Cabinet contents.
+@folder        => Folder to decompress.
+@data_reserved => Size of the per-block reserved area (from the header).
+
+RETURN
+returns the uncompressed folder contents.
+*/
+fn extract_folder(data: &[u8], folder: &Folder, data_reserved: usize) -> PyResult<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut offset = folder.data_offset;
+
+    for _ in 0..folder.data_blocks {
+        let compressed_size = read_u16(data, offset + 4)? as usize;
+        let uncompressed_size = read_u16(data, offset + 6)? as usize;
+        let block_start = offset + 8 + data_reserved;
+        let block = data.get(block_start..block_start + compressed_size)
+                        .ok_or_else(|| cab_error("data block out of bounds"))?;
+
+        match folder.compression & 0xf {
+            COMPRESSION_NONE => output.extend_from_slice(block),
+            COMPRESSION_MSZIP => {
+                if !block.starts_with(b"CK") {
+                    return Err(cab_error("missing MSZIP block signature"));
+                }
+                let inflated = inflate_mszip_block(&output, &block[2..], uncompressed_size)?;
+                output.extend_from_slice(&inflated);
+            },
+            COMPRESSION_QUANTUM => return Err(cab_error("Quantum compression is not supported")),
+            COMPRESSION_LZX => return Err(cab_error("LZX compression is not supported")),
+            other => return Err(cab_error(&format!("unknown compression type {}", other)))
+        }
+        offset = block_start + compressed_size;
+    }
+    Ok(output)
+}
+
+struct Header {
+    files_offset: usize,
+    file_count: u16,
+    data_reserved: usize,
+    folders: Vec<Folder>,
+}
+
+/*
+Reads the cabinet header and its folder entries.
+
+@data => Cabinet contents.
+
+RETURN
+returns the header, an error if it isn't a (single part) cabinet.
+*/
+fn read_header(data: &[u8]) -> PyResult<Header> {
+    if !is_cabinet(data) || data.len() < CFHEADER_SIZE {
+        return Err(cab_error("bad signature"));
+    }
+
+    let files_offset = read_u32(data, 16)? as usize;
+    let folder_count = read_u16(data, 26)?;
+    let file_count = read_u16(data, 28)?;
+    let flags = read_u16(data, 30)?;
+
+    // Optional reserved areas and chained cabinet names
+    let mut offset = CFHEADER_SIZE;
+    let mut folder_reserved = 0;
+    let mut data_reserved = 0;
+    if flags & FLAG_RESERVE_PRESENT != 0 {
+        let header_reserved = read_u16(data, offset)? as usize;
+        folder_reserved = read_u8(data, offset + 2)? as usize;
+        data_reserved = read_u8(data, offset + 3)? as usize;
+        offset += 4 + header_reserved;
+    }
+    if flags & (FLAG_PREV_CABINET | FLAG_NEXT_CABINET) != 0 {
+        return Err(cab_error("multi-part cabinets are not supported"));
+    }
+
+    let mut folders = Vec::with_capacity(folder_count as usize);
+    for _ in 0..folder_count {
+        folders.push(Folder {
+            data_offset: read_u32(data, offset)? as usize,
+            data_blocks: read_u16(data, offset + 4)?,
+            compression: read_u16(data, offset + 6)?,
+        });
+        offset += 8 + folder_reserved;
+    }
+
+    Ok(Header { files_offset, file_count, data_reserved, folders })
+}
+
+/*
+Checks whether a buffer is a cabinet this reader can extract: a single part cabinet whose
+folders are stored or MSZIP compressed (not Quantum or LZX).
+
+@data => Buffer to check.
+
+RETURN
+returns true if extract_file can handle it.
+*/
+pub fn is_supported_cabinet(data: &[u8]) -> bool {
+    match read_header(data) {
+        Ok(header) => header.folders.iter().all(|folder| matches!(folder.compression & 0xf, COMPRESSION_NONE | COMPRESSION_MSZIP)),
+        Err(_) => false
+    }
+}
+
+/*
+Extracts a single file from a cabinet.
+
+@data => Cabinet contents.
+@name => Name of the file to extract (case insensitive). If none, the first file is extracted.
+
+RETURN
+returns the stored file name and its contents.
+*/
+pub fn extract_file(data: &[u8], name: Option<&str>) -> PyResult<(String, Vec<u8>)> {
+    let Header { files_offset, file_count, data_reserved, folders } = read_header(data)?;
+
+    let mut offset = files_offset;
+    let mut wanted = None;
+    for _ in 0..file_count {
+        let (file_name, next_offset) = read_cstring(data, offset + 16)?;
+        let file = CabFile {
+            size: read_u32(data, offset)? as usize,
+            folder_offset: read_u32(data, offset + 4)? as usize,
+            folder: read_u16(data, offset + 8)?,
+            name: file_name,
+        };
+        offset = next_offset;
+
+        let is_wanted = match name {
+            Some(name) => file.name.eq_ignore_ascii_case(name),
+            None => true
+        };
+        if is_wanted {
+            wanted = Some(file);
+            break;
+        }
+    }
+
+    let file = match wanted {
+        Some(file) => file,
+        None => {
+            return Err(cab_error(&format!("file {} not found", name.unwrap_or("<any>"))));
+        }
+    };
+
+    let folder = folders.get(file.folder as usize).ok_or_else(|| cab_error("bad folder index"))?;
+    let mut contents = extract_folder(data, folder, data_reserved)?;
+    if contents.len() < file.folder_offset + file.size {
+        return Err(cab_error("file exceeds its folder"));
+    }
+    contents.truncate(file.folder_offset + file.size);
+
+    Ok((file.name, contents.split_off(file.folder_offset)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::cabinet;
+
+    // Compressible and longer than one 32K block
+    fn contents() -> Vec<u8> {
+        (0..100_000u32).map(|value| (value % 251) as u8 ^ (value / 1000) as u8).collect()
+    }
+
+    #[test]
+    fn extracts_stored_files() {
+        let data = cabinet("a.pdb", &contents(), COMPRESSION_NONE);
+        assert!(is_cabinet(&data));
+        assert_eq!(extract_file(&data, None).unwrap(), (String::from("a.pdb"), contents()));
+    }
+
+    #[test]
+    fn extracts_mszip_files() {
+        let data = cabinet("a.pdb", &contents(), COMPRESSION_MSZIP);
+        assert!(data.len() < contents().len());
+        assert_eq!(extract_file(&data, Some("A.PDB")).unwrap().1, contents());
+    }
+
+    #[test]
+    fn rejects_bad_cabinets() {
+        assert!(!is_cabinet(b"<html>Not found</html>"));
+        assert!(extract_file(b"<html>Not found</html>", None).is_err());
+
+        let data = cabinet("a.pdb", b"pdb", COMPRESSION_NONE);
+        assert!(extract_file(&data, Some("b.pdb")).is_err());
+        assert!(extract_file(&data[..data.len() - 1], None).is_err());
+        assert!(!is_supported_cabinet(b"<html>Not found</html>"));
+    }
+
+    #[test]
+    fn detects_unsupported_compression() {
+        assert!(is_supported_cabinet(&cabinet("a.pdb", b"pdb", COMPRESSION_NONE)));
+        assert!(is_supported_cabinet(&cabinet("a.pdb", b"pdb", COMPRESSION_MSZIP)));
+
+        for compression in &[COMPRESSION_QUANTUM, COMPRESSION_LZX] {
+            let data = cabinet("a.pdb", b"pdb", *compression);
+            assert!(is_cabinet(&data));
+            assert!(!is_supported_cabinet(&data));
+            assert!(extract_file(&data, None).is_err());
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use pdb::{PrimitiveKind, TypeIndex};
+use crate::layout::{self, TypeLayouts, TypeRef};
+
+/*
+ *
+ * CODE GENERATION HELPERS
+ *
+ * Shared by the C header, Rust binding and ctypes class generators.
+ *
+ */
+
+pub const INDENT: &str = "    ";
+
+/*
+Member names of a definition. Generated names can repeat (padding at the same offset of two
+anonymous structs, a field named like another one's replacement), repeats get a _<N> suffix.
+*/
+#[derive(Default)]
+pub struct UniqueNames(HashSet<String>);
+
+impl UniqueNames {
+    pub fn unique(&mut self, name: String) -> String {
+        let mut unique = name.clone();
+        let mut count = 1;
+        while !self.0.insert(unique.clone()) {
+            unique = format!("{}_{}", name, count);
+            count += 1;
+        }
+        unique
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+pub fn line(out: &mut String, indent: usize, text: &str) {
+    let _ = writeln!(out, "{}{}", INDENT.repeat(indent), text);
+}
+
+/*
+Name of the definition generated for a struct / union / enum, unnamed ones are named by their
+type index (_unnamed_0x1010).
+
+@identifier => Turns a name into an identifier of the generated language.
+*/
+pub fn type_identifier(layouts: &TypeLayouts, index: TypeIndex, identifier: fn(&str) -> String) -> String {
+    let name = layouts.name(index);
+    match layout::is_unnamed(name) {
+        true => format!("_unnamed_{:#x}", index.0),
+        false => identifier(name)
+    }
+}
+
+/*
+Stand-in for primitives a language has no type of (128 bit integers, complex numbers, ...): an
+array of bytes of the same size.
+*/
+pub fn primitive_bytes(kind: PrimitiveKind) -> TypeRef {
+    TypeRef::Array {
+        element: Box::new(TypeRef::Primitive(PrimitiveKind::UChar)),
+        count: layout::primitive_size(kind) as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_repeated_names() {
+        let mut names = UniqueNames::default();
+        let unique: Vec<String> = ["_padding_0x10", "_padding_0x10", "Flags", "_padding_0x10"].iter().map(|name| names.unique(String::from(*name))).collect();
+        assert_eq!(unique, vec!["_padding_0x10", "_padding_0x10_1", "Flags", "_padding_0x10_2"]);
+    }
+
+    #[test]
+    fn writes_unknown_primitives_as_bytes() {
+        let bytes = TypeRef::Array { element: Box::new(TypeRef::Primitive(PrimitiveKind::UChar)), count: 16 };
+        assert_eq!(primitive_bytes(PrimitiveKind::U128), bytes);
+    }
+}
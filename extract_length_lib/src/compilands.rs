@@ -0,0 +1,159 @@
+use std::collections::BTreeSet;
+use fallible_iterator::FallibleIterator;
+use pyo3::exceptions::*;
+use pyo3::{PyErr, PyResult};
+
+/*
+ *
+ * COMPILANDS
+ *
+ * Every module of the DBI stream is one compiland: an object file handed to the linker, an
+ * object pulled out of a static library, an import stub ("Import:ntdll.dll") or the linker's
+ * own "* Linker *" module. The section contribution substream says which parts of the image
+ * each one produced, and the C13 file checksums of its module stream list its source files.
+ *
+ */
+
+#[derive(Debug, Clone)]
+pub struct Contribution {
+    pub section: u16,
+    pub offset: u32,
+    pub rva: Option<u32>,
+    pub size: u32,
+    // IMAGE_SCN_* flags of the contribution
+    pub characteristics: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Compiland {
+    pub index: usize,
+    // Path of the object file (or "Import:<dll>" / "* Linker *")
+    pub name: String,
+    // Static library the object was pulled out of
+    pub library: Option<String>,
+    pub contributions: Vec<Contribution>,
+    pub source_files: Vec<String>,
+}
+
+fn pdb_error(e: pdb::Error) -> PyErr {
+    Exception::py_err(e.to_string())
+}
+
+/*
+Source files listed in a module's line information.
+*/
+fn source_files(module_info: &pdb::ModuleInfo, string_table: Option<&pdb::StringTable>) -> PyResult<Vec<String>> {
+    let string_table = match string_table {
+        Some(string_table) => string_table,
+        None => return Ok(Vec::new())
+    };
+
+    let program = module_info.line_program().map_err(pdb_error)?;
+    let mut files = BTreeSet::new();
+    let mut iter = program.files();
+    while let Some(file) = iter.next().map_err(pdb_error)? {
+        if let Ok(name) = file.name.to_string_lossy(string_table) {
+            files.insert(name.into_owned());
+        }
+    }
+    Ok(files.into_iter().collect())
+}
+
+/*
+Lists the compilands of a PDB file.
+
+@pdb_name => Path to the PDB file.
+
+RETURN
+returns every module of the DBI stream, in module index order.
+*/
+pub fn collect_compilands(pdb_name: &str) -> PyResult<Vec<Compiland>> {
+    let mut pdb = crate::open_pdb_and_parse(pdb_name)?;
+
+    let address_map = pdb.address_map().map_err(pdb_error)?;
+    let string_table = pdb.string_table().ok();
+    let debug_information = pdb.debug_information().map_err(pdb_error)?;
+
+    let mut compilands = Vec::new();
+    let mut modules = debug_information.modules().map_err(pdb_error)?;
+    while let Some(module) = modules.next().map_err(pdb_error)? {
+        let name = module.module_name().into_owned();
+        let object_file = module.object_file_name().into_owned();
+
+        // Objects passed directly to the linker have their own path as the "object file" name
+        let library = if object_file.is_empty() || object_file == name {
+            None
+        } else {
+            Some(object_file)
+        };
+
+        let source_files = match pdb.module_info(&module).map_err(pdb_error)? {
+            Some(module_info) => source_files(&module_info, string_table.as_ref())?,
+            None => Vec::new()
+        };
+
+        compilands.push(Compiland {
+            index: compilands.len(),
+            name,
+            library,
+            contributions: Vec::new(),
+            source_files,
+        });
+    }
+
+    let mut contributions = debug_information.section_contributions().map_err(pdb_error)?;
+    while let Some(contribution) = contributions.next().map_err(pdb_error)? {
+        if let Some(compiland) = compilands.get_mut(contribution.module as usize) {
+            compiland.contributions.push(Contribution {
+                section: contribution.offset.section,
+                offset: contribution.offset.offset,
+                rva: contribution.offset.to_rva(&address_map).map(|rva| rva.0),
+                size: contribution.size,
+                characteristics: contribution.characteristics,
+            });
+        }
+    }
+
+    Ok(compilands)
+}
+
+/*
+Finds the compiland that produced an address.
+
+@compilands => Compilands (see collect_compilands).
+@rva        => Address relative to the image base.
+
+RETURN
+returns the compiland whose section contribution holds rva.
+*/
+pub fn compiland_at(compilands: &[Compiland], rva: u32) -> Option<&Compiland> {
+    compilands.iter().find(|compiland| {
+        compiland.contributions.iter().any(|contribution| match contribution.rva {
+            Some(start) => rva >= start && rva - start < contribution.size,
+            None => false
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fixture;
+
+    #[test]
+    fn lists_compilands_and_their_code() {
+        let compilands = collect_compilands(&fixture("symbols.pdb")).unwrap();
+        let names: Vec<&str> = compilands.iter().map(|compiland| compiland.name.as_str()).collect();
+        assert!(names[0].ends_with("symbols.o"));
+        assert_eq!(names[1], "* Linker *");
+
+        assert_eq!(compilands[0].index, 0);
+        assert_eq!(compilands[0].library, None);
+        assert!(compilands[0].source_files.iter().all(|file| file.ends_with("symbols.rs")));
+        assert_eq!(compilands[0].source_files.len(), 1);
+
+        assert_eq!(compiland_at(&compilands, 0x1012).map(|compiland| compiland.index), Some(0));
+        assert_eq!(compiland_at(&compilands, 0x2000).map(|compiland| compiland.index), Some(1));
+        assert!(compiland_at(&compilands, 0x1013).is_none());
+    }
+}
@@ -0,0 +1,419 @@
+use pdb::{PrimitiveKind, TypeIndex};
+use crate::c_header::c_identifier;
+use crate::codegen::{self, UniqueNames};
+use crate::layout::{self, Field, Member, TypeLayouts, TypeRef, Udt, UdtKind};
+
+/*
+ *
+ * CTYPES CLASS GENERATION
+ *
+ * Emits python source defining a ctypes.Structure / ctypes.Union subclass per struct / union of
+ * a type closure (every class after the ones it embeds), so from_buffer_copy decodes raw memory
+ * of the PDB's target:
+ *      integers        fixed size types (c_uint32, never c_ulong, whose size depends on the host)
+ *      pointers        c_uint64 / c_uint32 of the target's pointer size, they're addresses of
+ *                      another address space
+ *      enums           their underlying integer, plus an enum.IntEnum of the values
+ *      bitfields       ctypes bitfields, every storage unit filled up to its size with reserved
+ *                      bits and explicitly padded up to its offset, so the host compiler's
+ *                      bitfield rules can't merge or move units
+ *      anonymous       <Parent>_union_0x.. / <Union>_struct<N> classes, listed in _anonymous_ so
+ *      members         their fields read as fields of the parent
+ *
+ * Every class is followed by asserts on its size and field offsets (the storage unit offset for
+ * bitfields).
+ *
+ */
+
+const KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in",
+    "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with", "yield",
+];
+
+fn ctypes_primitive(kind: PrimitiveKind) -> Option<&'static str> {
+    match kind {
+        PrimitiveKind::Char | PrimitiveKind::RChar | PrimitiveKind::I8 => Some("ctypes.c_int8"),
+        PrimitiveKind::UChar | PrimitiveKind::U8 | PrimitiveKind::Bool8 => Some("ctypes.c_uint8"),
+        PrimitiveKind::Short | PrimitiveKind::I16 => Some("ctypes.c_int16"),
+        PrimitiveKind::UShort | PrimitiveKind::U16 | PrimitiveKind::WChar | PrimitiveKind::RChar16 |
+        PrimitiveKind::Bool16 | PrimitiveKind::F16 => Some("ctypes.c_uint16"),
+        PrimitiveKind::Long | PrimitiveKind::I32 | PrimitiveKind::HRESULT => Some("ctypes.c_int32"),
+        PrimitiveKind::ULong | PrimitiveKind::U32 | PrimitiveKind::RChar32 | PrimitiveKind::Bool32 => Some("ctypes.c_uint32"),
+        PrimitiveKind::Quad | PrimitiveKind::I64 => Some("ctypes.c_int64"),
+        PrimitiveKind::UQuad | PrimitiveKind::U64 | PrimitiveKind::Bool64 => Some("ctypes.c_uint64"),
+        PrimitiveKind::F32 | PrimitiveKind::F32PP => Some("ctypes.c_float"),
+        PrimitiveKind::F64 => Some("ctypes.c_double"),
+        _ => None
+    }
+}
+
+fn is_integer(kind: PrimitiveKind) -> bool {
+    ctypes_primitive(kind).is_some_and(|name| name.contains("int"))
+}
+
+/*
+Turns a type / member name into a python identifier.
+*/
+pub fn python_identifier(name: &str) -> String {
+    let identifier = c_identifier(name);
+    match KEYWORDS.contains(&identifier.as_str()) {
+        true => format!("{}_", identifier),
+        false => identifier
+    }
+}
+
+/*
+Name of the class generated for a struct / union / enum.
+*/
+pub fn class_name(layouts: &TypeLayouts, index: TypeIndex) -> String {
+    codegen::type_identifier(layouts, index, python_identifier)
+}
+
+// Bitfield storage unit being filled
+struct Unit {
+    typ: String,
+    offset: u64,
+    bits: u64,
+    // Next bit not declared yet
+    cursor: u64,
+}
+
+#[derive(Default)]
+struct Class {
+    fields: Vec<String>,
+    anonymous: Vec<String>,
+    // Field => offset, for the asserts
+    offsets: Vec<(String, u64)>,
+    names: UniqueNames,
+    unit: Option<Unit>,
+}
+
+impl Class {
+    fn reserve_bits(&mut self, up_to: u64) {
+        let (typ, offset, cursor) = match &self.unit {
+            Some(unit) if up_to > unit.cursor => (unit.typ.clone(), unit.offset, unit.cursor),
+            _ => return
+        };
+
+        let name = self.names.unique(format!("_reserved_{:#x}_{}", offset, cursor));
+        self.fields.push(format!("(\"{}\", {}, {}),", name, typ, up_to - cursor));
+        if let Some(unit) = self.unit.as_mut() {
+            unit.cursor = up_to;
+        }
+    }
+
+    /*
+    Fills the rest of the current bitfield storage unit.
+    */
+    fn close_unit(&mut self) {
+        if let Some(bits) = self.unit.as_ref().map(|unit| unit.bits) {
+            self.reserve_bits(bits);
+        }
+        self.unit = None;
+    }
+}
+
+struct ClassWriter<'l> {
+    layouts: &'l TypeLayouts,
+    out: String,
+}
+
+impl<'l> ClassWriter<'l> {
+    fn line(&mut self, indent: usize, text: &str) {
+        codegen::line(&mut self.out, indent, text);
+    }
+
+    fn type_name(&self, typ: &TypeRef) -> String {
+        match typ {
+            TypeRef::Primitive(kind) => match ctypes_primitive(*kind) {
+                Some(name) => String::from(name),
+                None => self.type_name(&codegen::primitive_bytes(*kind))
+            },
+            TypeRef::Udt(index) => class_name(self.layouts, *index),
+            TypeRef::Enum(index) => match self.layouts.enumeration(*index) {
+                Some(enumeration) => self.type_name(&TypeRef::Primitive(enumeration.underlying)),
+                None => String::from("ctypes.c_int32")
+            },
+            TypeRef::Pointer { size, .. } => match size {
+                2 => String::from("ctypes.c_uint16"),
+                4 => String::from("ctypes.c_uint32"),
+                8 => String::from("ctypes.c_uint64"),
+                size => format!("ctypes.c_uint8 * {}", size)
+            },
+            // Multiplying again makes an array of arrays
+            TypeRef::Array { element, count } => format!("{} * {}", self.type_name(element), count),
+            TypeRef::Modified { inner, .. } => self.type_name(inner),
+            TypeRef::Declared { .. } | TypeRef::Function | TypeRef::Unknown => String::from("ctypes.c_uint8 * 0")
+        }
+    }
+
+    fn pad(&self, class: &mut Class, offset: u64, alignment: u64, cursor: &mut u64, base: u64) {
+        if let Some(size) = layout::padding(*cursor, offset, alignment) {
+            let name = class.names.unique(format!("_padding_{:#x}", *cursor - base));
+            class.fields.push(format!("(\"{}\", ctypes.c_uint8 * {:#x}),", name, size));
+            *cursor = offset;
+        }
+    }
+
+    /*
+    Declares a field. Bitfields go into the storage unit opened by the first one of them.
+    */
+    fn add_field(&self, class: &mut Class, field: &Field, base: u64) {
+        let bitfield = match (field.bitfield, self.layouts.storage_kind(&field.typ)) {
+            (Some(bitfield), Some(kind)) if is_integer(kind) => Some((bitfield, kind)),
+            _ => None
+        };
+
+        let name = class.names.unique(python_identifier(&field.name));
+        match bitfield {
+            Some((bitfield, kind)) => {
+                let typ = self.type_name(&TypeRef::Primitive(kind));
+                if class.unit.is_none() {
+                    // The offset of a ctypes bitfield is the one of its storage unit
+                    class.offsets.push((name.clone(), field.offset - base));
+                    class.unit = Some(Unit {
+                        typ: typ.clone(),
+                        offset: field.offset - base,
+                        bits: layout::primitive_size(kind) * 8,
+                        cursor: 0,
+                    });
+                }
+                class.reserve_bits(u64::from(bitfield.position));
+                class.fields.push(format!("(\"{}\", {}, {}),", name, typ, bitfield.length));
+                if let Some(unit) = class.unit.as_mut() {
+                    unit.cursor = u64::from(bitfield.position) + u64::from(bitfield.length);
+                }
+            },
+            None => {
+                class.fields.push(format!("(\"{}\", {}),", name, self.type_name(&field.typ)));
+                class.offsets.push((name, field.offset - base));
+            }
+        }
+    }
+
+    /*
+    Declares a member of a helper class, written before the class using it.
+    */
+    fn add_anonymous(&mut self, class: &mut Class, name: String, offset: u64, helper: String) {
+        class.fields.push(format!("(\"{}\", {}),", name, helper));
+        class.offsets.push((name.clone(), offset));
+        class.anonymous.push(name);
+    }
+
+    fn struct_class(&mut self, name: &str, members: &[Member], base: u64, size: Option<u64>, packing: Option<u64>) -> Class {
+        let mut class = Class::default();
+        let alignment = self.layouts.members_alignment(members, packing);
+        let mut cursor = base;
+        let mut previous: Option<&Field> = None;
+
+        for member in members {
+            match member {
+                Member::Field(field) => {
+                    if !previous.is_some_and(|previous| layout::is_bitfield_continuation(previous, field)) {
+                        class.close_unit();
+                        let alignment = self.layouts.padding_alignment(field, packing);
+                        self.pad(&mut class, field.offset, alignment, &mut cursor, base);
+                    }
+                    self.add_field(&mut class, field, base);
+                    cursor = cursor.max(self.layouts.field_end(field));
+                    previous = Some(field);
+                },
+                Member::Union(alternatives) => {
+                    class.close_unit();
+                    let start = alternatives.first().map_or(cursor, |alternative| layout::members_start(alternative));
+                    let alignment = self.layouts.alternatives_alignment(alternatives, packing);
+                    self.pad(&mut class, start, alignment, &mut cursor, base);
+
+                    let member_name = class.names.unique(format!("_union_{:#x}", start - base));
+                    let helper = format!("{}{}", name, member_name);
+                    self.write_union(&helper, alternatives, start, None, packing);
+                    self.add_anonymous(&mut class, member_name, start - base, helper);
+
+                    cursor = cursor.max(self.layouts.alternatives_end(alternatives));
+                    previous = None;
+                }
+            }
+        }
+        class.close_unit();
+
+        if let Some(size) = size {
+            let end = cursor - base;
+            if let Some(padding) = layout::padding(end, size, alignment) {
+                let name = class.names.unique(format!("_padding_{:#x}", end));
+                class.fields.push(format!("(\"{}\", ctypes.c_uint8 * {:#x}),", name, padding));
+            }
+        }
+        class
+    }
+
+    fn write_struct(&mut self, name: &str, members: &[Member], base: u64, size: Option<u64>, packing: Option<u64>) {
+        let class = self.struct_class(name, members, base, size, packing);
+        self.write_class(name, "ctypes.Structure", class, size, packing);
+    }
+
+    fn write_union(&mut self, name: &str, alternatives: &[Vec<Member>], base: u64, size: Option<u64>, packing: Option<u64>) {
+        let mut class = Class::default();
+        let alignment = self.layouts.alternatives_alignment(alternatives, packing);
+
+        for (count, alternative) in alternatives.iter().enumerate() {
+            if let [Member::Field(field)] = alternative.as_slice() {
+                self.add_field(&mut class, field, base);
+                class.unit = None;
+                continue;
+            }
+
+            let member_name = class.names.unique(format!("_struct{}", count));
+            let helper = format!("{}{}", name, member_name);
+            self.write_struct(&helper, alternative, base, None, packing);
+            self.add_anonymous(&mut class, member_name, 0, helper);
+        }
+
+        if let Some(size) = size {
+            if layout::padding(self.layouts.alternatives_end(alternatives) - base, size, alignment).is_some() {
+                let name = class.names.unique(String::from("_padding_0x0"));
+                class.fields.push(format!("(\"{}\", ctypes.c_uint8 * {:#x}),", name, size));
+            }
+        }
+        self.write_class(name, "ctypes.Union", class, size, packing);
+    }
+
+    fn write_class(&mut self, name: &str, base_class: &str, class: Class, size: Option<u64>, packing: Option<u64>) {
+        self.line(0, &format!("class {}({}):", name, base_class));
+        if let Some(packing) = packing {
+            self.line(1, &format!("_pack_ = {}", packing));
+            self.line(1, "_layout_ = \"ms\"");
+        }
+        if !class.anonymous.is_empty() {
+            let anonymous: Vec<String> = class.anonymous.iter().map(|name| format!("\"{}\", ", name)).collect();
+            self.line(1, &format!("_anonymous_ = ({})", anonymous.concat().trim_end()));
+        }
+        self.line(1, "_fields_ = [");
+        for field in &class.fields {
+            self.line(2, field);
+        }
+        self.line(1, "]");
+
+        if size.is_some() || !class.offsets.is_empty() {
+            self.line(0, "");
+        }
+        if let Some(size) = size {
+            self.line(0, &format!("assert ctypes.sizeof({}) == {:#x}", name, size));
+        }
+        for (field, offset) in &class.offsets {
+            self.line(0, &format!("assert {}.{}.offset == {:#x}", name, field, offset));
+        }
+        self.line(0, "");
+        self.line(0, "");
+    }
+
+    fn write_udt(&mut self, udt: &Udt) {
+        let name = class_name(self.layouts, udt.index);
+        let fields = self.layouts.all_fields(udt);
+        let packing = self.layouts.packing(udt);
+
+        match udt.kind {
+            UdtKind::Union => {
+                let alternatives = self.layouts.union_alternatives(&fields);
+                self.write_union(&name, &alternatives, 0, Some(udt.size), packing);
+            },
+            _ => {
+                let members = self.layouts.group_members(&fields);
+                self.write_struct(&name, &members, 0, Some(udt.size), packing);
+            }
+        }
+    }
+
+    fn write_enum(&mut self, index: TypeIndex) {
+        let enumeration = match self.layouts.enumeration(index) {
+            Some(enumeration) => enumeration,
+            None => return
+        };
+
+        self.line(0, &format!("class {}(enum.IntEnum):", class_name(self.layouts, index)));
+        if enumeration.values.is_empty() {
+            self.line(1, "pass");
+        }
+        for (name, value) in &enumeration.values {
+            self.line(1, &format!("{} = {}", python_identifier(name), layout::underlying_value(*value, enumeration.underlying)));
+        }
+        self.line(0, "");
+        self.line(0, "");
+    }
+}
+
+/*
+Generates python source defining ctypes classes for a set of types.
+
+@layouts         => Type layouts of the PDB.
+@roots           => Types to generate (and everything they reference).
+@follow_pointers => Also generate the types that are only pointed at.
+@pdb_name        => PDB the types come from (for the header comment).
+
+RETURN
+returns the source of a python module.
+*/
+pub fn generate(layouts: &TypeLayouts, roots: &[TypeIndex], follow_pointers: bool, pdb_name: &str) -> String {
+    let mut writer = ClassWriter {
+        layouts,
+        out: String::new(),
+    };
+
+    writer.line(0, &format!("# Generated by pymspdb from {} ({} bit pointers)", pdb_name, layouts.pointer_size() * 8));
+    writer.line(0, "");
+    writer.line(0, "import ctypes");
+    writer.line(0, "import enum");
+    writer.line(0, "");
+    writer.line(0, "");
+
+    for index in layouts.closure(roots, follow_pointers) {
+        match layouts.udt(index) {
+            Some(udt) => writer.write_udt(udt),
+            None => writer.write_enum(index)
+        }
+    }
+
+    let mut source = writer.out;
+    source.truncate(source.trim_end().len());
+    source.push('\n');
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{kernel_layouts, kernel_types};
+
+    fn source(roots: &[&str]) -> String {
+        let layouts = kernel_layouts();
+        let roots = kernel_types(&layouts, roots);
+        generate(&layouts, &roots, false, "kernel.pdb")
+    }
+
+    #[test]
+    fn pads_bitfield_storage_units_to_their_offset() {
+        let source = source(&["_MIX"]);
+        assert!(source.contains("        (\"_reserved_0x0_1\", ctypes.c_uint8, 7),\n        (\"_padding_0x1\", ctypes.c_uint8 * 0x3),\n        (\"b\", ctypes.c_uint32, 1),\n"));
+        assert!(source.contains("        (\"_reserved_0x8_1\", ctypes.c_uint8, 7),\n    ]\n"));
+        assert!(source.contains("assert _MIX.b.offset == 0x4\nassert _MIX.c.offset == 0x8\n"));
+        assert!(source.contains("assert ctypes.sizeof(_MIX) == 0xc\n"));
+    }
+
+    #[test]
+    fn lists_anonymous_unions() {
+        let source = source(&["_EPROCESS"]);
+        assert!(source.contains("class _EPROCESS(ctypes.Structure):\n    _anonymous_ = (\"_union_0x88\",)\n"));
+        assert!(source.contains("class _EPROCESS_union_0x88(ctypes.Union):\n    _anonymous_ = (\"_struct1\",)\n"));
+        assert!(source.contains("assert _EPROCESS_union_0x88_struct1.Terminated.offset == 0x0\n"));
+        assert!(source.find("class _KPROCESS(").unwrap() < source.find("class _EPROCESS(").unwrap());
+        assert!(source.contains("        (\"Peb\", ctypes.c_uint64),\n"));
+    }
+
+    #[test]
+    fn writes_packed_classes_and_enums() {
+        let source = source(&["_PACKED_HEADER", "_PS_STATE"]);
+        assert!(source.contains("class _PACKED_HEADER(ctypes.Structure):\n    _pack_ = 1\n    _layout_ = \"ms\"\n"));
+        assert!(source.contains("assert _PACKED_HEADER.Length.offset == 0x1\n"));
+        assert!(source.contains("class _PS_STATE(enum.IntEnum):\n    PsActive = 0\n    PsExiting = 1\n    PsTerminated = -2147483648\n"));
+    }
+}
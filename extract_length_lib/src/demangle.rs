@@ -0,0 +1,584 @@
+/*
+ *
+ * MSVC NAME DEMANGLING
+ *
+ * Undecorates Microsoft C++ names (?Foo@Bar@@QEAAXH@Z) into what undname prints
+ * (public: void __cdecl Bar::Foo(int)), minus the __ptr64 noise. Covers functions, data,
+ * vftables, operators, templates and back references, which is what public symbols are made
+ * of. Anything else (RTTI descriptors, local static guards, ...) is left decorated.
+ *
+ */
+
+#[derive(Debug, Clone)]
+pub struct Demangled {
+    // Full declaration, e.g "public: void __cdecl Bar::Foo(int)"
+    pub full: String,
+    // Only the qualified name, e.g "Bar::Foo"
+    pub qualified_name: String,
+}
+
+enum Special {
+    Constructor,
+    Destructor,
+    Conversion,
+    Name(&'static str),
+}
+
+const OPERATORS: &[(&str, &str)] = &[
+    ("2", "operator new"), ("3", "operator delete"), ("4", "operator="), ("5", "operator>>"),
+    ("6", "operator<<"), ("7", "operator!"), ("8", "operator=="), ("9", "operator!="),
+    ("A", "operator[]"), ("C", "operator->"), ("D", "operator*"), ("E", "operator++"),
+    ("F", "operator--"), ("G", "operator-"), ("H", "operator+"), ("I", "operator&"),
+    ("J", "operator->*"), ("K", "operator/"), ("L", "operator%"), ("M", "operator<"),
+    ("N", "operator<="), ("O", "operator>"), ("P", "operator>="), ("Q", "operator,"),
+    ("R", "operator()"), ("S", "operator~"), ("T", "operator^"), ("U", "operator|"),
+    ("V", "operator&&"), ("W", "operator||"), ("X", "operator*="), ("Y", "operator+="),
+    ("Z", "operator-="), ("_0", "operator/="), ("_1", "operator%="), ("_2", "operator>>="),
+    ("_3", "operator<<="), ("_4", "operator&="), ("_5", "operator|="), ("_6", "operator^="),
+    ("_7", "`vftable'"), ("_8", "`vbtable'"), ("_9", "`vcall'"), ("_A", "`typeof'"),
+    ("_B", "`local static guard'"), ("_D", "`vbase destructor'"),
+    ("_E", "`vector deleting destructor'"), ("_F", "`default constructor closure'"),
+    ("_G", "`scalar deleting destructor'"), ("_H", "`vector constructor iterator'"),
+    ("_I", "`vector destructor iterator'"), ("_J", "`vector vbase constructor iterator'"),
+    ("_K", "`virtual displacement map'"), ("_L", "`eh vector constructor iterator'"),
+    ("_M", "`eh vector destructor iterator'"), ("_N", "`eh vector vbase constructor iterator'"),
+    ("_O", "`copy constructor closure'"), ("_S", "`local vftable'"),
+    ("_T", "`local vftable constructor closure'"), ("_U", "operator new[]"),
+    ("_V", "operator delete[]"), ("_X", "`placement delete closure'"),
+    ("_Y", "`placement delete[] closure'"),
+];
+
+const CALLING_CONVENTIONS: &[&str] = &[
+    "__cdecl", "__pascal", "__thiscall", "__stdcall", "__fastcall", "", "__clrcall", "__eabi", "__vectorcall",
+];
+
+// Nesting allowed for types and template arguments, deeper names are rejected instead of
+// overflowing the stack
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+    // Types and template names being parsed
+    depth: usize,
+    // Back references: up to 10 names and 10 argument types
+    names: Vec<String>,
+    types: Vec<String>,
+}
+
+impl<'a> Parser<'a> {
+    /*
+    Runs a parser that may recurse, failing once MAX_DEPTH parsers are nested.
+    */
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> Option<T>) -> Option<T> {
+        if self.depth == MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.position += 1;
+        Some(c)
+    }
+
+    fn consume(&mut self, prefix: &str) -> bool {
+        if self.input[self.position..].starts_with(prefix.as_bytes()) {
+            self.position += prefix.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn remember_name(&mut self, name: &str) {
+        if self.names.len() < 10 && !self.names.iter().any(|known| known == name) {
+            self.names.push(String::from(name));
+        }
+    }
+
+    /*
+    Encoded number: 0-9 stand for 1-10, anything else is hex with A-P digits ended by '@'.
+    */
+    fn number(&mut self) -> Option<i64> {
+        let negative = self.consume("?");
+        let mut c = self.next()?;
+        let value = if c.is_ascii_digit() {
+            i64::from(c - b'0') + 1
+        } else {
+            let mut value: i64 = 0;
+            while c != b'@' {
+                if !(b'A'..=b'P').contains(&c) {
+                    return None;
+                }
+                value = value.checked_mul(16)? + i64::from(c - b'A');
+                c = self.next()?;
+            }
+            value
+        };
+        Some(if negative { -value } else { value })
+    }
+
+    fn simple_name(&mut self) -> Option<String> {
+        let start = self.position;
+        let end = start + self.input[start..].iter().position(|&c| c == b'@')?;
+        self.position = end + 1;
+        String::from_utf8(self.input[start..end].to_vec()).ok()
+    }
+
+    /*
+    Template instance (after "?$"). Its arguments have back references of their own.
+    */
+    fn template_name(&mut self) -> Option<String> {
+        self.nested(Self::scoped_template_name)
+    }
+
+    fn scoped_template_name(&mut self) -> Option<String> {
+        let outer_names = std::mem::take(&mut self.names);
+        let outer_types = std::mem::take(&mut self.types);
+        let name = self.template_name_and_arguments();
+        self.names = outer_names;
+        self.types = outer_types;
+        name
+    }
+
+    fn template_name_and_arguments(&mut self) -> Option<String> {
+        let name = if self.consume("?") {
+            match self.special_name()? {
+                Special::Name(name) => String::from(name),
+                _ => return None
+            }
+        } else {
+            self.simple_name()?
+        };
+        self.remember_name(&name);
+
+        let mut arguments = Vec::new();
+        while !self.consume("@") {
+            arguments.push(self.template_argument()?);
+        }
+
+        let arguments = arguments.join(",");
+        // Keep ">>" apart, like undname does
+        Some(match arguments.ends_with('>') {
+            true => format!("{}<{} >", name, arguments),
+            false => format!("{}<{}>", name, arguments)
+        })
+    }
+
+    fn template_argument(&mut self) -> Option<String> {
+        if self.consume("$0") {
+            return self.number().map(|number| number.to_string());
+        }
+        if self.consume("$1") {
+            let symbol = self.symbol()?;
+            return Some(format!("&{}", symbol.qualified_name));
+        }
+        self.argument_type()
+    }
+
+    /*
+    One component of a qualified name (innermost first in the mangled form).
+    */
+    fn name_component(&mut self) -> Option<String> {
+        match self.peek()? {
+            c @ b'0'..=b'9' => {
+                self.position += 1;
+                self.names.get(usize::from(c - b'0')).cloned()
+            },
+            b'?' => {
+                if self.consume("?$") {
+                    let name = self.template_name()?;
+                    self.remember_name(&name);
+                    Some(name)
+                } else if self.consume("?A") {
+                    self.simple_name()?;
+                    Some(String::from("`anonymous namespace'"))
+                } else {
+                    // Numbered scopes of function local names
+                    None
+                }
+            },
+            _ => {
+                let name = self.simple_name()?;
+                self.remember_name(&name);
+                Some(name)
+            }
+        }
+    }
+
+    /*
+    Scope components up to the terminating '@', outermost first.
+    */
+    fn scopes(&mut self) -> Option<Vec<String>> {
+        let mut scopes = Vec::new();
+        while !self.consume("@") {
+            scopes.push(self.name_component()?);
+        }
+        scopes.reverse();
+        Some(scopes)
+    }
+
+    fn type_name(&mut self) -> Option<String> {
+        let name = self.name_component()?;
+        let mut components = self.scopes()?;
+        components.push(name);
+        Some(components.join("::"))
+    }
+
+    fn special_name(&mut self) -> Option<Special> {
+        if self.consume("0") {
+            return Some(Special::Constructor);
+        }
+        if self.consume("1") {
+            return Some(Special::Destructor);
+        }
+        if self.consume("B") {
+            return Some(Special::Conversion);
+        }
+        for &(code, name) in OPERATORS {
+            if self.consume(code) {
+                return Some(Special::Name(name));
+            }
+        }
+        None
+    }
+
+    fn cv_qualifier(&mut self) -> Option<&'static str> {
+        match self.next()? {
+            b'A' => Some(""),
+            b'B' => Some(" const"),
+            b'C' => Some(" volatile"),
+            b'D' => Some(" const volatile"),
+            _ => None
+        }
+    }
+
+    fn skip_pointer_modifiers(&mut self) {
+        // __ptr64, __restrict, __unaligned
+        while let Some(b'E') | Some(b'I') | Some(b'F') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn calling_convention(&mut self) -> Option<&'static str> {
+        let c = self.next()?;
+        if !(b'A'..=b'Q').contains(&c) {
+            return None;
+        }
+        CALLING_CONVENTIONS.get(usize::from(c - b'A') / 2).copied()
+    }
+
+    fn return_type(&mut self) -> Option<Option<String>> {
+        if self.consume("@") {
+            return Some(None);
+        }
+        self.data_type().map(Some)
+    }
+
+    fn argument_list(&mut self) -> Option<String> {
+        if self.consume("X") {
+            return Some(String::from("void"));
+        }
+
+        let mut arguments = Vec::new();
+        loop {
+            if self.consume("@") {
+                break;
+            }
+            if self.consume("Z") {
+                arguments.push(String::from("..."));
+                break;
+            }
+            arguments.push(self.argument_type()?);
+        }
+        Some(arguments.join(","))
+    }
+
+    fn argument_type(&mut self) -> Option<String> {
+        if let Some(c @ b'0'..=b'9') = self.peek() {
+            self.position += 1;
+            return self.types.get(usize::from(c - b'0')).cloned();
+        }
+
+        let start = self.position;
+        let typ = self.data_type()?;
+        // Only types longer than one character are worth a back reference
+        if self.position - start > 1 && self.types.len() < 10 {
+            self.types.push(typ.clone());
+        }
+        Some(typ)
+    }
+
+    fn pointer(&mut self, pointer_cv: &str, symbol: &str) -> Option<String> {
+        self.skip_pointer_modifiers();
+
+        if self.consume("6") {
+            let calling_convention = self.calling_convention()?;
+            let return_type = self.return_type()?.unwrap_or_default();
+            let arguments = self.argument_list()?;
+            self.consume("Z");
+            return Some(format!("{} ({} {}{})({})", return_type, calling_convention, symbol, pointer_cv, arguments));
+        }
+
+        let cv = self.cv_qualifier()?;
+        let pointee = self.data_type()?;
+        Some(format!("{}{} {}{}", pointee, cv, symbol, pointer_cv))
+    }
+
+    fn data_type(&mut self) -> Option<String> {
+        self.nested(Self::any_type)
+    }
+
+    fn any_type(&mut self) -> Option<String> {
+        let builtin = |name: &str| Some(String::from(name));
+
+        match self.next()? {
+            b'X' => builtin("void"),
+            b'C' => builtin("signed char"),
+            b'D' => builtin("char"),
+            b'E' => builtin("unsigned char"),
+            b'F' => builtin("short"),
+            b'G' => builtin("unsigned short"),
+            b'H' => builtin("int"),
+            b'I' => builtin("unsigned int"),
+            b'J' => builtin("long"),
+            b'K' => builtin("unsigned long"),
+            b'M' => builtin("float"),
+            b'N' => builtin("double"),
+            b'O' => builtin("long double"),
+            b'_' => match self.next()? {
+                b'N' => builtin("bool"),
+                b'J' => builtin("__int64"),
+                b'K' => builtin("unsigned __int64"),
+                b'W' => builtin("wchar_t"),
+                b'S' => builtin("char16_t"),
+                b'U' => builtin("char32_t"),
+                b'Q' => builtin("char8_t"),
+                _ => None
+            },
+            b'T' => self.type_name().map(|name| format!("union {}", name)),
+            b'U' => self.type_name().map(|name| format!("struct {}", name)),
+            b'V' => self.type_name().map(|name| format!("class {}", name)),
+            b'W' => {
+                self.next()?;
+                self.type_name().map(|name| format!("enum {}", name))
+            },
+            b'P' => self.pointer("", "*"),
+            b'Q' => self.pointer(" const", "*"),
+            b'R' => self.pointer(" volatile", "*"),
+            b'S' => self.pointer(" const volatile", "*"),
+            b'A' => self.pointer("", "&"),
+            b'B' => self.pointer(" volatile", "&"),
+            b'?' => {
+                let cv = self.cv_qualifier()?;
+                self.data_type().map(|typ| format!("{}{}", typ, cv))
+            },
+            b'$' => {
+                if self.consume("$Q") {
+                    self.pointer("", "&&")
+                } else if self.consume("$T") {
+                    builtin("std::nullptr_t")
+                } else if self.consume("$C") {
+                    let cv = self.cv_qualifier()?;
+                    self.data_type().map(|typ| format!("{}{}", typ, cv))
+                } else {
+                    None
+                }
+            },
+            _ => None
+        }
+    }
+
+    fn symbol(&mut self) -> Option<Demangled> {
+        if !self.consume("?") {
+            return None;
+        }
+        if self.consume("?_C@_") {
+            let string = String::from("`string'");
+            return Some(Demangled { full: string.clone(), qualified_name: string });
+        }
+
+        let (special, first) = if self.consume("?$") {
+            let name = self.template_name()?;
+            self.remember_name(&name);
+            (None, Some(name))
+        } else if self.consume("?") {
+            (Some(self.special_name()?), None)
+        } else {
+            (None, Some(self.name_component()?))
+        };
+
+        let mut components = self.scopes()?;
+        let class_name = components.last().cloned().unwrap_or_default();
+        // The class name of a template constructor doesn't repeat the arguments
+        let class_name = class_name.split('<').next().unwrap_or_default().to_string();
+        let name = match (&special, first) {
+            (_, Some(name)) => name,
+            (Some(Special::Constructor), None) => class_name,
+            (Some(Special::Destructor), None) => format!("~{}", class_name),
+            (Some(Special::Conversion), None) => String::from("operator"),
+            (Some(Special::Name(name)), None) => String::from(*name),
+            (None, None) => return None
+        };
+        components.push(name);
+
+        match self.peek()? {
+            b'0'..=b'4' => self.data_symbol(components),
+            b'6' | b'7' => self.table_symbol(components),
+            b'A'..=b'Z' => self.function_symbol(components, matches!(special, Some(Special::Conversion))),
+            _ => None
+        }
+    }
+
+    fn data_symbol(&mut self, components: Vec<String>) -> Option<Demangled> {
+        let storage = match self.next()? {
+            b'0' => "private: static ",
+            b'1' => "protected: static ",
+            b'2' => "public: static ",
+            _ => ""
+        };
+        let typ = self.data_type()?;
+        self.skip_pointer_modifiers();
+        let cv = self.cv_qualifier().unwrap_or("");
+
+        let qualified_name = components.join("::");
+        Some(Demangled {
+            full: format!("{}{}{} {}", storage, typ, cv, qualified_name),
+            qualified_name,
+        })
+    }
+
+    fn table_symbol(&mut self, components: Vec<String>) -> Option<Demangled> {
+        self.next()?;
+        let cv = self.cv_qualifier()?;
+
+        let qualified_name = components.join("::");
+        let mut full = format!("{} {}", cv.trim_start(), qualified_name).trim_start().to_string();
+        if !self.consume("@") {
+            full.push_str(&format!("{{for `{}'}}", self.type_name()?));
+        }
+        Some(Demangled { full, qualified_name })
+    }
+
+    fn function_symbol(&mut self, mut components: Vec<String>, conversion: bool) -> Option<Demangled> {
+        let c = self.next()?;
+        let (access, storage, member) = match c {
+            b'Y' | b'Z' => ("", "", false),
+            b'A'..=b'X' => {
+                let index = usize::from(c - b'A');
+                let access = ["private: ", "protected: ", "public: "][index / 8];
+                match (index % 8) / 2 {
+                    0 => (access, "", true),
+                    1 => (access, "static ", false),
+                    2 => (access, "virtual ", true),
+                    _ => {
+                        // Virtual thunk, the this adjustment comes first
+                        self.number()?;
+                        (access, "virtual ", true)
+                    }
+                }
+            },
+            _ => return None
+        };
+
+        let this_cv = if member {
+            self.skip_pointer_modifiers();
+            self.cv_qualifier()?
+        } else {
+            ""
+        };
+
+        let calling_convention = self.calling_convention()?;
+        let return_type = self.return_type()?;
+        let arguments = self.argument_list()?;
+
+        if conversion {
+            if let (Some(last), Some(return_type)) = (components.last_mut(), &return_type) {
+                *last = format!("operator {}", return_type);
+            }
+        }
+        let qualified_name = components.join("::");
+
+        let return_type = match (&return_type, conversion) {
+            (Some(return_type), false) => format!("{} ", return_type),
+            _ => String::new()
+        };
+        Some(Demangled {
+            full: format!("{}{}{}{} {}({}){}", access, storage, return_type, calling_convention, qualified_name, arguments, this_cv),
+            qualified_name,
+        })
+    }
+}
+
+/*
+Undecorates an MSVC C++ name.
+
+@name => Decorated name (e.g ?Foo@Bar@@QEAAXH@Z).
+
+RETURN
+returns the demangled declaration and qualified name, or None for names that aren't decorated
+C++ names (C symbols) or use an encoding this demangler doesn't know.
+*/
+pub fn demangle(name: &str) -> Option<Demangled> {
+    let mut parser = Parser {
+        input: name.as_bytes(),
+        position: 0,
+        depth: 0,
+        names: Vec::new(),
+        types: Vec::new(),
+    };
+    parser.symbol()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full(name: &str) -> Option<String> {
+        demangle(name).map(|demangled| demangled.full)
+    }
+
+    #[test]
+    fn demangles_functions() {
+        let demangled = demangle("?Foo@Bar@@QEAAXH@Z").unwrap();
+        assert_eq!(demangled.full, "public: void __cdecl Bar::Foo(int)");
+        assert_eq!(demangled.qualified_name, "Bar::Foo");
+        assert_eq!(full("??0Bar@@QEAA@XZ").unwrap(), "public: __cdecl Bar::Bar(void)");
+    }
+
+    #[test]
+    fn demangles_data_and_templates() {
+        assert_eq!(full("?Count@@3HA").unwrap(), "int Count");
+        assert_eq!(full("?Head@@3PEAU_LIST_ENTRY@@EA").unwrap(), "struct _LIST_ENTRY * Head");
+        assert_eq!(demangle("?x@?$List@H@@2HA").unwrap().qualified_name, "List<int>::x");
+        assert_eq!(full("??_7Bar@@6B@").unwrap(), "const Bar::`vftable'");
+    }
+
+    #[test]
+    fn leaves_other_names_alone() {
+        assert!(demangle("KeWaitForSingleObject").is_none());
+        assert!(demangle("?Foo@Bar@@QEAAX").is_none());
+        assert!(demangle("?").is_none());
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let pointers = format!("?x@@3{}HEA", "PEA".repeat(100_000));
+        assert!(demangle(&pointers).is_none());
+
+        let templates = format!("?x@{}@2HA", "?$A@V".repeat(100_000));
+        assert!(demangle(&templates).is_none());
+
+        let shallow = format!("?x@@3{}HEA", "PEA".repeat(10));
+        assert!(demangle(&shallow).is_some());
+    }
+}
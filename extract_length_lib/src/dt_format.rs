@@ -0,0 +1,238 @@
+use pdb::{PrimitiveKind, TypeIndex};
+use crate::layout::{self, Bitfield, Field, TypeLayouts, TypeRef};
+
+/*
+ *
+ * DT FORMAT
+ *
+ * Prints types the way WinDbg's dt command does:
+ *
+ *      nt!_EPROCESS
+ *         +0x000 Pcb              : _KPROCESS
+ *         +0x440 UniqueProcessId  : Ptr64 Void
+ *         +0x464 Flags2           : Uint4B
+ *         +0x464 JobNotReallySignaled : Pos 0, 1 Bit
+ *         +0x5a8 ImageFileName    : [15] UChar
+ *
+ * Field names are padded to 16 columns. Embedded structs / unions (and arrays of them) expand
+ * depth levels deep (dt -r), each level indented by 3 more columns with offsets relative to the
+ * embedded type. Field filters are dt wildcards (* and ?, case insensitive) matched against the
+ * top level field names. Enums print their enumerates (   PsActive = 0n0).
+ *
+ */
+
+const INDENT: &str = "   ";
+const NAME_WIDTH: usize = 16;
+
+/*
+dt names of the primitive types.
+*/
+fn primitive_name(kind: PrimitiveKind) -> String {
+    let name = match kind {
+        PrimitiveKind::NoType | PrimitiveKind::Void => "Void",
+        PrimitiveKind::Char | PrimitiveKind::RChar | PrimitiveKind::I8 => "Char",
+        PrimitiveKind::UChar | PrimitiveKind::U8 => "UChar",
+        PrimitiveKind::WChar | PrimitiveKind::RChar16 => "Wchar",
+        PrimitiveKind::Short | PrimitiveKind::I16 => "Int2B",
+        PrimitiveKind::UShort | PrimitiveKind::U16 => "Uint2B",
+        PrimitiveKind::Long | PrimitiveKind::I32 | PrimitiveKind::HRESULT => "Int4B",
+        PrimitiveKind::ULong | PrimitiveKind::U32 | PrimitiveKind::RChar32 => "Uint4B",
+        PrimitiveKind::Quad | PrimitiveKind::I64 => "Int8B",
+        PrimitiveKind::UQuad | PrimitiveKind::U64 => "Uint8B",
+        PrimitiveKind::Octa | PrimitiveKind::I128 => "Int16B",
+        PrimitiveKind::UOcta | PrimitiveKind::U128 => "Uint16B",
+        PrimitiveKind::F32 | PrimitiveKind::F32PP => "Float",
+        PrimitiveKind::F64 => "Double",
+        PrimitiveKind::F80 => "Float10",
+        PrimitiveKind::Bool8 => "Bool",
+        kind => return format!("{:?}", kind)
+    };
+    String::from(name)
+}
+
+/*
+Matches a dt wildcard (* any run, ? any character), case insensitively.
+*/
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Position after the last * and the name position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                },
+                None => return false
+            }
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/*
+Spells a type the way dt does (Ptr64 _PEB, [16] UChar, Uint8B).
+*/
+pub fn type_name(layouts: &TypeLayouts, typ: &TypeRef) -> String {
+    match typ {
+        TypeRef::Primitive(kind) => primitive_name(*kind),
+        TypeRef::Udt(index) | TypeRef::Enum(index) => String::from(layouts.name(*index)),
+        TypeRef::Declared { name, .. } => name.clone(),
+        TypeRef::Pointer { pointee, size } => format!("Ptr{} {}", size * 8, type_name(layouts, pointee)),
+        TypeRef::Array { element, count } => format!("[{}] {}", count, type_name(layouts, element)),
+        TypeRef::Modified { inner, .. } => type_name(layouts, inner),
+        TypeRef::Function | TypeRef::Unknown => String::from("Void")
+    }
+}
+
+/*
+One member line without its indentation (+0x464 JobNotReallySignaled : Pos 0, 1 Bit), bitfields
+described by their position and length instead of their type.
+*/
+pub fn member_line(offset: u64, name: &str, typ: &str, bitfield: Option<Bitfield>) -> String {
+    let description = match bitfield {
+        Some(bitfield) => format!("Pos {}, {} Bit{}", bitfield.position, bitfield.length, if bitfield.length == 1 { "" } else { "s" }),
+        None => String::from(typ)
+    };
+    format!("+0x{:03x} {:<width$} : {}", offset, name, description, width = NAME_WIDTH)
+}
+
+struct DtWriter<'l> {
+    layouts: &'l TypeLayouts,
+    lines: Vec<String>,
+}
+
+impl<'l> DtWriter<'l> {
+    /*
+    Struct / union a field embeds (directly or as array elements), the one -r expands.
+    */
+    fn embedded_udt(typ: &TypeRef) -> Option<TypeIndex> {
+        match typ {
+            TypeRef::Udt(index) => Some(*index),
+            TypeRef::Array { element: inner, .. } | TypeRef::Modified { inner, .. } => Self::embedded_udt(inner),
+            _ => None
+        }
+    }
+
+    fn write_field(&mut self, level: usize, name: &str, field: &Field, depth: usize) {
+        let typ = type_name(self.layouts, &field.typ);
+        self.lines.push(format!("{}{}", INDENT.repeat(level), member_line(field.offset, name, &typ, field.bitfield)));
+
+        if field.bitfield.is_none() && level < depth {
+            if let Some(index) = Self::embedded_udt(&field.typ) {
+                self.write_fields(index, level + 1, depth, &[]);
+            }
+        }
+    }
+
+    fn write_fields(&mut self, index: TypeIndex, level: usize, depth: usize, patterns: &[String]) {
+        let udt = match self.layouts.udt(index) {
+            Some(udt) => udt,
+            None => return
+        };
+
+        let bases = udt.bases.len() + udt.has_vfptr as usize;
+        for (position, field) in self.layouts.all_fields(udt).iter().enumerate() {
+            let name = match position {
+                0 if udt.has_vfptr => "__VFN_table",
+                position if position < bases => "__BaseClass",
+                _ => field.name.as_str()
+            };
+            if !patterns.is_empty() && !patterns.iter().any(|pattern| wildcard_match(pattern, name)) {
+                continue;
+            }
+            self.write_field(level, name, field, depth);
+        }
+    }
+}
+
+/*
+Formats a type like WinDbg's dt.
+
+@layouts  => Type layouts of the PDB.
+@index    => Struct / union / enum to print.
+@module   => Module name the header line shows (nt in nt!_EPROCESS).
+@patterns => Top level field name wildcards (dt nt!_EPROCESS Image*), all fields when empty.
+@depth    => Levels of embedded structs to expand (dt -r<depth>), 0 to expand none.
+
+RETURN
+returns the dt text, a line per field.
+*/
+pub fn format(layouts: &TypeLayouts, index: TypeIndex, module: &str, patterns: &[String], depth: usize) -> String {
+    let mut writer = DtWriter {
+        layouts,
+        lines: vec![format!("{}!{}", module, layouts.name(index))],
+    };
+
+    match layouts.enumeration(index) {
+        Some(enumeration) => {
+            for (name, value) in &enumeration.values {
+                if patterns.is_empty() || patterns.iter().any(|pattern| wildcard_match(pattern, name)) {
+                    writer.lines.push(format!("{}{} = 0n{}", INDENT, name, layout::underlying_value(*value, enumeration.underlying)));
+                }
+            }
+        },
+        None => writer.write_fields(index, 1, depth + 1, patterns)
+    }
+
+    writer.lines.push(String::new());
+    writer.lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{kernel_layouts, kernel_types};
+
+    fn dt(name: &str, patterns: &[&str], depth: usize) -> String {
+        let layouts = kernel_layouts();
+        let patterns: Vec<String> = patterns.iter().map(|pattern| String::from(*pattern)).collect();
+        format(&layouts, kernel_types(&layouts, &[name])[0], "nt", &patterns, depth)
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match("Image*", "ImageFileName"));
+        assert!(wildcard_match("*flags*", "CrossThreadFlags"));
+        assert!(wildcard_match("P?b", "Peb"));
+        assert!(!wildcard_match("P?b", "Pcb2"));
+        assert!(!wildcard_match("Image", "ImageFileName"));
+    }
+
+    #[test]
+    fn prints_fields_like_dt() {
+        let text = dt("_EPROCESS", &[], 0);
+        assert!(text.starts_with("nt!_EPROCESS\n   +0x000 Pcb              : _KPROCESS\n   +0x030 UniqueProcessId  : Ptr64 Void\n"));
+        assert!(text.contains("\n   +0x038 ActiveProcessLinks : _LIST_ENTRY\n"));
+        assert!(text.contains("\n   +0x078 Matrix           : [2] [3] Uint2B\n"));
+        assert!(text.contains("\n   +0x088 Terminated       : Pos 0, 1 Bit\n   +0x088 Spare            : Pos 1, 31 Bits\n"));
+        assert!(text.ends_with("   +0x090 ExitTime         : Int8B\n"));
+    }
+
+    #[test]
+    fn filters_and_expands_fields() {
+        let text = dt("_EPROCESS", &["Pcb", "*flags*"], 1);
+        assert_eq!(text.lines().count(), 12);
+        assert!(text.contains("   +0x000 Pcb              : _KPROCESS\n      +0x000 ProfileListHead  : _LIST_ENTRY\n"));
+        assert!(text.contains("\n      +0x018 ReservedFlags    : Pos 2, 30 Bits\n"));
+        assert!(text.ends_with("   +0x084 Flags2           : Uint4B\n   +0x088 CrossThreadFlags : Uint4B\n"));
+    }
+
+    #[test]
+    fn prints_enumerates() {
+        assert_eq!(dt("_PS_STATE", &["*Term*"], 0), "nt!_PS_STATE\n   PsTerminated = 0n-2147483648\n");
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+use crate::dt_format;
+use crate::layout::{self, Field, TypeLayouts, TypeRef, Udt};
+
+/*
+ *
+ * FIELD SEARCH
+ *
+ * Finds members across every struct / union of the TPI stream, by name ("ActiveProcessLinks",
+ * "*Flags") and / or by type: fields that embed a type (by value, or as array elements) or point
+ * to it (_LIST_ENTRY, _ETHREAD). Patterns are dt wildcards.
+ *
+ * Unnamed structs / unions aren't results of their own, their fields are found through the
+ * named type embedding them (_EPROCESS.u.High).
+ *
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    Embeds,
+    Points,
+}
+
+impl Relation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Relation::Embeds => "embeds",
+            Relation::Points => "points",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldMatch {
+    pub type_name: String,
+    // Named by its path, offset from the start of the type
+    pub field: Field,
+    // How the field relates to the searched type, None when only searching by name
+    pub relation: Option<Relation>,
+}
+
+pub struct FieldQuery<'q> {
+    pub name: Option<&'q str>,
+    pub field_type: Option<&'q str>,
+    pub embeds: bool,
+    pub points: bool,
+}
+
+struct FieldSearch<'l, 'q> {
+    layouts: &'l TypeLayouts,
+    query: &'q FieldQuery<'q>,
+    matches: Vec<FieldMatch>,
+}
+
+impl<'l, 'q> FieldSearch<'l, 'q> {
+    fn relation(&self, field: &Field) -> Option<Option<Relation>> {
+        let pattern = match self.query.field_type {
+            Some(pattern) => pattern,
+            None => return Some(None)
+        };
+        let (name, pointed) = self.layouts.referenced_type(&field.typ)?;
+        if !dt_format::wildcard_match(pattern, &name) {
+            return None;
+        }
+        match (pointed, self.query.embeds, self.query.points) {
+            (false, true, _) => Some(Some(Relation::Embeds)),
+            (true, _, true) => Some(Some(Relation::Points)),
+            _ => None
+        }
+    }
+
+    fn search_udt(&mut self, type_name: &str, udt: &Udt, path: &str, start: u64) {
+        for field in self.layouts.all_fields(udt) {
+            let name_matches = self.query.name.is_none_or(|pattern| dt_format::wildcard_match(pattern, &field.name));
+            let field = Field {
+                name: format!("{}.{}", path, field.name),
+                offset: start + field.offset,
+                ..field
+            };
+
+            if name_matches {
+                if let Some(relation) = self.relation(&field) {
+                    self.matches.push(FieldMatch { type_name: String::from(type_name), field: field.clone(), relation });
+                }
+            }
+
+            if let TypeRef::Udt(index) = &field.typ {
+                match self.layouts.udt(*index) {
+                    Some(inner) if layout::is_unnamed(&inner.name) => self.search_udt(type_name, inner, &field.name, field.offset),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/*
+Searches the fields of every named struct / union.
+
+@layouts => Type layouts of the PDB.
+@query   => Field name pattern, field type pattern (with embeds / points, the relations to accept).
+
+RETURN
+returns the matching fields, in TPI order of their types.
+*/
+pub fn search(layouts: &TypeLayouts, query: &FieldQuery) -> Vec<FieldMatch> {
+    let mut search = FieldSearch {
+        layouts,
+        query,
+        matches: Vec::new(),
+    };
+
+    let mut seen = HashSet::new();
+    for index in layouts.definitions() {
+        let udt = match layouts.udt(index) {
+            Some(udt) => udt,
+            None => continue
+        };
+        if layout::is_unnamed(&udt.name) || !seen.insert(udt.name.as_str()) {
+            continue;
+        }
+        search.search_udt(&udt.name, udt, &udt.name, 0);
+    }
+    search.matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{hit, kernel_layouts};
+
+    fn found(name: Option<&str>, field_type: Option<&str>, embeds: bool, points: bool) -> Vec<(String, u64, Option<Relation>)> {
+        let layouts = kernel_layouts();
+        let query = FieldQuery { name, field_type, embeds, points };
+        search(&layouts, &query).into_iter().map(|found| (found.field.name, found.field.offset, found.relation)).collect()
+    }
+
+    #[test]
+    fn finds_fields_by_name() {
+        assert_eq!(found(Some("*flags*"), None, true, true), vec![hit("_KPROCESS.ReservedFlags", 0x18, None), hit("_KPROCESS.ProcessFlags", 0x18, None),
+                                                                  hit("_EPROCESS.Flags2", 0x84, None), hit("_EPROCESS.CrossThreadFlags", 0x88, None)]);
+        // Through the unnamed union, which isn't a result of its own
+        assert_eq!(found(Some("H*"), None, true, true), vec![hit("_EPROCESS.u.High", 0x66, None)]);
+    }
+
+    #[test]
+    fn finds_fields_by_type() {
+        let list_entries = found(None, Some("_LIST_ENTRY"), true, true);
+        assert_eq!(list_entries.len(), 6);
+        assert_eq!(list_entries[0], hit("_LIST_ENTRY.Flink", 0, Some(Relation::Points)));
+        assert!(list_entries.contains(&hit("_EPROCESS.ActiveProcessLinks", 0x38, Some(Relation::Embeds))));
+
+        assert_eq!(found(None, Some("_LIST_ENTRY"), false, true).len(), 2);
+        assert_eq!(found(None, Some("_E*"), true, false), vec![hit("_EPROCESS.Token", 0x48, Some(Relation::Embeds))]);
+        assert_eq!(found(Some("Active*"), Some("_E*"), false, true), vec![hit("_EPROCESS.ActiveThread", 0x70, Some(Relation::Points))]);
+    }
+}
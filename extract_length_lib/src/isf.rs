@@ -0,0 +1,337 @@
+use std::collections::BTreeMap;
+use pdb::{PrimitiveKind, TypeIndex};
+use serde_json::{json, Map, Value};
+use crate::layout::{self, TypeLayouts, TypeRef, UdtKind};
+use crate::symbols::{GlobalSymbol, SymbolKind};
+
+/*
+ *
+ * VOLATILITY 3 ISF
+ *
+ * Writes a PDB as a Volatility 3 Intermediate Symbol Format table:
+ *      metadata        format version, producer, and the PDB's GUID / age / file / machine,
+ *                      which Volatility matches against the kernel's debug directory
+ *      base_types      the primitive types used (and "pointer", sized for the target)
+ *      user_types      every struct / union / class, fields keyed by name with their offset
+ *      enums           underlying base type and constants
+ *      symbols         addresses (RVAs) of the global / public symbols
+ *
+ * Unnamed types are called __unnamed_<type index>. Types only ever forward declared get an empty
+ * user type, so pointers to them still resolve.
+ *
+ */
+
+const FORMAT_VERSION: &str = "6.2.0";
+
+/*
+Volatility name, kind and signedness of a primitive type.
+*/
+fn base_type(kind: PrimitiveKind) -> Option<(&'static str, &'static str, bool)> {
+    match kind {
+        PrimitiveKind::NoType | PrimitiveKind::Void => Some(("void", "void", false)),
+        PrimitiveKind::Char | PrimitiveKind::RChar => Some(("char", "char", true)),
+        PrimitiveKind::UChar => Some(("unsigned char", "char", false)),
+        PrimitiveKind::I8 => Some(("int8", "int", true)),
+        PrimitiveKind::U8 => Some(("uint8", "int", false)),
+        PrimitiveKind::WChar | PrimitiveKind::RChar16 => Some(("wchar", "int", false)),
+        PrimitiveKind::RChar32 => Some(("dchar", "int", false)),
+        PrimitiveKind::Short | PrimitiveKind::I16 => Some(("short", "int", true)),
+        PrimitiveKind::UShort | PrimitiveKind::U16 => Some(("unsigned short", "int", false)),
+        PrimitiveKind::Long => Some(("long", "int", true)),
+        PrimitiveKind::ULong => Some(("unsigned long", "int", false)),
+        PrimitiveKind::I32 => Some(("int", "int", true)),
+        PrimitiveKind::U32 => Some(("unsigned int", "int", false)),
+        PrimitiveKind::HRESULT => Some(("HRESULT", "int", true)),
+        PrimitiveKind::Quad | PrimitiveKind::I64 => Some(("long long", "int", true)),
+        PrimitiveKind::UQuad | PrimitiveKind::U64 => Some(("unsigned long long", "int", false)),
+        PrimitiveKind::Octa | PrimitiveKind::I128 => Some(("int128", "int", true)),
+        PrimitiveKind::UOcta | PrimitiveKind::U128 => Some(("unsigned int128", "int", false)),
+        PrimitiveKind::F16 => Some(("half", "float", true)),
+        PrimitiveKind::F32 | PrimitiveKind::F32PP => Some(("float", "float", true)),
+        PrimitiveKind::F64 => Some(("double", "float", true)),
+        PrimitiveKind::F80 => Some(("long double", "float", true)),
+        PrimitiveKind::F128 => Some(("float128", "float", true)),
+        PrimitiveKind::Bool8 => Some(("bool", "bool", false)),
+        PrimitiveKind::Bool16 => Some(("bool16", "bool", false)),
+        PrimitiveKind::Bool32 => Some(("bool32", "bool", false)),
+        PrimitiveKind::Bool64 => Some(("bool64", "bool", false)),
+        _ => None
+    }
+}
+
+fn machine_value(machine: pdb::MachineType) -> u16 {
+    match machine {
+        pdb::MachineType::X86 => 0x14c,
+        pdb::MachineType::Amd64 => 0x8664,
+        pdb::MachineType::Arm => 0x1c0,
+        pdb::MachineType::ArmNT => 0x1c4,
+        pdb::MachineType::Thumb => 0x1c2,
+        pdb::MachineType::Arm64 => 0xaa64,
+        pdb::MachineType::Ia64 => 0x200,
+        _ => 0
+    }
+}
+
+fn isf_kind(kind: UdtKind) -> &'static str {
+    match kind {
+        UdtKind::Struct => "struct",
+        UdtKind::Union => "union",
+        UdtKind::Class | UdtKind::Interface => "class",
+    }
+}
+
+struct IsfWriter<'l> {
+    layouts: &'l TypeLayouts,
+    base_types: BTreeMap<String, Value>,
+    // Forward declared only types referenced => kind
+    declared: BTreeMap<String, &'static str>,
+}
+
+impl<'l> IsfWriter<'l> {
+    fn type_name(&self, index: TypeIndex) -> String {
+        let name = self.layouts.name(index);
+        match layout::is_unnamed(name) {
+            true => format!("__unnamed_{:x}", index.0),
+            false => String::from(name)
+        }
+    }
+
+    fn add_base_type(&mut self, name: &str, kind: &str, signed: bool, size: u64) {
+        self.base_types.entry(String::from(name)).or_insert_with(|| json!({
+            "kind": kind,
+            "size": size,
+            "signed": signed,
+            "endian": "little",
+        }));
+    }
+
+    fn base_ref(&mut self, kind: PrimitiveKind) -> Value {
+        match base_type(kind) {
+            Some((name, base_kind, signed)) => {
+                self.add_base_type(name, base_kind, signed, layout::primitive_size(kind));
+                json!({"kind": "base", "name": name})
+            },
+            // Complex numbers and the like, as bytes
+            None => {
+                self.add_base_type("unsigned char", "char", false, 1);
+                json!({
+                    "kind": "array",
+                    "count": layout::primitive_size(kind),
+                    "subtype": {"kind": "base", "name": "unsigned char"},
+                })
+            }
+        }
+    }
+
+    fn type_ref(&mut self, typ: &TypeRef) -> Value {
+        match typ {
+            TypeRef::Primitive(kind) => self.base_ref(*kind),
+            TypeRef::Udt(index) => json!({
+                "kind": self.layouts.udt(*index).map_or("struct", |udt| isf_kind(udt.kind)),
+                "name": self.type_name(*index),
+            }),
+            TypeRef::Enum(index) => json!({
+                "kind": "enum",
+                "name": self.type_name(*index),
+            }),
+            TypeRef::Declared { kind, name } => {
+                self.declared.entry(name.clone()).or_insert_with(|| isf_kind(*kind));
+                json!({"kind": isf_kind(*kind), "name": name})
+            },
+            // Volatility sizes every pointer like the "pointer" base type
+            TypeRef::Pointer { pointee, size } if u64::from(*size) == u64::from(self.layouts.pointer_size()) => json!({
+                "kind": "pointer",
+                "subtype": self.type_ref(pointee),
+            }),
+            TypeRef::Pointer { size, .. } => self.base_ref(match size {
+                2 => PrimitiveKind::UShort,
+                4 => PrimitiveKind::ULong,
+                _ => PrimitiveKind::UQuad
+            }),
+            TypeRef::Array { element, count } => json!({
+                "kind": "array",
+                "count": count,
+                "subtype": self.type_ref(element),
+            }),
+            TypeRef::Modified { inner, .. } => self.type_ref(inner),
+            TypeRef::Function => json!({"kind": "function"}),
+            TypeRef::Unknown => self.base_ref(PrimitiveKind::Void)
+        }
+    }
+
+    fn user_types(&mut self) -> Map<String, Value> {
+        let mut user_types = Map::new();
+        for index in self.layouts.definitions() {
+            let udt = match self.layouts.udt(index) {
+                Some(udt) => udt,
+                None => continue
+            };
+            let name = self.type_name(index);
+            if user_types.contains_key(&name) {
+                continue;
+            }
+
+            let mut fields = Map::new();
+            for field in self.layouts.all_fields(udt) {
+                if fields.contains_key(&field.name) {
+                    continue;
+                }
+
+                let typ = match field.bitfield {
+                    Some(bitfield) => json!({
+                        "kind": "bitfield",
+                        "bit_position": bitfield.position,
+                        "bit_length": bitfield.length,
+                        "type": self.type_ref(&field.typ),
+                    }),
+                    None => self.type_ref(&field.typ)
+                };
+                fields.insert(field.name, json!({"offset": field.offset, "type": typ}));
+            }
+
+            user_types.insert(name, json!({
+                "kind": isf_kind(udt.kind),
+                "size": udt.size,
+                "fields": fields,
+            }));
+        }
+
+        for (name, kind) in std::mem::take(&mut self.declared) {
+            if !user_types.contains_key(&name) {
+                user_types.insert(name, json!({"kind": kind, "size": 0, "fields": {}}));
+            }
+        }
+        user_types
+    }
+
+    fn enums(&mut self) -> Map<String, Value> {
+        let mut enums = Map::new();
+        for index in self.layouts.definitions() {
+            let enumeration = match self.layouts.enumeration(index) {
+                Some(enumeration) => enumeration,
+                None => continue
+            };
+
+            let base = match self.base_ref(enumeration.underlying) {
+                Value::Object(mut base) => base.remove("name").unwrap_or(Value::Null),
+                _ => Value::Null
+            };
+            let constants: Map<String, Value> = enumeration.values.iter()
+                                                          .map(|(name, value)| (name.clone(), json!(layout::underlying_value(*value, enumeration.underlying) as i64)))
+                                                          .collect();
+            enums.entry(self.type_name(index)).or_insert_with(|| json!({
+                "size": layout::primitive_size(enumeration.underlying),
+                "base": base,
+                "constants": constants,
+            }));
+        }
+        enums
+    }
+}
+
+/*
+Generates the Volatility 3 ISF of a PDB.
+
+@layouts  => Type layouts of the PDB.
+@symbols  => Global / public symbols of the PDB.
+@pdb_name => File name of the PDB (e.g ntkrnlmp.pdb).
+
+RETURN
+returns the ISF JSON text.
+*/
+pub fn generate(layouts: &TypeLayouts, symbols: &[GlobalSymbol], pdb_name: &str) -> String {
+    let mut writer = IsfWriter {
+        layouts,
+        base_types: BTreeMap::new(),
+        declared: BTreeMap::new(),
+    };
+
+    let user_types = writer.user_types();
+    let enums = writer.enums();
+
+    writer.add_base_type("pointer", "int", false, u64::from(layouts.pointer_size()));
+    writer.add_base_type("void", "void", false, 0);
+
+    let mut isf_symbols = Map::new();
+    for symbol in symbols.iter().filter(|symbol| symbol.kind != SymbolKind::Constant) {
+        if let Some(rva) = symbol.rva {
+            if !isf_symbols.contains_key(&symbol.name) {
+                isf_symbols.insert(symbol.name.clone(), json!({"address": rva}));
+            }
+        }
+    }
+
+    let isf = json!({
+        "metadata": {
+            "format": FORMAT_VERSION,
+            "producer": {
+                "name": "pymspdb",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "windows": {
+                "pdb": {
+                    "GUID": layouts.guid().replace('-', ""),
+                    "age": layouts.age(),
+                    "database": pdb_name,
+                    "machine_type": machine_value(layouts.machine()),
+                },
+            },
+        },
+        "base_types": writer.base_types.into_iter().collect::<Map<String, Value>>(),
+        "user_types": user_types,
+        "enums": enums,
+        "symbols": isf_symbols,
+    });
+    serde_json::to_string_pretty(&isf).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::collect_symbols;
+    use crate::test_support::{fixture, kernel_layouts};
+
+    fn kernel_isf(symbols: &[GlobalSymbol]) -> Value {
+        let layouts = kernel_layouts();
+        serde_json::from_str(&generate(&layouts, symbols, "kernel.pdb")).unwrap()
+    }
+
+    #[test]
+    fn describes_the_pdb() {
+        let isf = kernel_isf(&[]);
+        let pdb = &isf["metadata"]["windows"]["pdb"];
+        assert_eq!(isf["metadata"]["format"], FORMAT_VERSION);
+        assert_eq!(pdb["database"], "kernel.pdb");
+        assert_eq!(pdb["machine_type"], 0x8664);
+        assert!(!pdb["GUID"].as_str().unwrap().contains('-'));
+        assert_eq!(isf["base_types"]["pointer"], json!({"kind": "int", "size": 8, "signed": false, "endian": "little"}));
+        assert_eq!(isf["symbols"], json!({}));
+    }
+
+    #[test]
+    fn writes_user_types_and_enums() {
+        let isf = kernel_isf(&[]);
+        let eprocess = &isf["user_types"]["_EPROCESS"];
+        assert_eq!(eprocess["size"], 0x98);
+        assert_eq!(eprocess["fields"]["ActiveThread"], json!({"offset": 0x70, "type": {"kind": "pointer", "subtype": {"kind": "struct", "name": "_ETHREAD"}}}));
+        assert_eq!(eprocess["fields"]["Spare"]["type"], json!({"kind": "bitfield", "bit_position": 1, "bit_length": 31, "type": {"kind": "base", "name": "unsigned long"}}));
+        // Members of anonymous unions are fields of the parent
+        assert_eq!(eprocess["fields"]["CrossThreadFlags"]["offset"], 0x88);
+        assert_eq!(eprocess["fields"]["u"]["type"], json!({"kind": "union", "name": "__unnamed_1015"}));
+        assert_eq!(isf["user_types"]["__unnamed_1015"]["kind"], "union");
+
+        // Only forward declared, still resolvable
+        assert_eq!(isf["user_types"]["_PEB"], json!({"kind": "struct", "size": 0, "fields": {}}));
+
+        assert_eq!(isf["enums"]["_PS_STATE"]["base"], "int");
+        assert_eq!(isf["enums"]["_PS_STATE"]["constants"]["PsTerminated"], -2147483648i64);
+    }
+
+    #[test]
+    fn lists_symbol_addresses() {
+        let symbols = collect_symbols(&fixture("symbols.pdb")).unwrap();
+        let isf = kernel_isf(&symbols);
+        assert_eq!(isf["symbols"]["KeWaitForSingleObject"], json!({"address": 0x1000}));
+        assert_eq!(isf["symbols"]["PsActiveProcessHead"], json!({"address": 0x3008}));
+    }
+}
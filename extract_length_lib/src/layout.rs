@@ -0,0 +1,1261 @@
+use std::collections::{HashMap, HashSet};
+use fallible_iterator::FallibleIterator;
+use pdb::{PrimitiveKind, TypeFinder, TypeIndex};
+use pyo3::exceptions::*;
+use pyo3::{PyErr, PyResult};
+
+/*
+ *
+ * TYPE LAYOUTS
+ *
+ * An owned model of the structs, unions and enums of the TPI stream, with forward references
+ * resolved to their definitions. The generators (headers, bindings, exports) and the layout
+ * queries work on it instead of walking pdb::TypeData again.
+ *
+ * MSVC flattens anonymous unions and structs into the field list of their parent, so a union
+ * only shows up as fields going back to an earlier offset. group_members rebuilds them.
+ *
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdtKind {
+    Struct,
+    Class,
+    Interface,
+    Union,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeRef {
+    Primitive(PrimitiveKind),
+    // Struct / class / union definition
+    Udt(TypeIndex),
+    Enum(TypeIndex),
+    // Struct / union the PDB only has a forward declaration of
+    Declared { kind: UdtKind, name: String },
+    Pointer { pointee: Box<TypeRef>, size: u32 },
+    Array { element: Box<TypeRef>, count: u32 },
+    Modified { inner: Box<TypeRef>, constant: bool, volatile: bool },
+    // Procedure / member function type (only ever pointed at)
+    Function,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bitfield {
+    pub position: u8,
+    pub length: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub offset: u64,
+    // Underlying type for bitfields
+    pub typ: TypeRef,
+    pub bitfield: Option<Bitfield>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BaseClass {
+    pub typ: TypeRef,
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Udt {
+    pub index: TypeIndex,
+    pub kind: UdtKind,
+    pub name: String,
+    pub size: u64,
+    pub fields: Vec<Field>,
+    pub bases: Vec<BaseClass>,
+    // Starts with a vftable pointer
+    pub has_vfptr: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Enumeration {
+    pub name: String,
+    pub underlying: PrimitiveKind,
+    pub values: Vec<(String, i128)>,
+}
+
+#[derive(Debug)]
+pub enum Member<'a> {
+    Field(&'a Field),
+    // Anonymous union, every alternative is a single field or an anonymous struct
+    Union(Vec<Vec<Member<'a>>>),
+}
+
+/*
+Everything a set of types references.
+*/
+#[derive(Debug, Default)]
+pub struct Closure {
+    // Struct / union / enum definitions, each one after the types it embeds
+    pub definitions: Vec<TypeIndex>,
+    // Types pointed at (definitions and forward declarations), in the order they're met
+    pub pointed: Vec<TypeRef>,
+}
+
+/*
+A stretch of a field path inside one block of memory.
+*/
+#[derive(Debug, Clone)]
+pub struct PathSegment {
+    // Type the field's offset is relative to
+    pub base: String,
+    pub field: Field,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PathStep<'p> {
+    Field(&'p str),
+    Deref(&'p str),
+    Index(u64),
+}
+
+fn pdb_error(e: pdb::Error) -> PyErr {
+    Exception::py_err(e.to_string())
+}
+
+pub fn primitive_size(kind: PrimitiveKind) -> u64 {
+    match kind {
+        PrimitiveKind::Char | PrimitiveKind::UChar | PrimitiveKind::RChar |
+        PrimitiveKind::I8 | PrimitiveKind::U8 | PrimitiveKind::Bool8 => 1,
+        PrimitiveKind::WChar | PrimitiveKind::RChar16 | PrimitiveKind::Short | PrimitiveKind::UShort |
+        PrimitiveKind::I16 | PrimitiveKind::U16 | PrimitiveKind::F16 | PrimitiveKind::Bool16 => 2,
+        PrimitiveKind::RChar32 | PrimitiveKind::Long | PrimitiveKind::ULong | PrimitiveKind::I32 |
+        PrimitiveKind::U32 | PrimitiveKind::F32 | PrimitiveKind::F32PP | PrimitiveKind::Bool32 |
+        PrimitiveKind::HRESULT => 4,
+        PrimitiveKind::F48 => 6,
+        PrimitiveKind::Quad | PrimitiveKind::UQuad | PrimitiveKind::I64 | PrimitiveKind::U64 |
+        PrimitiveKind::F64 | PrimitiveKind::Bool64 | PrimitiveKind::Complex32 => 8,
+        PrimitiveKind::F80 => 10,
+        PrimitiveKind::Octa | PrimitiveKind::UOcta | PrimitiveKind::I128 | PrimitiveKind::U128 |
+        PrimitiveKind::F128 | PrimitiveKind::Complex64 => 16,
+        PrimitiveKind::Complex80 => 20,
+        PrimitiveKind::Complex128 => 32,
+        _ => 0
+    }
+}
+
+/*
+Reads an enumerate value as a value of the enum's underlying integer (0x80000000 is negative for
+a LONG enum).
+*/
+pub fn underlying_value(value: i128, kind: PrimitiveKind) -> i128 {
+    let bits = primitive_size(kind) * 8;
+    if bits == 0 || bits >= 128 {
+        return value;
+    }
+
+    let signed = matches!(kind, PrimitiveKind::Char | PrimitiveKind::RChar | PrimitiveKind::I8 | PrimitiveKind::Short |
+                                PrimitiveKind::I16 | PrimitiveKind::Long | PrimitiveKind::I32 | PrimitiveKind::HRESULT |
+                                PrimitiveKind::Quad | PrimitiveKind::I64);
+    let value = value & ((1i128 << bits) - 1);
+    match signed && value >> (bits - 1) != 0 {
+        true => value - (1i128 << bits),
+        false => value
+    }
+}
+
+pub fn strip_modifiers(typ: &TypeRef) -> &TypeRef {
+    match typ {
+        TypeRef::Modified { inner, .. } => strip_modifiers(inner),
+        typ => typ
+    }
+}
+
+/*
+Splits a field path (Type.Field[3]->Field) into its type name and steps.
+*/
+fn parse_path(path: &str) -> PyResult<(&str, Vec<PathStep<'_>>)> {
+    let invalid = || KeyError::py_err(format!("[pymspdb] {} is not a valid field path", path));
+    let name_end = |text: &str| text.find(['.', '[', '-']).unwrap_or(text.len());
+
+    let type_end = name_end(path);
+    let (type_name, mut rest) = path.split_at(type_end);
+    let mut steps = Vec::new();
+    while !rest.is_empty() {
+        let (step, remaining) = if let Some(after) = rest.strip_prefix("->") {
+            let (name, remaining) = after.split_at(name_end(after));
+            (PathStep::Deref(name), remaining)
+        } else if let Some(after) = rest.strip_prefix('.') {
+            let (name, remaining) = after.split_at(name_end(after));
+            (PathStep::Field(name), remaining)
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let close = after.find(']').ok_or_else(invalid)?;
+            let index = after[..close].trim();
+            let index = match index.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => index.parse()
+            }.map_err(|_| invalid())?;
+            (PathStep::Index(index), &after[close + 1..])
+        } else {
+            return Err(invalid());
+        };
+
+        if let PathStep::Field("") | PathStep::Deref("") = step {
+            return Err(invalid());
+        }
+        steps.push(step);
+        rest = remaining;
+    }
+
+    match type_name.is_empty() {
+        true => Err(invalid()),
+        false => Ok((type_name, steps))
+    }
+}
+
+fn indirection_size(indirection: pdb::Indirection) -> u32 {
+    match indirection {
+        pdb::Indirection::Near16 | pdb::Indirection::Far16 | pdb::Indirection::Huge16 => 2,
+        pdb::Indirection::Near32 | pdb::Indirection::Far32 => 4,
+        pdb::Indirection::Near64 => 8,
+        pdb::Indirection::Near128 => 16,
+    }
+}
+
+/*
+Names MSVC gives to unnamed structs / unions / enums (<unnamed-tag>, <anonymous-tag>,
+<unnamed-type-u>, __unnamed).
+*/
+pub fn is_unnamed(name: &str) -> bool {
+    name.starts_with('<') || name.starts_with("__unnamed") || name.contains("<unnamed") || name.contains("<anonymous")
+}
+
+pub fn is_bitfield_continuation(previous: &Field, field: &Field) -> bool {
+    match (previous.bitfield, field.bitfield) {
+        (Some(previous_bits), Some(bits)) => previous.offset == field.offset && bits.position >= previous_bits.position + previous_bits.length,
+        _ => false
+    }
+}
+
+pub fn align_up(value: u64, alignment: u64) -> u64 {
+    match alignment {
+        0 | 1 => value,
+        alignment => value.div_ceil(alignment) * alignment
+    }
+}
+
+/*
+Explicit padding generated code needs in front of a member (or at the end of a type).
+
+@cursor    => End of what's laid out so far.
+@offset    => Where the member starts (or the size of the type).
+@alignment => Alignment the compiler pads the cursor to on its own.
+
+RETURN
+returns the size of the padding, None if the compiler gets there on its own.
+*/
+pub fn padding(cursor: u64, offset: u64, alignment: u64) -> Option<u64> {
+    match offset > align_up(cursor, alignment) {
+        true => Some(offset - cursor),
+        false => None
+    }
+}
+
+/*
+Offset the first of a sequence of members starts at.
+*/
+pub fn members_start(members: &[Member]) -> u64 {
+    match members.first() {
+        Some(Member::Field(field)) => field.offset,
+        Some(Member::Union(alternatives)) => alternatives.first().map_or(0, |alternative| members_start(alternative)),
+        None => 0
+    }
+}
+
+/*
+Converts TPI records into TypeRefs. Forward references are replaced by their definition.
+*/
+struct Converter<'a, 't> {
+    type_finder: &'a TypeFinder<'t>,
+    // Forward reference => definition
+    definitions: &'a HashMap<TypeIndex, TypeIndex>,
+}
+
+impl<'a, 't> Converter<'a, 't> {
+    fn resolve(&self, index: TypeIndex) -> TypeIndex {
+        self.definitions.get(&index).copied().unwrap_or(index)
+    }
+
+    fn size(&self, index: TypeIndex) -> u64 {
+        let index = self.resolve(index);
+        match self.type_finder.find(index).and_then(|typ| typ.parse()) {
+            Ok(pdb::TypeData::Primitive(primitive)) => match primitive.indirection {
+                Some(indirection) => u64::from(indirection_size(indirection)),
+                None => primitive_size(primitive.kind)
+            },
+            Ok(pdb::TypeData::Class(class)) => u64::from(class.size),
+            Ok(pdb::TypeData::Union(union)) => u64::from(union.size),
+            Ok(pdb::TypeData::Enumeration(enumeration)) => self.size(enumeration.underlying_type),
+            Ok(pdb::TypeData::Pointer(pointer)) => u64::from(pointer.attributes.size()),
+            Ok(pdb::TypeData::Modifier(modifier)) => self.size(modifier.underlying_type),
+            Ok(pdb::TypeData::Array(array)) => array.dimensions.last().copied().map(u64::from).unwrap_or(0),
+            Ok(pdb::TypeData::Bitfield(bitfield)) => self.size(bitfield.underlying_type),
+            _ => 0
+        }
+    }
+
+    fn convert(&self, index: TypeIndex) -> TypeRef {
+        let index = self.resolve(index);
+        let typ = match self.type_finder.find(index).and_then(|typ| typ.parse()) {
+            Ok(typ) => typ,
+            Err(_) => return TypeRef::Unknown
+        };
+
+        match typ {
+            pdb::TypeData::Primitive(primitive) => match primitive.indirection {
+                Some(indirection) => TypeRef::Pointer {
+                    pointee: Box::new(TypeRef::Primitive(primitive.kind)),
+                    size: indirection_size(indirection),
+                },
+                None => TypeRef::Primitive(primitive.kind)
+            },
+            pdb::TypeData::Class(class) => match class.properties.forward_reference() {
+                true => TypeRef::Declared {
+                    kind: class_kind(class.kind),
+                    name: class.name.to_string().into_owned(),
+                },
+                false => TypeRef::Udt(index)
+            },
+            pdb::TypeData::Union(union) => match union.properties.forward_reference() {
+                true => TypeRef::Declared {
+                    kind: UdtKind::Union,
+                    name: union.name.to_string().into_owned(),
+                },
+                false => TypeRef::Udt(index)
+            },
+            pdb::TypeData::Enumeration(_) => TypeRef::Enum(index),
+            pdb::TypeData::Pointer(pointer) => TypeRef::Pointer {
+                pointee: Box::new(self.convert(pointer.underlying_type)),
+                size: u32::from(pointer.attributes.size()),
+            },
+            pdb::TypeData::Modifier(modifier) => TypeRef::Modified {
+                inner: Box::new(self.convert(modifier.underlying_type)),
+                constant: modifier.constant,
+                volatile: modifier.volatile,
+            },
+            pdb::TypeData::Array(array) => {
+                // Dimensions are byte sizes, each one including the lower ones
+                let mut typ = self.convert(array.element_type);
+                let mut element_size = self.size(array.element_type);
+                for dimension in array.dimensions {
+                    let dimension = u64::from(dimension);
+                    let count = match element_size {
+                        0 => 0,
+                        element_size => dimension / element_size
+                    };
+                    typ = TypeRef::Array { element: Box::new(typ), count: count as u32 };
+                    element_size = dimension;
+                }
+                typ
+            },
+            pdb::TypeData::Procedure(_) | pdb::TypeData::MemberFunction(_) => TypeRef::Function,
+            _ => TypeRef::Unknown
+        }
+    }
+
+    /*
+    Types a symbol's type is made of: the return and argument types of a procedure type, the type
+    itself otherwise.
+    */
+    fn signature(&self, index: TypeIndex) -> Vec<TypeRef> {
+        let (return_type, argument_list) = match self.type_finder.find(index).and_then(|typ| typ.parse()) {
+            Ok(pdb::TypeData::Procedure(procedure)) => (procedure.return_type, procedure.argument_list),
+            Ok(pdb::TypeData::MemberFunction(function)) => (Some(function.return_type), function.argument_list),
+            _ => return vec![self.convert(index)]
+        };
+
+        let mut types: Vec<TypeRef> = return_type.map(|return_type| self.convert(return_type)).into_iter().collect();
+        if let Ok(pdb::TypeData::ArgumentList(list)) = self.type_finder.find(argument_list).and_then(|typ| typ.parse()) {
+            types.extend(list.arguments.into_iter().map(|argument| self.convert(argument)));
+        }
+        types
+    }
+
+    fn field_list(&self, fields: TypeIndex, udt: &mut Udt) {
+        let mut next = Some(fields);
+        while let Some(fields) = next.take() {
+            let list = match self.type_finder.find(fields).and_then(|typ| typ.parse()) {
+                Ok(pdb::TypeData::FieldList(list)) => list,
+                _ => return
+            };
+
+            for field in list.fields {
+                match field {
+                    pdb::TypeData::Member(member) => {
+                        let bitfield = match self.type_finder.find(member.field_type).and_then(|typ| typ.parse()) {
+                            Ok(pdb::TypeData::Bitfield(bitfield)) => Some(bitfield),
+                            _ => None
+                        };
+
+                        udt.fields.push(Field {
+                            name: member.name.to_string().into_owned(),
+                            offset: u64::from(member.offset),
+                            typ: self.convert(bitfield.as_ref().map_or(member.field_type, |bitfield| bitfield.underlying_type)),
+                            bitfield: bitfield.map(|bitfield| Bitfield { position: bitfield.position, length: bitfield.length }),
+                        });
+                    },
+                    pdb::TypeData::BaseClass(base) => udt.bases.push(BaseClass {
+                        typ: self.convert(base.base_class),
+                        offset: u64::from(base.offset),
+                    }),
+                    pdb::TypeData::VirtualFunctionTablePointer(_) => udt.has_vfptr = true,
+                    _ => {}
+                }
+            }
+            next = list.continuation;
+        }
+    }
+
+    fn enumerates(&self, fields: TypeIndex) -> Vec<(String, i128)> {
+        let mut values = Vec::new();
+        let mut next = Some(fields);
+        while let Some(fields) = next.take() {
+            let list = match self.type_finder.find(fields).and_then(|typ| typ.parse()) {
+                Ok(pdb::TypeData::FieldList(list)) => list,
+                _ => break
+            };
+
+            for field in list.fields {
+                if let pdb::TypeData::Enumerate(enumerate) = field {
+                    values.push((enumerate.name.to_string().into_owned(), crate::symbols::variant_value(enumerate.value)));
+                }
+            }
+            next = list.continuation;
+        }
+        values
+    }
+}
+
+fn class_kind(kind: pdb::ClassKind) -> UdtKind {
+    match kind {
+        pdb::ClassKind::Class => UdtKind::Class,
+        pdb::ClassKind::Struct => UdtKind::Struct,
+        pdb::ClassKind::Interface => UdtKind::Interface,
+    }
+}
+
+pub struct TypeLayouts {
+    udts: HashMap<TypeIndex, Udt>,
+    enums: HashMap<TypeIndex, Enumeration>,
+    // First definition of every (named) type
+    names: HashMap<String, TypeIndex>,
+    // Forward reference => definition
+    forward_references: HashMap<TypeIndex, TypeIndex>,
+    machine: pdb::MachineType,
+    // Size of a data pointer on the PDB's target
+    pointer_size: u32,
+    // PDB GUID (uppercase, with dashes) and DBI age, what the image's debug directory points to
+    guid: String,
+    age: u32,
+}
+
+impl TypeLayouts {
+    /*
+    Reads every struct, union and enum definition of a PDB file.
+
+    @pdb_name => Path to the PDB file.
+    */
+    pub fn load(pdb_name: &str) -> PyResult<TypeLayouts> {
+        let mut pdb = crate::open_pdb_and_parse(pdb_name)?;
+        let information = pdb.pdb_information().map_err(pdb_error)?;
+        let debug_information = pdb.debug_information().map_err(pdb_error)?;
+        let machine = debug_information.machine_type().unwrap_or(pdb::MachineType::Unknown);
+        let pointer_size = match machine {
+            pdb::MachineType::X86 | pdb::MachineType::Arm | pdb::MachineType::ArmNT | pdb::MachineType::Thumb => 4,
+            _ => 8
+        };
+        let age = debug_information.age().unwrap_or(information.age);
+        let type_information = pdb.type_information().map_err(pdb_error)?;
+        let type_finder = crate::fill_item_finder(&type_information)?;
+
+        // Forward references are matched to their definition by unique (decorated) name, or by name
+        let mut by_name: HashMap<String, TypeIndex> = HashMap::new();
+        let mut forward_references: Vec<(TypeIndex, String)> = Vec::new();
+        let mut definitions = Vec::new();
+
+        let mut iter = type_information.iter();
+        while let Some(typ) = iter.next().map_err(pdb_error)? {
+            let (name, unique_name, forward_reference) = match typ.parse() {
+                Ok(pdb::TypeData::Class(class)) => (class.name, class.unique_name, class.properties.forward_reference()),
+                Ok(pdb::TypeData::Union(union)) => (union.name, union.unique_name, union.properties.forward_reference()),
+                Ok(pdb::TypeData::Enumeration(enumeration)) => (enumeration.name, enumeration.unique_name, enumeration.properties.forward_reference()),
+                _ => continue
+            };
+            let key = unique_name.unwrap_or(name).to_string().into_owned();
+
+            if forward_reference {
+                forward_references.push((typ.index(), key));
+            } else {
+                definitions.push(typ.index());
+                by_name.entry(key).or_insert_with(|| typ.index());
+            }
+        }
+
+        let resolved: HashMap<TypeIndex, TypeIndex> = forward_references.into_iter()
+                                                                        .filter_map(|(index, key)| by_name.get(&key).map(|&definition| (index, definition)))
+                                                                        .collect();
+        let mut layouts = TypeLayouts {
+            udts: HashMap::new(),
+            enums: HashMap::new(),
+            names: HashMap::new(),
+            forward_references: resolved,
+            machine,
+            pointer_size,
+            guid: information.guid.to_hyphenated().to_string().to_uppercase(),
+            age,
+        };
+        let converter = Converter {
+            type_finder: &type_finder,
+            definitions: &layouts.forward_references,
+        };
+
+        for index in definitions {
+            let (name, fields) = match type_finder.find(index).and_then(|typ| typ.parse()) {
+                Ok(pdb::TypeData::Class(class)) => {
+                    let mut udt = Udt {
+                        index,
+                        kind: class_kind(class.kind),
+                        name: class.name.to_string().into_owned(),
+                        size: u64::from(class.size),
+                        fields: Vec::new(),
+                        bases: Vec::new(),
+                        has_vfptr: false,
+                    };
+                    if let Some(fields) = class.fields {
+                        converter.field_list(fields, &mut udt);
+                    }
+                    (udt.name.clone(), Some(udt))
+                },
+                Ok(pdb::TypeData::Union(union)) => {
+                    let mut udt = Udt {
+                        index,
+                        kind: UdtKind::Union,
+                        name: union.name.to_string().into_owned(),
+                        size: u64::from(union.size),
+                        fields: Vec::new(),
+                        bases: Vec::new(),
+                        has_vfptr: false,
+                    };
+                    converter.field_list(union.fields, &mut udt);
+                    (udt.name.clone(), Some(udt))
+                },
+                Ok(pdb::TypeData::Enumeration(enumeration)) => {
+                    let underlying = match type_finder.find(enumeration.underlying_type).and_then(|typ| typ.parse()) {
+                        Ok(pdb::TypeData::Primitive(primitive)) => primitive.kind,
+                        _ => PrimitiveKind::I32
+                    };
+                    let name = enumeration.name.to_string().into_owned();
+                    layouts.enums.insert(index, Enumeration {
+                        name: name.clone(),
+                        underlying,
+                        values: converter.enumerates(enumeration.fields),
+                    });
+                    (name, None)
+                },
+                _ => continue
+            };
+
+            if let Some(udt) = fields {
+                layouts.udts.insert(index, udt);
+            }
+            if !is_unnamed(&name) {
+                layouts.names.entry(name).or_insert(index);
+            }
+        }
+        Ok(layouts)
+    }
+
+    pub fn udt(&self, index: TypeIndex) -> Option<&Udt> {
+        self.udts.get(&index)
+    }
+
+    pub fn enumeration(&self, index: TypeIndex) -> Option<&Enumeration> {
+        self.enums.get(&index)
+    }
+
+    /*
+    Converts the types of symbols (GlobalSymbol::type_index): a data symbol's type, or the return
+    type then the argument types of a function's procedure type.
+
+    @pdb_name => Path to the PDB file the layouts were loaded from.
+    @indexes  => TPI indexes of the symbols' types.
+
+    RETURN
+    returns the types of each index, in order.
+    */
+    pub fn symbol_types(&self, pdb_name: &str, indexes: &[TypeIndex]) -> PyResult<Vec<Vec<TypeRef>>> {
+        let mut pdb = crate::open_pdb_and_parse(pdb_name)?;
+        let type_information = pdb.type_information().map_err(pdb_error)?;
+        let type_finder = crate::fill_item_finder(&type_information)?;
+        let converter = Converter {
+            type_finder: &type_finder,
+            definitions: &self.forward_references,
+        };
+
+        Ok(indexes.iter().map(|&index| converter.signature(index)).collect())
+    }
+
+    /*
+    Finds a struct / union / enum definition by its exact name.
+    */
+    pub fn find(&self, name: &str) -> Option<TypeIndex> {
+        self.names.get(name).copied()
+    }
+
+    fn references(&self, typ: &TypeRef, by_value: bool, embedded: &mut Vec<TypeIndex>, pointed: &mut Vec<TypeRef>) {
+        match typ {
+            TypeRef::Udt(index) => match by_value {
+                true => embedded.push(*index),
+                false => pointed.push(typ.clone())
+            },
+            TypeRef::Enum(index) => embedded.push(*index),
+            TypeRef::Declared { .. } => pointed.push(typ.clone()),
+            TypeRef::Pointer { pointee, .. } => self.references(pointee, false, embedded, pointed),
+            TypeRef::Array { element: inner, .. } | TypeRef::Modified { inner, .. } => self.references(inner, by_value, embedded, pointed),
+            _ => {}
+        }
+    }
+
+    /*
+    References of a struct / union's fields. With inline_unnamed, the unnamed types of plain
+    fields are part of the type (written inline) and their references count as its own.
+    */
+    fn udt_references(&self, udt: &Udt, inline_unnamed: bool, embedded: &mut Vec<TypeIndex>, pointed: &mut Vec<TypeRef>) {
+        for field in self.all_fields(udt) {
+            let inlined = match (&field.typ, inline_unnamed) {
+                (TypeRef::Udt(index), true) => self.udts.get(index).filter(|inner| is_unnamed(&inner.name)),
+                _ => None
+            };
+            match inlined {
+                Some(inner) => self.udt_references(inner, inline_unnamed, embedded, pointed),
+                None => self.references(&field.typ, true, embedded, pointed)
+            }
+        }
+    }
+
+    /*
+    Every struct / union / enum a set of types references, each one after the types it embeds.
+
+    @roots           => Types to start from.
+    @follow_pointers => Also include the types that are only pointed at.
+
+    RETURN
+    returns the definitions, in dependency order.
+    */
+    pub fn closure(&self, roots: &[TypeIndex], follow_pointers: bool) -> Vec<TypeIndex> {
+        self.type_closure(roots, follow_pointers, false).definitions
+    }
+
+    /*
+    Everything a set of types references.
+
+    @roots           => Types to start from.
+    @follow_pointers => Also define the types that are only pointed at.
+    @inline_unnamed  => Unnamed types of plain fields aren't definitions of their own, for
+                        generators writing them where the field is.
+
+    RETURN
+    returns the definitions in dependency order, and the types pointed at.
+    */
+    pub fn type_closure(&self, roots: &[TypeIndex], follow_pointers: bool, inline_unnamed: bool) -> Closure {
+        let mut closure = Closure::default();
+        let mut done = HashSet::new();
+        let mut visiting = HashSet::new();
+        let mut stack: Vec<(TypeIndex, bool)> = roots.iter().rev().map(|&index| (index, false)).collect();
+
+        while let Some((index, dependencies_done)) = stack.pop() {
+            if done.contains(&index) {
+                continue;
+            }
+            if dependencies_done {
+                done.insert(index);
+                closure.definitions.push(index);
+                continue;
+            }
+            // Embedding cycles can't happen in valid PDBs, don't loop on broken ones
+            if !visiting.insert(index) {
+                continue;
+            }
+
+            let mut embedded = Vec::new();
+            let mut pointed = Vec::new();
+            if let Some(udt) = self.udts.get(&index) {
+                self.udt_references(udt, inline_unnamed, &mut embedded, &mut pointed);
+            }
+
+            // Pointed at types go after this one, embedded ones before
+            if follow_pointers {
+                stack.extend(pointed.iter().rev().filter_map(|typ| match typ {
+                    TypeRef::Udt(pointed) => Some((*pointed, false)),
+                    _ => None
+                }));
+            }
+            closure.pointed.extend(pointed);
+            stack.push((index, true));
+            stack.extend(embedded.into_iter().rev().filter(|embedded| !done.contains(embedded)).map(|embedded| (embedded, false)));
+        }
+        closure
+    }
+
+    pub fn machine(&self) -> pdb::MachineType {
+        self.machine
+    }
+
+    pub fn pointer_size(&self) -> u32 {
+        self.pointer_size
+    }
+
+    pub fn guid(&self) -> &str {
+        &self.guid
+    }
+
+    pub fn age(&self) -> u32 {
+        self.age
+    }
+
+    /*
+    Every struct / union / enum definition, in TPI order.
+    */
+    pub fn definitions(&self) -> Vec<TypeIndex> {
+        let mut definitions: Vec<TypeIndex> = self.udts.keys().chain(self.enums.keys()).copied().collect();
+        definitions.sort();
+        definitions
+    }
+
+    /*
+    Name of a definition, or "" for unknown indexes.
+    */
+    pub fn name(&self, index: TypeIndex) -> &str {
+        match (self.udts.get(&index), self.enums.get(&index)) {
+            (Some(udt), _) => &udt.name,
+            (_, Some(enumeration)) => &enumeration.name,
+            _ => ""
+        }
+    }
+
+    pub fn size_of(&self, typ: &TypeRef) -> u64 {
+        match typ {
+            TypeRef::Primitive(kind) => primitive_size(*kind),
+            TypeRef::Udt(index) => self.udts.get(index).map_or(0, |udt| udt.size),
+            TypeRef::Enum(index) => self.enums.get(index).map_or(4, |enumeration| primitive_size(enumeration.underlying)),
+            TypeRef::Pointer { size, .. } => u64::from(*size),
+            TypeRef::Array { element, count } => self.size_of(element) * u64::from(*count),
+            TypeRef::Modified { inner, .. } => self.size_of(inner),
+            TypeRef::Declared { .. } | TypeRef::Function | TypeRef::Unknown => 0,
+        }
+    }
+
+    /*
+    Alignment the compiler pads to on its own in front of a field, for generated code padding the
+    rest explicitly. Only MSVC aligns bitfield storage units to their type, so bitfields count on
+    none.
+    */
+    pub fn padding_alignment(&self, field: &Field, packing: Option<u64>) -> u64 {
+        match field.bitfield {
+            Some(_) => 1,
+            None => self.align_of(&field.typ).min(packing.unwrap_or(u64::MAX))
+        }
+    }
+
+    /*
+    Alignment the compiler would give a type, taking the packing of structs into account.
+    */
+    pub fn align_of(&self, typ: &TypeRef) -> u64 {
+        match typ {
+            TypeRef::Udt(index) => self.udts.get(index).map_or(1, |udt| self.alignment(udt)),
+            TypeRef::Array { element, .. } => self.align_of(element),
+            TypeRef::Modified { inner, .. } => self.align_of(inner),
+            typ => match self.size_of(typ) {
+                0 => 1,
+                size @ (1 | 2 | 4 | 8 | 16) => size,
+                _ => 8
+            }
+        }
+    }
+
+    fn natural_alignment(&self, udt: &Udt) -> u64 {
+        let fields = udt.fields.iter().map(|field| self.align_of(&field.typ));
+        let bases = udt.bases.iter().map(|base| self.align_of(&base.typ));
+        let vfptr = match udt.has_vfptr {
+            true => u64::from(self.pointer_size),
+            false => 1
+        };
+        fields.chain(bases).fold(vfptr, u64::max)
+    }
+
+    pub fn alignment(&self, udt: &Udt) -> u64 {
+        let natural = self.natural_alignment(udt);
+        match self.packing(udt) {
+            Some(packing) => natural.min(packing),
+            None => natural
+        }
+    }
+
+    /*
+    Finds the #pragma pack a struct was declared with: the largest packing under which every
+    field offset and the struct size are still aligned.
+
+    RETURN
+    returns None when the natural alignment explains the layout.
+    */
+    pub fn packing(&self, udt: &Udt) -> Option<u64> {
+        let natural = self.natural_alignment(udt);
+        let fits = |packing: u64| {
+            udt.size.is_multiple_of(natural.min(packing)) &&
+                udt.fields.iter().all(|field| field.offset.is_multiple_of(self.align_of(&field.typ).min(packing)))
+        };
+
+        if fits(natural) {
+            return None;
+        }
+        [8, 4, 2, 1].iter().copied().filter(|&packing| packing < natural).find(|&packing| fits(packing))
+    }
+
+    /*
+    Fields of a struct / union, including its vftable pointer and base classes.
+    */
+    pub fn all_fields(&self, udt: &Udt) -> Vec<Field> {
+        let mut fields = Vec::new();
+        if udt.has_vfptr {
+            fields.push(Field {
+                name: String::from("__vfptr"),
+                offset: 0,
+                typ: TypeRef::Pointer { pointee: Box::new(TypeRef::Primitive(PrimitiveKind::Void)), size: self.pointer_size },
+                bitfield: None,
+            });
+        }
+        for (count, base) in udt.bases.iter().enumerate() {
+            fields.push(Field {
+                name: format!("__base{}", count),
+                offset: base.offset,
+                typ: base.typ.clone(),
+                bitfield: None,
+            });
+        }
+        fields.extend(udt.fields.iter().cloned());
+        fields
+    }
+
+    /*
+    Resolves a field path: fields of embedded structs / unions (.), array elements ([3]) and
+    fields behind pointers (->), e.g _KPCR.Prcb.VectorToInterruptObject[3]->Dispatch. Offsets
+    never add up across a pointer, a -> starts a new segment at the start of the pointed type.
+
+    RETURN
+    returns the segments of the path, each ending with the field it reaches (named by the path up
+    to it, with its offset from the start of the segment's type), the last one the target.
+    */
+    pub fn resolve_path(&self, path: &str) -> PyResult<Vec<PathSegment>> {
+        self.lookup_path(path)?
+    }
+
+    /*
+    resolve_path, telling apart a path this PDB doesn't have (no such type or field, the inner
+    error) from one that can't be resolved in any PDB (a bad path, an index out of range, a field
+    of something that isn't a struct, the outer error).
+    */
+    fn lookup_path(&self, path: &str) -> PyResult<PyResult<Vec<PathSegment>>> {
+        let (type_name, steps) = parse_path(path)?;
+        let index = match self.find(type_name) {
+            Some(index) => index,
+            None => return Ok(Err(KeyError::py_err(format!("[pymspdb] No type named {}", type_name))))
+        };
+        if steps.is_empty() {
+            return Err(KeyError::py_err(format!("[pymspdb] {} is not a field path (Type.Field)", path)));
+        }
+
+        let mut segments = Vec::new();
+        let mut base = String::from(type_name);
+        let mut walked = String::from(type_name);
+        let mut current = TypeRef::Udt(index);
+        let mut offset = 0;
+        let mut bitfield = None;
+
+        for step in steps {
+            if bitfield.is_some() {
+                return Err(KeyError::py_err(format!("[pymspdb] {}: {} is a bitfield", path, walked)));
+            }
+
+            let name = match step {
+                PathStep::Index(element_index) => {
+                    let (element, count) = match strip_modifiers(&current) {
+                        TypeRef::Array { element, count } => (element.as_ref().clone(), *count),
+                        _ => return Err(KeyError::py_err(format!("[pymspdb] {}: {} is not an array", path, walked)))
+                    };
+                    if element_index >= u64::from(count) {
+                        return Err(IndexError::py_err(format!("[pymspdb] {}: {} has {} elements", path, walked, count)));
+                    }
+                    offset += element_index * self.size_of(&element);
+                    current = element;
+                    walked.push_str(&format!("[{}]", element_index));
+                    continue;
+                },
+                PathStep::Deref(name) => {
+                    let pointee = match strip_modifiers(&current) {
+                        TypeRef::Pointer { pointee, .. } => pointee.as_ref().clone(),
+                        _ => return Err(KeyError::py_err(format!("[pymspdb] {}: {} is not a pointer", path, walked)))
+                    };
+                    let pointee_name = match strip_modifiers(&pointee) {
+                        TypeRef::Udt(index) => String::from(self.name(*index)),
+                        TypeRef::Declared { name, .. } => return Err(KeyError::py_err(format!("[pymspdb] {}: {} is only forward declared", path, name))),
+                        _ => return Err(KeyError::py_err(format!("[pymspdb] {}: {} does not point to a struct or union", path, walked)))
+                    };
+                    segments.push(PathSegment {
+                        base: std::mem::replace(&mut base, pointee_name),
+                        field: Field { name: walked.clone(), offset, typ: current, bitfield: None },
+                    });
+                    walked.push_str("->");
+                    current = pointee;
+                    offset = 0;
+                    name
+                },
+                PathStep::Field(name) => {
+                    walked.push('.');
+                    name
+                }
+            };
+
+            let udt = match self.embedded_udt(&current) {
+                Some(udt) => udt,
+                None => return Err(KeyError::py_err(format!("[pymspdb] {}: {} is not a struct or union", path, walked.trim_end_matches(&['.', '-', '>'][..]))))
+            };
+            let field = match self.all_fields(udt).into_iter().find(|field| field.name == name) {
+                Some(field) => field,
+                None => return Ok(Err(KeyError::py_err(format!("[pymspdb] {}: {} has no field {}", path, udt.name, name))))
+            };
+            offset += field.offset;
+            current = field.typ;
+            bitfield = field.bitfield;
+            walked.push_str(name);
+        }
+
+        segments.push(PathSegment {
+            base,
+            field: Field { name: walked, offset, typ: current, bitfield },
+        });
+        Ok(Ok(segments))
+    }
+
+    /*
+    Resolves a field path that stays inside its type (no ->), e.g _EPROCESS.Pcb.DirectoryTableBase.
+
+    RETURN
+    returns the field, named by the whole path, with its offset from the start of the type.
+    */
+    pub fn field_path(&self, path: &str) -> PyResult<Field> {
+        self.find_field_path(path)?
+    }
+
+    /*
+    field_path for a field some builds don't have.
+
+    RETURN
+    returns the field, or the error of field_path as the inner error when this PDB has no such type
+    or field, the outer error for a path no PDB could resolve.
+    */
+    pub fn find_field_path(&self, path: &str) -> PyResult<PyResult<Field>> {
+        let mut segments = match self.lookup_path(path)? {
+            Ok(segments) => segments,
+            Err(e) => return Ok(Err(e))
+        };
+        match segments.len() {
+            1 => Ok(Ok(segments.remove(0).field)),
+            _ => Err(KeyError::py_err(format!("[pymspdb] {} goes through a pointer (->), it has no constant offset", path)))
+        }
+    }
+
+    /*
+    Every field covering a byte of a type, through nested structs, unions (each alternative) and
+    array elements, e.g offset 0x2e8 of _EPROCESS.
+
+    RETURN
+    returns the innermost fields covering the byte, named by their path (_EPROCESS.Pcb.Header.Lock,
+    ...[3] for array elements) with their offset from the start of the type, each with the offset
+    of the byte into it. Empty when the byte is padding.
+    */
+    pub fn fields_at(&self, index: TypeIndex, offset: u64) -> Vec<(Field, u64)> {
+        let mut hits = Vec::new();
+        if let Some(udt) = self.udts.get(&index) {
+            if offset < udt.size {
+                self.udt_fields_at(udt, &udt.name, 0, offset, &mut hits);
+            }
+        }
+        hits
+    }
+
+    fn udt_fields_at(&self, udt: &Udt, path: &str, start: u64, offset: u64, hits: &mut Vec<(Field, u64)>) {
+        for field in self.all_fields(udt) {
+            let size = self.size_of(&field.typ);
+            if offset < field.offset || offset >= field.offset + size {
+                continue;
+            }
+            // Bitfields only cover the bytes their bits are in
+            if let Some(bitfield) = field.bitfield {
+                let first_bit = (offset - field.offset) * 8;
+                let (position, length) = (u64::from(bitfield.position), u64::from(bitfield.length));
+                if first_bit + 8 <= position || first_bit >= position + length {
+                    continue;
+                }
+            }
+
+            let field = Field {
+                name: format!("{}.{}", path, field.name),
+                offset: start + field.offset,
+                ..field
+            };
+            let delta = offset - (field.offset - start);
+            self.typed_fields_at(field, delta, hits);
+        }
+    }
+
+    fn typed_fields_at(&self, field: Field, delta: u64, hits: &mut Vec<(Field, u64)>) {
+        if field.bitfield.is_none() {
+            match strip_modifiers(&field.typ) {
+                TypeRef::Udt(index) => {
+                    let found = hits.len();
+                    if let Some(udt) = self.udts.get(index) {
+                        self.udt_fields_at(udt, &field.name, field.offset, delta, hits);
+                    }
+                    // Padding of the embedded struct: it is the innermost field
+                    if hits.len() == found {
+                        hits.push((field, delta));
+                    }
+                    return;
+                },
+                TypeRef::Array { element, .. } => {
+                    let element_size = self.size_of(element);
+                    if let Some(element_index) = delta.checked_div(element_size) {
+                        let element = Field {
+                            name: format!("{}[{}]", field.name, element_index),
+                            offset: field.offset + element_index * element_size,
+                            typ: element.as_ref().clone(),
+                            bitfield: None,
+                        };
+                        self.typed_fields_at(element, delta % element_size, hits);
+                        return;
+                    }
+                },
+                _ => {}
+            }
+        }
+        hits.push((field, delta));
+    }
+
+    /*
+    Struct / union / enum a type is made of (through arrays and modifiers).
+
+    RETURN
+    returns its name, and whether it's only reached through a pointer.
+    */
+    pub fn referenced_type(&self, typ: &TypeRef) -> Option<(String, bool)> {
+        self.referenced_type_through(typ, false)
+    }
+
+    fn referenced_type_through(&self, typ: &TypeRef, pointed: bool) -> Option<(String, bool)> {
+        match typ {
+            TypeRef::Udt(index) | TypeRef::Enum(index) => Some((String::from(self.name(*index)), pointed)),
+            TypeRef::Declared { name, .. } => Some((name.clone(), pointed)),
+            TypeRef::Pointer { pointee, .. } => self.referenced_type_through(pointee, true),
+            TypeRef::Array { element: inner, .. } | TypeRef::Modified { inner, .. } => self.referenced_type_through(inner, pointed),
+            _ => None
+        }
+    }
+
+    fn embedded_udt(&self, typ: &TypeRef) -> Option<&Udt> {
+        match typ {
+            TypeRef::Udt(index) => self.udts.get(index),
+            TypeRef::Modified { inner, .. } => self.embedded_udt(inner),
+            _ => None
+        }
+    }
+
+    /*
+    Integer a bitfield of the type is stored in.
+    */
+    pub fn storage_kind(&self, typ: &TypeRef) -> Option<PrimitiveKind> {
+        match typ {
+            TypeRef::Primitive(kind) => Some(*kind),
+            TypeRef::Enum(index) => self.enums.get(index).map(|enumeration| enumeration.underlying),
+            TypeRef::Modified { inner, .. } => self.storage_kind(inner),
+            _ => None
+        }
+    }
+
+    /*
+    Byte the storage of a field ends at (the whole storage unit for bitfields).
+    */
+    pub fn field_end(&self, field: &Field) -> u64 {
+        field.offset + self.size_of(&field.typ)
+    }
+
+    /*
+    Alignment of a sequence of members (an anonymous struct), under the packing of the type
+    they're declared in.
+    */
+    pub fn members_alignment(&self, members: &[Member], packing: Option<u64>) -> u64 {
+        members.iter().map(|member| match member {
+            Member::Field(field) => self.align_of(&field.typ).min(packing.unwrap_or(u64::MAX)),
+            Member::Union(alternatives) => self.alternatives_alignment(alternatives, packing)
+        }).max().unwrap_or(1)
+    }
+
+    pub fn alternatives_alignment(&self, alternatives: &[Vec<Member>], packing: Option<u64>) -> u64 {
+        alternatives.iter().map(|alternative| self.members_alignment(alternative, packing)).max().unwrap_or(1)
+    }
+
+    /*
+    Byte a sequence of members ends at.
+    */
+    pub fn members_end(&self, members: &[Member]) -> u64 {
+        members.iter().map(|member| match member {
+            Member::Field(field) => self.field_end(field),
+            Member::Union(alternatives) => self.alternatives_end(alternatives)
+        }).max().unwrap_or(0)
+    }
+
+    pub fn alternatives_end(&self, alternatives: &[Vec<Member>]) -> u64 {
+        alternatives.iter().map(|alternative| self.members_end(alternative)).max().unwrap_or(0)
+    }
+
+    /*
+    Rebuilds the anonymous unions (and the anonymous structs inside them) MSVC flattened into a
+    field list: a union starts at a field whose offset a later field goes back to.
+
+    @fields => Fields, in declaration order.
+
+    RETURN
+    returns the members in declaration order.
+    */
+    pub fn group_members<'a>(&self, fields: &'a [Field]) -> Vec<Member<'a>> {
+        let mut members = Vec::new();
+        let mut index = 0;
+
+        while index < fields.len() {
+            let start = fields[index].offset;
+            let mut bounds: Vec<usize> = vec![index];
+            bounds.extend((index + 1..fields.len()).filter(|&later| {
+                fields[later].offset == start && !is_bitfield_continuation(&fields[later - 1], &fields[later])
+            }));
+
+            if bounds.len() == 1 {
+                members.push(Member::Field(&fields[index]));
+                index += 1;
+                continue;
+            }
+
+            // The last alternative goes on for as long as it stays inside the earlier ones
+            let last = bounds[bounds.len() - 1];
+            let union_end = fields[index..last].iter().map(|field| self.field_end(field)).max().unwrap_or(start);
+            let mut end = last + 1;
+            while end < fields.len() && fields[end].offset < union_end && fields[end].offset >= fields[end - 1].offset {
+                end += 1;
+            }
+            bounds.push(end);
+
+            members.push(Member::Union(bounds.windows(2).map(|range| self.group_members(&fields[range[0]..range[1]])).collect()));
+            index = end;
+        }
+        members
+    }
+
+    /*
+    Alternatives of a union type, each one a single field or an anonymous struct.
+    */
+    pub fn union_alternatives<'a>(&self, fields: &'a [Field]) -> Vec<Vec<Member<'a>>> {
+        let mut members = self.group_members(fields);
+        if let [Member::Union(_)] = members.as_slice() {
+            if let Some(Member::Union(alternatives)) = members.pop() {
+                return alternatives;
+            }
+        }
+        members.into_iter().map(|member| vec![member]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{hit, kernel_layouts, kernel_types};
+
+    #[test]
+    fn resolves_field_paths() {
+        let layouts = kernel_layouts();
+        let field = layouts.field_path("_EPROCESS.Pcb.DirectoryTableBase").unwrap();
+        assert_eq!((field.name.as_str(), field.offset), ("_EPROCESS.Pcb.DirectoryTableBase", 0x10));
+
+        // Members of anonymous unions and structs are fields of the parent
+        let field = layouts.field_path("_EPROCESS.Spare").unwrap();
+        assert_eq!(field.offset, 0x88);
+        assert_eq!((field.bitfield.unwrap().position, field.bitfield.unwrap().length), (1, 31));
+
+        let field = layouts.field_path("_EPROCESS.Matrix[1][2]").unwrap();
+        assert_eq!((field.name.as_str(), field.offset), ("_EPROCESS.Matrix[1][2]", 0x78 + 6 + 4));
+        assert_eq!(layouts.size_of(&field.typ), 2);
+    }
+
+    #[test]
+    fn starts_a_segment_at_every_pointer() {
+        let layouts = kernel_layouts();
+        let segments = layouts.resolve_path("_ETHREAD.ThreadsProcess->ActiveProcessLinks.Blink").unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!((segments[0].base.as_str(), segments[0].field.name.as_str(), segments[0].field.offset), ("_ETHREAD", "_ETHREAD.ThreadsProcess", 0));
+        assert_eq!(segments[1].base, "_EPROCESS");
+        assert_eq!((segments[1].field.name.as_str(), segments[1].field.offset), ("_ETHREAD.ThreadsProcess->ActiveProcessLinks.Blink", 0x40));
+
+        assert!(layouts.field_path("_ETHREAD.ThreadsProcess->Token").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_paths() {
+        let layouts = kernel_layouts();
+        for path in &["_NOPE.Field", "_EPROCESS", "_EPROCESS.Nope", "_EPROCESS.Spare.Bit", "_EPROCESS.Flags2[0]",
+                      "_EPROCESS.Matrix[2]", "_EPROCESS.Pcb->Header", "_EPROCESS.Peb->Ldr", "_EPROCESS.Flags2.Bit"] {
+            assert!(layouts.resolve_path(path).is_err(), "{}", path);
+        }
+
+        // Only a type or field the PDB lacks is missing, every other bad path is an error
+        for path in &["_NOPE.Field", "_EPROCESS.Nope", "_EPROCESS.Pcb.Nope"] {
+            assert!(layouts.find_field_path(path).unwrap().is_err(), "{}", path);
+        }
+        for path in &["_EPROCESS", "_EPROCESS.Spare.Bit", "_EPROCESS.Flags2[0]", "_EPROCESS.Peb->Ldr", "_ETHREAD.ThreadsProcess->Token"] {
+            assert!(layouts.find_field_path(path).is_err(), "{}", path);
+        }
+    }
+
+    fn names_at(layouts: &TypeLayouts, name: &str, offset: u64) -> Vec<(String, u64, u64)> {
+        layouts.fields_at(kernel_types(layouts, &[name])[0], offset).into_iter().map(|(field, delta)| (field.name, field.offset, delta)).collect()
+    }
+
+    #[test]
+    fn finds_the_innermost_fields_at_an_offset() {
+        let layouts = kernel_layouts();
+        assert_eq!(names_at(&layouts, "_EPROCESS", 0x12), vec![hit("_EPROCESS.Pcb.DirectoryTableBase", 0x10, 2)]);
+        assert_eq!(names_at(&layouts, "_EPROCESS", 0x66), vec![hit("_EPROCESS.u.Value", 0x64, 2), hit("_EPROCESS.u.High", 0x66, 0)]);
+        assert_eq!(names_at(&layouts, "_EPROCESS", 0x7f), vec![hit("_EPROCESS.Matrix[1][0]", 0x7e, 1)]);
+    }
+
+    #[test]
+    fn only_counts_the_bytes_bitfields_are_in() {
+        let layouts = kernel_layouts();
+        assert_eq!(names_at(&layouts, "_EPROCESS", 0x88), vec![hit("_EPROCESS.CrossThreadFlags", 0x88, 0), hit("_EPROCESS.Terminated", 0x88, 0),
+                                                               hit("_EPROCESS.Spare", 0x88, 0)]);
+        assert_eq!(names_at(&layouts, "_EPROCESS", 0x8b), vec![hit("_EPROCESS.CrossThreadFlags", 0x88, 3), hit("_EPROCESS.Spare", 0x88, 3)]);
+        assert_eq!(names_at(&layouts, "_MIX", 0x4), vec![hit("_MIX.b", 0x4, 0)]);
+        assert!(names_at(&layouts, "_MIX", 0x5).is_empty());
+    }
+
+    #[test]
+    fn finds_nothing_in_padding() {
+        let layouts = kernel_layouts();
+        assert!(names_at(&layouts, "_EPROCESS", 0x8d).is_empty());
+        assert!(names_at(&layouts, "_MIX", 0x2).is_empty());
+        assert!(names_at(&layouts, "_EPROCESS", 0x98).is_empty());
+    }
+}
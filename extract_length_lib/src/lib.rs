@@ -1,15 +1,48 @@
+use std::collections::HashMap;
 use std::env;
 use std::fmt::Debug;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str;
 use std::fs::File;
 use fallible_iterator::FallibleIterator;
 use pyo3::prelude::*;
 use pyo3::exceptions::*;
 use pyo3::{PyResult};
-use pyo3::types::{PyString,  PyDict};
+use pyo3::types::{PyString,  PyDict, PyList};
 use pdb::{RawString, TypeIndex, TypeFinder};
 
+mod build_info;
+mod bytes;
+mod c_header;
+mod cab;
+mod codegen;
+mod compilands;
+mod ctypes_classes;
+mod demangle;
+mod dt_format;
+mod field_search;
+mod isf;
+mod layout;
+mod lines;
+mod manifest;
+mod offset_constants;
+mod offset_history;
+mod offset_tables;
+mod pe;
+mod rust_bindings;
+mod session;
+mod struct_diff;
+mod symbolize;
+mod symbols;
+mod symsrv;
+mod symstore;
+#[cfg(test)]
+mod test_support;
+mod type_export;
+mod type_graph;
+mod udt_sources;
+mod variables;
+
 /**
  * 
  * TYPEDATA REPLICA USED IN EXTRACT_SYMBOLS function
@@ -42,16 +75,13 @@ pub enum TypeData {
 }
 trait ErrorHandler {
     // Error checking for dictionary inserting
-    fn handle_properly(&self) -> ();
+    fn handle_properly(&self);
 }
 
 impl<T> ErrorHandler for PyResult<T> {
-    fn handle_properly(&self) -> () {
-        match self { 
-            Err(_) => {
-                println!("[{}] Couldn't set dict key.", line!());
-            },
-            _ => {}
+    fn handle_properly(&self) {
+        if self.is_err() {
+            println!("[{}] Couldn't set dict key.", line!());
         }
     }
 }
@@ -117,6 +147,30 @@ fn open_pdb_and_parse<'a>(pdb_file_name: &str) -> Result<pdb::PDB<'a, File>, PyE
     Ok(pdb)
 }
 
+/*
+Iterates over every item of a TPI / IPI stream, so the returned finder can look up any index.
+
+@information => Type information (or id information) of the PDB file.
+
+RETURN
+returns a filled item finder.
+*/
+fn fill_item_finder<'t, I: pdb::ItemIndex>(information: &'t pdb::ItemInformation<'_, I>) -> Result<pdb::ItemFinder<'t, I>, PyErr> {
+    let mut finder = information.finder();
+    let mut iter = information.iter();
+
+    loop {
+        match iter.next() {
+            Ok(Some(_)) => finder.update(&iter),
+            Ok(None) => break,
+            Err(e) => {
+                return Err(Exception::py_err(e.to_string()));
+            }
+        }
+    }
+    Ok(finder)
+}
+
 /*
 
 This function takes in a type index, and returns the type it is (according to the replica TypeDat at the top)
@@ -132,12 +186,20 @@ returns type (in replica TypeData), and string representation of type.
 */
 fn extract_type_string(type_finder: &TypeFinder, field_type: pdb::TypeIndex, 
                         member_name: Option<String>, prev_string: Option<&str>) -> (crate::TypeData, String) {
+    try_extract_type_string(type_finder, field_type, member_name, prev_string).expect("Could not parse field")
+}
+
+/*
+Same as extract_type_string, for callers that can't trust every type of the PDB.
+
+RETURN
+returns None if the type (or any type it's built from) can't be found or parsed.
+*/
+fn try_extract_type_string(type_finder: &TypeFinder, field_type: pdb::TypeIndex, 
+                           member_name: Option<String>, prev_string: Option<&str>) -> Option<(crate::TypeData, String)> {
 
     // Acquire type 
-    let type_str: (crate::TypeData, String) = match type_finder.find(field_type)
-        .expect("Could not find member")
-        .parse()
-        .expect("Could not parse field") {
+    let type_str: (crate::TypeData, String) = match type_finder.find(field_type).ok()?.parse().ok()? {
 
         pdb::TypeData::Primitive(pdb::PrimitiveType{kind, ..}) => {
             match member_name {
@@ -149,14 +211,14 @@ fn extract_type_string(type_finder: &TypeFinder, field_type: pdb::TypeIndex,
         pdb::TypeData::Array(pdb::ArrayType {element_type, indexing_type, dimensions, ..}) => {
             match member_name {
                 Some(member_name_) => (crate::TypeData::Array, 
-                    format!("{} {:?} {} (Index type: {});", extract_type_string(type_finder, element_type, None, None).1, 
+                    format!("{} {:?} {} (Index type: {});", try_extract_type_string(type_finder, element_type, None, None)?.1, 
                                                     dimensions,
                                                     member_name_,
-                                                    extract_type_string(type_finder, indexing_type, None, None).1)),
+                                                    try_extract_type_string(type_finder, indexing_type, None, None)?.1)),
                 None => (crate::TypeData::Array, 
-                    format!("{} {:?} (Index type: {})", extract_type_string(type_finder, element_type, None, None).1, 
+                    format!("{} {:?} (Index type: {})", try_extract_type_string(type_finder, element_type, None, None)?.1, 
                                                     dimensions,
-                                                    extract_type_string(type_finder, indexing_type, None, None).1))
+                                                    try_extract_type_string(type_finder, indexing_type, None, None)?.1))
             }
         },
         pdb::TypeData::Bitfield(pdb::BitfieldType{underlying_type, length, position}) => {
@@ -164,13 +226,13 @@ fn extract_type_string(type_finder: &TypeFinder, field_type: pdb::TypeIndex,
                 match member_name {
                     Some(member_name_) => (crate::TypeData::Bitfield, 
                         format!("struct {{\n{} ({:#x}) - {}:{:#x} {};\n}}", 
-                                            extract_type_string(type_finder, underlying_type, None, None).1,
+                                            try_extract_type_string(type_finder, underlying_type, None, None)?.1,
                                             length, 
                                             position, position,
                                             member_name_)),
                     None => (crate::TypeData::Bitfield, 
                         format!("struct {{\n{} ({:#x}) - {}:{:#x};\n}}", 
-                                            extract_type_string(type_finder, underlying_type, None, None).1,
+                                            try_extract_type_string(type_finder, underlying_type, None, None)?.1,
                                             length, 
                                             position, position))
                 }
@@ -184,11 +246,11 @@ fn extract_type_string(type_finder: &TypeFinder, field_type: pdb::TypeIndex,
                     match member_name {
                         Some(member_name_) => (crate::TypeData::Bitfield, format!("{}{} ({:#x}) - {}:{:#x} {};\n}}", 
                                                         prev_string, 
-                                                        extract_type_string(type_finder, underlying_type, None, None).1, 
+                                                        try_extract_type_string(type_finder, underlying_type, None, None)?.1, 
                                                         length, position, position, member_name_)),
                         None => (crate::TypeData::Bitfield, format!("{}{} ({:#x}) - {}:{:#x};\n}}", 
                                                         prev_string, 
-                                                        extract_type_string(type_finder, underlying_type, None, None).1, 
+                                                        try_extract_type_string(type_finder, underlying_type, None, None)?.1, 
                                                         length, position, position))
                     }
 
@@ -228,11 +290,11 @@ fn extract_type_string(type_finder: &TypeFinder, field_type: pdb::TypeIndex,
 
             if let Some(member_name) = member_name {
                 let member_name = format!("*{}", member_name);
-                let pointer_type = extract_type_string(type_finder, underlying_type, Some(member_name), None).1;
+                let pointer_type = try_extract_type_string(type_finder, underlying_type, Some(member_name), None)?.1;
                 final_pointer.push_str(&pointer_type[..]);
             } else {
                 let _member_name = String::from("*");
-                let pointer_type = extract_type_string(type_finder, underlying_type, None, None).1;
+                let pointer_type = try_extract_type_string(type_finder, underlying_type, None, None)?.1;
                 let pointer_type = format!("{}*", pointer_type);
                 final_pointer.push_str(&pointer_type[..]);
             }
@@ -254,12 +316,11 @@ fn extract_type_string(type_finder: &TypeFinder, field_type: pdb::TypeIndex,
             }
 
             if let Some(member_name) = member_name {
-                let modifier_type = extract_type_string(type_finder, underlying_type, Some(member_name), None).1;
+                let modifier_type = try_extract_type_string(type_finder, underlying_type, Some(member_name), None)?.1;
                 final_modified.push_str(&modifier_type[..]);
             } else {
                 let _member_name = String::from("<unknown>");
-                let modifier_type = extract_type_string(type_finder, underlying_type, None, None).1;
-                let modifier_type = format!("{}", modifier_type);//, member_name);
+                let modifier_type = try_extract_type_string(type_finder, underlying_type, None, None)?.1;
                 final_modified.push_str(&modifier_type[..]);
             }
 
@@ -271,19 +332,18 @@ fn extract_type_string(type_finder: &TypeFinder, field_type: pdb::TypeIndex,
 
             // Acquire return type and insert into procedure type
             if let Some(return_type) = return_type {
-                let return_type = extract_type_string(type_finder, return_type, None, None).1;
+                let return_type = try_extract_type_string(type_finder, return_type, None, None)?.1;
                 final_procedure.push_str(&return_type[..]);
-                final_procedure.push_str(" ");
+                final_procedure.push(' ');
             }
 
             // Acquire arguments list and variable name and insert to procedure type
             if let Some(member_name) = member_name {
-                let procedure_type = extract_type_string(type_finder, argument_list, Some(member_name.clone()), None).1;
+                let procedure_type = try_extract_type_string(type_finder, argument_list, Some(member_name.clone()), None)?.1;
                 let procedure_type = format!("{} {}", member_name, procedure_type);
                 final_procedure.push_str(&procedure_type[..]);
             } else {
-                let procedure_type = extract_type_string(type_finder, argument_list, None, None).1;
-                let procedure_type = format!("{}", procedure_type);
+                let procedure_type = try_extract_type_string(type_finder, argument_list, None, None)?.1;
                 final_procedure.push_str(&procedure_type[..]);
             }
 
@@ -292,25 +352,20 @@ fn extract_type_string(type_finder: &TypeFinder, field_type: pdb::TypeIndex,
         },
         pdb::TypeData::ArgumentList(pdb::ArgumentList {arguments}) => {
             let mut final_argument_string = String::from("(");
-            let vec_len_index = arguments.len() - 1;
-            let mut count = 0;
-
-            for type_index in arguments.into_iter() {
+            let vec_len_index = arguments.len().saturating_sub(1);
+            for (count, type_index) in arguments.into_iter().enumerate() {
                 // Acquire argument type
-                let mut type_var = extract_type_string(type_finder, type_index, None, None).1;
+                let mut type_var = try_extract_type_string(type_finder, type_index, None, None)?.1;
 
                 // Check if last argument
-                if count == vec_len_index {
-                    type_var = format!("{}", type_var);
-                } else {
+                if count != vec_len_index {
                     type_var = format!("{}, ", type_var);
                 }
                 
                 final_argument_string.push_str(&type_var[..]);
-                count += 1;
             }
 
-            final_argument_string.push_str(")");
+            final_argument_string.push(')');
             (crate::TypeData::ArgumentList,
                 final_argument_string)
         }
@@ -319,7 +374,7 @@ fn extract_type_string(type_finder: &TypeFinder, field_type: pdb::TypeIndex,
                 String::from("** CANNOT FIND TYPE, PLEASE SUBMIT ISSUE ON GITHUB **"))
         },
     };
-    type_str
+    Some(type_str)
 }
 
 /*
@@ -331,60 +386,56 @@ Loops over type and inserts type data into dict
 @current_dict => Inserts data into dict in order to incorporate it into python.
 
 */
-fn loop_over_fields(_: &Python, type_finder: &TypeFinder, fields: TypeIndex, current_dict: &PyDict) -> () {
-    match type_finder.find(fields)
+fn loop_over_fields(_: &Python, type_finder: &TypeFinder, fields: TypeIndex, current_dict: &PyDict) {
+    if let pdb::TypeData::FieldList(list) = type_finder.find(fields)
     .expect("Could not find fields")
     .parse()
     .expect("Could not parse field") {
-        pdb::TypeData::FieldList(list) => {
-            for field in list.fields {
-                if let pdb::TypeData::Member(member) = field {
+        for field in list.fields {
+            if let pdb::TypeData::Member(member) = field {
+
+                // Acquire current variable name
+                let current_name = member.name.to_string().into_owned();
+                let current_name = &current_name[..];
+
+                // Acquire current variable type
+                let (_, type_str) = extract_type_string(type_finder, member.field_type, 
+                                                    Some(current_name.to_string()), None);
+                
+                // Check if current field is a bitfield (=union but not really)
+                if let Some(type_cast) = current_dict.get_item(member.offset) {
+                    let prev_type_string = type_cast.downcast::<PyString>().expect("Bad downcast")
+                                                    .to_string().expect("Could not acquire pystring").into_owned();
 
-                    // Acquire current variable name
-                    let current_name = member.name.to_string().into_owned();
-                    let current_name = &current_name[..];
+                    let (type_data, type_str) = extract_type_string(type_finder, member.field_type, Some(current_name.to_string()),
+                                                    Some(&prev_type_string[..(prev_type_string.len() - 1)]));
+                    
+                    let prev_string_length = prev_type_string.len();
 
-                    // Acquire current variable type
-                    let (_, type_str) = extract_type_string(&type_finder, member.field_type, 
-                                                        Some(current_name.to_string()), None);
+                    let result_string;
+                    let last_character = prev_type_string.chars().last().unwrap();
                     
-                    // Check if current field is a bitfield (=union but not really)
-                    if let Some(type_cast) = current_dict.get_item(member.offset) {
-                        let prev_type_string = type_cast.downcast::<PyString>().expect("Bad downcast")
-                                                        .to_string().expect("Could not acquire pystring").into_owned();
-
-                        let (type_data, type_str) = extract_type_string(&type_finder, member.field_type, Some(current_name.to_string()),
-                                                        Some(&prev_type_string[..(prev_type_string.len() - 1)]));
-                        
-                        let prev_string_length = prev_type_string.len();
-
-                        let result_string;
-                        let last_character = prev_type_string.chars().last().unwrap();
-                        
-                        if last_character == '}' {
-                            if type_data != crate::TypeData::Bitfield {
-                                let prev_type_string = (&prev_type_string[..(prev_string_length - 2)]).to_string();
-                                result_string = format!("{}\n{};\n}}", prev_type_string, type_str);
-                            } else {
-                                result_string = format!("{};\n}}", type_str);
-                            }
+                    if last_character == '}' {
+                        if type_data != crate::TypeData::Bitfield {
+                            let prev_type_string = prev_type_string[..(prev_string_length - 2)].to_string();
+                            result_string = format!("{}\n{};\n}}", prev_type_string, type_str);
                         } else {
-                            result_string = format!("union {{\n{}\n{};\n}}", 
-                                                    prev_type_string, type_str);
+                            result_string = format!("{};\n}}", type_str);
                         }
-
-                        current_dict.set_item(member.offset, result_string).handle_properly();
-                        
                     } else {
-
-                        current_dict.set_item(member.offset, type_str).handle_properly();
+                        result_string = format!("union {{\n{}\n{};\n}}", 
+                                                prev_type_string, type_str);
                     }
+
+                    current_dict.set_item(member.offset, result_string).handle_properly();
+                    
+                } else {
+
+                    current_dict.set_item(member.offset, type_str).handle_properly();
                 }
             }
-        },
-        _ => { }
+        }
     }
-    ()
 }
 
 fn insert_length_into_dict(_: Python, dict: &PyDict, desired_type: String, pdb_name: String) -> Result<(), PyErr> {
@@ -444,6 +495,9 @@ fn insert_fields_into_dict(py: Python, dict: &PyDict, desired_type: String, pdb_
     
     let mut pdb = open_pdb_and_parse(&pdb_name[..])?;
 
+    // Header / line each struct was defined at (when the PDB has it)
+    let udt_sources = udt_sources::collect_udt_sources(&mut pdb);
+
     // Acquire type information from the pdb file
     let type_information = match pdb.type_information() {
         Ok(_type) => _type,
@@ -487,6 +541,9 @@ fn insert_fields_into_dict(py: Python, dict: &PyDict, desired_type: String, pdb_
 
                 current_dict.set_item("size", size).handle_properly();
                 current_dict.set_item("struct", struct_dict).handle_properly();
+                if let Some(source) = udt_sources.get(&typ.index()) {
+                    current_dict.set_item("source", source).handle_properly();
+                }
                 // Add dictionary to all dicts
                 dict.set_item(name.to_string(), current_dict).handle_properly();
                 
@@ -502,6 +559,42 @@ fn insert_fields_into_dict(py: Python, dict: &PyDict, desired_type: String, pdb_
     Ok(())
 }
 
+/*
+Converts a member of a struct diff (or offset history) into the dict handed to python.
+*/
+fn diff_member_to_dict<'p>(py: Python<'p>, member: &struct_diff::Member) -> &'p PyDict {
+    let dict = PyDict::new(py);
+    dict.set_item("path", &member.path).handle_properly();
+    dict.set_item("offset", member.offset).handle_properly();
+    dict.set_item("size", member.size).handle_properly();
+    dict.set_item("type", &member.type_name).handle_properly();
+    dict.set_item("bit_position", member.bitfield.map(|bitfield| bitfield.position)).handle_properly();
+    dict.set_item("bit_length", member.bitfield.map(|bitfield| bitfield.length)).handle_properly();
+    dict
+}
+
+fn diff_version_to_dict<'p>(py: Python<'p>, version: &struct_diff::StructVersion) -> &'p PyDict {
+    let dict = PyDict::new(py);
+    dict.set_item("pdb", &version.pdb_name).handle_properly();
+    dict.set_item("guid", &version.guid).handle_properly();
+    dict.set_item("age", version.age).handle_properly();
+    dict.set_item("size", version.size).handle_properly();
+    dict
+}
+
+/*
+Lists the PDBs an offset history reads and collects it.
+*/
+fn load_offset_history(name: &str, pdb_paths: &PyAny, labels: Option<HashMap<String, String>>, recursive: bool) -> PyResult<offset_history::OffsetHistory> {
+    let pdb_paths: Vec<String> = match pdb_paths.extract::<String>() {
+        Ok(path) => vec![path],
+        Err(_) => pdb_paths.extract()?
+    };
+    let pdb_paths: Vec<PathBuf> = pdb_paths.iter().map(PathBuf::from).collect();
+    let pdbs = offset_history::list_pdbs(&pdb_paths, recursive)?;
+    offset_history::collect(name, &pdbs, &labels.unwrap_or_default())
+}
+
 #[pymodule]
 fn pymspdb(_py: Python, m: &PyModule) -> PyResult<()> {
     // PyO3 aware function. All of our Python interfaces could be declared in a separate module.
@@ -530,5 +623,161 @@ fn pymspdb(_py: Python, m: &PyModule) -> PyResult<()> {
 
         Ok(existing_dict)
     }
+
+    #[pyfn(m, "fetch_pdb", symbol_path = "None", offline = "false")]
+    fn fetch_pdb_py(_py: Python, pdb_name: String, guid: String, age: u32, symbol_path: Option<String>, offline: bool) -> PyResult<Option<String>> {
+        let path = symsrv::fetch_pdb(&pdb_name, &guid, age, symbol_path.as_deref(), offline)?;
+
+        Ok(path.map(|path| path.to_string_lossy().into_owned()))
+    }
+
+    #[pyfn(m, "fetch_pdb_for_image", symbol_path = "None", offline = "false")]
+    fn fetch_pdb_for_image_py(_py: Python, image_path: String, symbol_path: Option<String>, offline: bool) -> PyResult<Option<String>> {
+        let path = symsrv::fetch_pdb_for_image(Path::new(&image_path), symbol_path.as_deref(), offline)?;
+
+        Ok(path.map(|path| path.to_string_lossy().into_owned()))
+    }
+
+    #[pyfn(m, "write_symbol_manifest", recursive = "true")]
+    fn write_symbol_manifest_py<'p>(py: Python<'p>, image_paths: &PyAny, manifest_path: String, recursive: bool) -> PyResult<&'p PyDict> {
+        let image_paths: Vec<String> = match image_paths.extract::<String>() {
+            Ok(path) => vec![path],
+            Err(_) => image_paths.extract()?
+        };
+        let image_paths: Vec<PathBuf> = image_paths.iter().map(PathBuf::from).collect();
+        let report = manifest::scan_images(&image_paths, recursive)?;
+        manifest::write_manifest(Path::new(&manifest_path), &report.entries)?;
+
+        let skipped = PyDict::new(py);
+        for (image, reason) in report.skipped {
+            skipped.set_item(image.to_string_lossy().into_owned(), reason).handle_properly();
+        }
+
+        let result = PyDict::new(py);
+        result.set_item("entries", report.entries.len()).handle_properly();
+        result.set_item("skipped", skipped).handle_properly();
+        Ok(result)
+    }
+
+    #[pyfn(m, "ingest_symbols", symbol_store = "String::from(symsrv::DEFAULT_CACHE)")]
+    fn ingest_symbols_py<'p>(py: Python<'p>, manifest_path: String, pdb_directory: String, symbol_store: String) -> PyResult<&'p PyDict> {
+        let entries = manifest::read_manifest(Path::new(&manifest_path))?;
+        let report = manifest::ingest(&entries, Path::new(&pdb_directory), Path::new(&symbol_store))?;
+
+        let to_strings = |paths: &[PathBuf]| -> Vec<String> {
+            paths.iter().map(|path| path.to_string_lossy().into_owned()).collect()
+        };
+        let missing = PyList::empty(py);
+        for entry in report.missing {
+            missing.append((entry.pdb_name, entry.key)).handle_properly();
+        }
+
+        let result = PyDict::new(py);
+        result.set_item("added", to_strings(&report.added)).handle_properly();
+        result.set_item("unexpected", to_strings(&report.unexpected)).handle_properly();
+        result.set_item("missing", missing).handle_properly();
+        Ok(result)
+    }
+
+    #[pyfn(m, "diff_structure")]
+    fn diff_structure_py<'p>(py: Python<'p>, name: String, old_pdb: String, new_pdb: String) -> PyResult<&'p PyDict> {
+        let diff = struct_diff::diff(&name, &old_pdb, &new_pdb)?;
+
+        let added = PyList::empty(py);
+        for member in &diff.added {
+            added.append(diff_member_to_dict(py, member))?;
+        }
+        let removed = PyList::empty(py);
+        for member in &diff.removed {
+            removed.append(diff_member_to_dict(py, member))?;
+        }
+        let changed = PyList::empty(py);
+        for change in &diff.changed {
+            let dict = PyDict::new(py);
+            dict.set_item("path", &change.new.path).handle_properly();
+            dict.set_item("changes", &change.changes).handle_properly();
+            dict.set_item("old", diff_member_to_dict(py, &change.old)).handle_properly();
+            dict.set_item("new", diff_member_to_dict(py, &change.new)).handle_properly();
+            changed.append(dict)?;
+        }
+
+        let result = PyDict::new(py);
+        result.set_item("name", &diff.name).handle_properly();
+        result.set_item("old", diff_version_to_dict(py, &diff.old)).handle_properly();
+        result.set_item("new", diff_version_to_dict(py, &diff.new)).handle_properly();
+        result.set_item("size_changed", diff.old.size != diff.new.size).handle_properly();
+        result.set_item("identical", diff.is_empty()).handle_properly();
+        result.set_item("added", added).handle_properly();
+        result.set_item("removed", removed).handle_properly();
+        result.set_item("changed", changed).handle_properly();
+        Ok(result)
+    }
+
+    #[pyfn(m, "diff_structure_text", context = "3")]
+    fn diff_structure_text_py(_py: Python, name: String, old_pdb: String, new_pdb: String, context: usize) -> PyResult<String> {
+        Ok(struct_diff::diff(&name, &old_pdb, &new_pdb)?.to_text(context))
+    }
+
+    #[pyfn(m, "offset_history", labels = "None", recursive = "true")]
+    fn offset_history_py<'p>(py: Python<'p>, name: String, pdb_paths: &PyAny, labels: Option<HashMap<String, String>>, recursive: bool) -> PyResult<&'p PyDict> {
+        let history = load_offset_history(&name, pdb_paths, labels, recursive)?;
+
+        let builds = PyList::empty(py);
+        for build in &history.builds {
+            let dict = PyDict::new(py);
+            dict.set_item("pdb", &build.pdb_name).handle_properly();
+            dict.set_item("label", &build.label).handle_properly();
+            dict.set_item("size", build.size).handle_properly();
+            builds.append(dict)?;
+        }
+        let rows = PyList::empty(py);
+        for row in &history.rows {
+            let cells = PyList::empty(py);
+            for cell in &row.cells {
+                match cell {
+                    Some(member) => cells.append(diff_member_to_dict(py, member))?,
+                    None => cells.append(py.None())?
+                }
+            }
+            let dict = PyDict::new(py);
+            dict.set_item("path", &row.path).handle_properly();
+            dict.set_item("cells", cells).handle_properly();
+            rows.append(dict)?;
+        }
+
+        let result = PyDict::new(py);
+        result.set_item("name", &history.name).handle_properly();
+        result.set_item("builds", builds).handle_properly();
+        result.set_item("rows", rows).handle_properly();
+        Ok(result)
+    }
+
+    #[pyfn(m, "offset_history_table", format = "\"csv\"", labels = "None", recursive = "true")]
+    fn offset_history_table_py(_py: Python, name: String, pdb_paths: &PyAny, format: &str, labels: Option<HashMap<String, String>>, recursive: bool) -> PyResult<String> {
+        let format = offset_history::Format::parse(format)
+                                             .ok_or_else(|| ValueError::py_err(format!("[pymspdb] Unknown format {} (csv, markdown, html)", format)))?;
+        let history = load_offset_history(&name, pdb_paths, labels, recursive)?;
+        Ok(offset_history::to_table(&history, format))
+    }
+
+    #[pyfn(m, "offset_tables", language = "\"c\"", name = "\"kernel_offsets\"")]
+    fn offset_tables_py(_py: Python, fields: &PyAny, builds: HashMap<String, u32>, language: &str, name: &str) -> PyResult<String> {
+        let fields: Vec<String> = match fields.extract::<String>() {
+            Ok(field) => vec![field],
+            Err(_) => fields.extract()?
+        };
+        let language = offset_constants::Language::parse(language)
+                                                   .ok_or_else(|| ValueError::py_err(format!("[pymspdb] Unknown language {} (c, cpp, rust)", language)))?;
+        let pdbs: Vec<(String, u32)> = builds.into_iter().collect();
+        let table = offset_tables::collect(&fields, &pdbs)?;
+        offset_tables::generate(&table, language, name)
+    }
+
+    #[pyfn(m, "demangle")]
+    fn demangle_py(_py: Python, name: String) -> PyResult<Option<String>> {
+        Ok(demangle::demangle(&name).map(|demangled| demangled.full))
+    }
+
+    m.add_class::<session::Session>()?;
     Ok(())
 }
\ No newline at end of file
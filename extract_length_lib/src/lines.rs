@@ -0,0 +1,344 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use fallible_iterator::FallibleIterator;
+use pdb::{FileIndex, IdFinder, IdIndex, PdbInternalSectionOffset};
+use pyo3::exceptions::*;
+use pyo3::{PyErr, PyResult};
+
+/*
+ *
+ * SOURCE LINES
+ *
+ * Line information lives in the C13 subsections of every DBI module stream: the line program
+ * maps code ranges of the module's functions to file:line, and inline sites (S_INLINESITE)
+ * carry binary annotations which, combined with the module's inlinee lines, map the code
+ * that was inlined into a function back to the inlined function's source.
+ *
+ */
+
+#[derive(Debug, Clone)]
+pub struct LineRecord {
+    pub rva: u32,
+    pub length: Option<u32>,
+    pub file: String,
+    pub line_start: u32,
+    pub line_end: u32,
+    pub column_start: Option<u32>,
+    pub column_end: Option<u32>,
+    // Function the code was inlined from, None for the function's own lines
+    pub inlinee: Option<String>,
+    // Inline nesting level (0 for the function's own lines)
+    pub depth: u32,
+}
+
+impl LineRecord {
+    fn contains(&self, rva: u32) -> bool {
+        rva >= self.rva && rva - self.rva < self.length.unwrap_or(1).max(1)
+    }
+}
+
+fn pdb_error(e: pdb::Error) -> PyErr {
+    Exception::py_err(e.to_string())
+}
+
+/*
+Compares source paths loosely: case insensitive, either slash, and "foo.c" matches "c:\src\foo.c".
+*/
+fn file_matches(path: &str, wanted: &str) -> bool {
+    let normalize = |path: &str| path.replace('\\', "/").to_lowercase();
+    let path = normalize(path);
+    let wanted = normalize(wanted);
+
+    path == wanted || path.ends_with(&format!("/{}", wanted.trim_start_matches('/')))
+}
+
+/*
+Name of an inlined function, from its IPI function id.
+*/
+fn inlinee_name(id_finder: &IdFinder, inlinee: IdIndex) -> String {
+    let name = id_finder.find(inlinee).and_then(|id| id.parse()).ok().and_then(|id| match id {
+        pdb::IdData::Function(function) => Some(function.name.to_string().into_owned()),
+        pdb::IdData::MemberFunction(function) => Some(function.name.to_string().into_owned()),
+        _ => None
+    });
+    name.unwrap_or_else(|| format!("<inlinee {:#x}>", inlinee.0))
+}
+
+struct ModuleLines<'a, 's> {
+    address_map: &'a pdb::AddressMap<'s>,
+    string_table: Option<&'a pdb::StringTable<'s>>,
+    program: pdb::LineProgram<'a>,
+    // File names are shared by most of a module's lines
+    files: HashMap<FileIndex, String>,
+}
+
+impl<'a, 's> ModuleLines<'a, 's> {
+    fn file_name(&mut self, file_index: FileIndex) -> String {
+        if let Some(name) = self.files.get(&file_index) {
+            return name.clone();
+        }
+
+        let name = match (self.program.get_file_info(file_index), self.string_table) {
+            (Ok(file_info), Some(string_table)) => file_info.name.to_string_lossy(string_table)
+                                                                .map(|name| name.into_owned())
+                                                                .unwrap_or_else(|_| String::from("<unknown>")),
+            _ => String::from("<unknown>")
+        };
+        self.files.insert(file_index, name.clone());
+        name
+    }
+
+    fn record(&mut self, line: pdb::LineInfo, inlinee: Option<String>, depth: u32) -> Option<LineRecord> {
+        // Inlinee line ranges are end exclusive, the line program's ones aren't
+        let line_end = match inlinee {
+            Some(_) => line.line_end.saturating_sub(1).max(line.line_start),
+            None => line.line_end
+        };
+
+        Some(LineRecord {
+            rva: line.offset.to_rva(self.address_map)?.0,
+            length: line.length,
+            file: self.file_name(line.file_index),
+            line_start: line.line_start,
+            line_end,
+            column_start: line.column_start,
+            column_end: line.column_end,
+            inlinee,
+            depth,
+        })
+    }
+}
+
+/*
+Fills in the length of line records, which the line program itself doesn't store: a line ends
+where the next one starts, or at the end of its function.
+
+@records    => The line program records of a single module.
+@procedures => Start and end RVAs of the module's functions.
+*/
+fn infer_lengths(records: &mut [LineRecord], procedures: &[(u32, u32)]) {
+    records.sort_by_key(|record| record.rva);
+    let mut procedures = procedures.to_vec();
+    procedures.sort_unstable();
+
+    // Walking backwards, the start of the closest record after the current RVA is always known
+    let mut next_start = None;
+    let mut current_start = None;
+    for record in records.iter_mut().rev() {
+        if current_start != Some(record.rva) {
+            next_start = current_start;
+            current_start = Some(record.rva);
+        }
+        if record.length.is_some() {
+            continue;
+        }
+
+        let rva = record.rva;
+        let procedure_end = procedures.partition_point(|&(start, _)| start <= rva)
+                                      .checked_sub(1)
+                                      .map(|index| procedures[index])
+                                      .filter(|&(_, end)| rva < end)
+                                      .map(|(_, end)| end);
+
+        let end = match (next_start, procedure_end) {
+            (Some(next_start), Some(procedure_end)) => Some(next_start.min(procedure_end)),
+            (next_start, procedure_end) => next_start.or(procedure_end)
+        };
+        record.length = end.map(|end| end - rva);
+    }
+}
+
+/*
+Collects every line record of a PDB file, including the lines of inlined code.
+
+@pdb_name => Path to the PDB file.
+
+RETURN
+returns the line records, sorted by RVA.
+*/
+fn collect_lines(pdb_name: &str) -> PyResult<Vec<LineRecord>> {
+    let mut pdb = crate::open_pdb_and_parse(pdb_name)?;
+
+    let id_information = pdb.id_information().map_err(pdb_error)?;
+    let id_finder = crate::fill_item_finder(&id_information)?;
+    let address_map = pdb.address_map().map_err(pdb_error)?;
+    // PDBs without any source file names have no string table at all
+    let string_table = pdb.string_table().ok();
+
+    let mut records = Vec::new();
+    let debug_information = pdb.debug_information().map_err(pdb_error)?;
+    let mut modules = debug_information.modules().map_err(pdb_error)?;
+    while let Some(module) = modules.next().map_err(pdb_error)? {
+        let module_info = match pdb.module_info(&module).map_err(pdb_error)? {
+            Some(module_info) => module_info,
+            None => continue
+        };
+
+        let mut module_lines = ModuleLines {
+            address_map: &address_map,
+            string_table: string_table.as_ref(),
+            program: module_info.line_program().map_err(pdb_error)?,
+            files: HashMap::new(),
+        };
+
+        let mut own_records = Vec::new();
+        let mut lines = module_lines.program.lines();
+        while let Some(line) = lines.next().map_err(pdb_error)? {
+            own_records.extend(module_lines.record(line, None, 0));
+        }
+
+        let inlinees: HashMap<IdIndex, pdb::Inlinee> = module_info.inlinees().map_err(pdb_error)?
+                                                                  .map(|inlinee| Ok((inlinee.index(), inlinee)))
+                                                                  .collect()
+                                                                  .map_err(pdb_error)?;
+
+        // Inline site code offsets are relative to the procedure they were inlined into
+        let mut procedure_offset: Option<PdbInternalSectionOffset> = None;
+        let mut procedures = Vec::new();
+        let mut inline_ends: Vec<pdb::SymbolIndex> = Vec::new();
+        let mut symbols = module_info.symbols().map_err(pdb_error)?;
+        while let Some(symbol) = symbols.next().map_err(pdb_error)? {
+            while inline_ends.last().is_some_and(|&end| end <= symbol.index()) {
+                inline_ends.pop();
+            }
+
+            match symbol.parse() {
+                Ok(pdb::SymbolData::Procedure(procedure)) => {
+                    procedure_offset = Some(procedure.offset);
+                    inline_ends.clear();
+
+                    if let Some(rva) = procedure.offset.to_rva(&address_map) {
+                        // A corrupt PDB may have a length running past the address space
+                        procedures.push((rva.0, rva.0.saturating_add(procedure.len)));
+                    }
+                },
+                Ok(pdb::SymbolData::InlineSite(site)) => {
+                    inline_ends.push(site.end);
+                    let depth = inline_ends.len() as u32;
+
+                    let (parent_offset, inlinee) = match (procedure_offset, inlinees.get(&site.inlinee)) {
+                        (Some(parent_offset), Some(inlinee)) => (parent_offset, inlinee),
+                        _ => continue
+                    };
+                    let name = inlinee_name(&id_finder, site.inlinee);
+
+                    let mut inline_lines = inlinee.lines(parent_offset, &site);
+                    while let Some(line) = inline_lines.next().map_err(pdb_error)? {
+                        records.extend(module_lines.record(line, Some(name.clone()), depth));
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        infer_lengths(&mut own_records, &procedures);
+        records.append(&mut own_records);
+    }
+
+    records.sort_by_key(|record| (record.rva, record.depth));
+    Ok(records)
+}
+
+pub struct LineTable {
+    // Sorted by RVA
+    records: Vec<LineRecord>,
+    // Longest record, bounds how far back a lookup has to look
+    longest: u32,
+}
+
+impl LineTable {
+    pub fn build(pdb_name: &str) -> PyResult<LineTable> {
+        let records = collect_lines(pdb_name)?;
+        let longest = records.iter().map(|record| record.length.unwrap_or(1)).max().unwrap_or(0);
+
+        Ok(LineTable {
+            records,
+            longest,
+        })
+    }
+
+    /*
+    Finds the lines an RVA belongs to.
+
+    @rva => Address relative to the image base.
+
+    RETURN
+    returns the matching records innermost first: the deepest inlined function, up to the lines
+    of the function that was actually compiled.
+    */
+    pub fn lines_at(&self, rva: u32) -> Vec<&LineRecord> {
+        let end = self.records.partition_point(|record| record.rva <= rva);
+
+        let mut found: Vec<&LineRecord> = self.records[..end].iter().rev()
+                                                             .take_while(|record| rva - record.rva < self.longest.max(1))
+                                                             .filter(|record| record.contains(rva))
+                                                             .collect();
+
+        // Only the closest record of each nesting level
+        found.sort_by_key(|record| (Reverse(record.depth), Reverse(record.rva)));
+        found.dedup_by_key(|record| record.depth);
+        found
+    }
+
+    /*
+    Finds the code generated for a source line.
+
+    @file => Source file, either the full path or only its trailing part (e.g "driver.c").
+    @line => Line number.
+
+    RETURN
+    returns every record whose line range covers line, sorted by RVA.
+    */
+    pub fn ranges_for_line(&self, file: &str, line: u32) -> Vec<&LineRecord> {
+        self.records.iter()
+                    .filter(|record| line >= record.line_start && line <= record.line_end.max(record.line_start))
+                    .filter(|record| file_matches(&record.file, file))
+                    .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fixture;
+
+    fn record(rva: u32, length: Option<u32>) -> LineRecord {
+        LineRecord {
+            rva,
+            length,
+            file: String::from("driver.c"),
+            line_start: 1,
+            line_end: 1,
+            column_start: None,
+            column_end: None,
+            inlinee: None,
+            depth: 0,
+        }
+    }
+
+    #[test]
+    fn lines_end_at_the_next_line_or_their_function() {
+        let mut records = vec![record(0x1010, None), record(0x1000, None), record(0x1000, None), record(0x1030, Some(2)), record(0x1038, None), record(0x2000, None)];
+        infer_lengths(&mut records, &[(0x1030, 0x1040), (0x1000, 0x1020)]);
+
+        let lengths: Vec<(u32, Option<u32>)> = records.iter().map(|record| (record.rva, record.length)).collect();
+        assert_eq!(lengths, vec![(0x1000, Some(0x10)), (0x1000, Some(0x10)), (0x1010, Some(0x10)), (0x1030, Some(2)), (0x1038, Some(8)), (0x2000, None)]);
+    }
+
+    #[test]
+    fn finds_the_lines_of_an_rva() {
+        let table = LineTable::build(&fixture("symbols.pdb")).unwrap();
+
+        let found = table.lines_at(0x1004);
+        assert_eq!(found.len(), 1);
+        assert!(file_matches(&found[0].file, "symbols.rs"));
+        assert_eq!((found[0].rva, found[0].length, found[0].line_start), (0x1000, Some(0xe), 22));
+        assert_eq!(table.lines_at(0x1010)[0].line_start, 25);
+        assert!(table.lines_at(0x1018).is_empty());
+
+        let ranges: Vec<u32> = table.ranges_for_line("SYMBOLS.RS", 27).iter().map(|record| record.rva).collect();
+        assert_eq!(ranges, vec![0x1020]);
+        assert!(file_matches("c:\\src\\driver.c", "src/driver.c"));
+        assert!(!file_matches("c:\\src\\mydriver.c", "driver.c"));
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use pyo3::exceptions::*;
+use pyo3::PyResult;
+use crate::pe;
+use crate::symstore;
+
+/*
+ *
+ * OFFLINE SYMBOL MANIFESTS (symchk /om, /im)
+ *
+ * A manifest holds one line per needed PDB: <name>,<index key>,1
+ * It's written on the machine that has the images, the PDBs are fetched somewhere else
+ * (e.g symchk /im manifest.txt /s srv*c:\symbols*https://msdl.microsoft.com/download/symbols)
+ * and are then ingested back into the local store.
+ *
+ */
+
+const IMAGE_EXTENSIONS: &[&str] = &["exe", "dll", "sys", "drv", "ocx", "cpl", "scr", "efi", "acm", "ax", "tsp"];
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ManifestEntry {
+    pub pdb_name: String,
+    pub key: String,
+}
+
+impl ManifestEntry {
+    fn matches(&self, pdb_name: &str, key: &str) -> bool {
+        self.pdb_name.eq_ignore_ascii_case(pdb_name) && self.key.eq_ignore_ascii_case(key)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    pub entries: BTreeSet<ManifestEntry>,
+    // Images that couldn't be parsed or have no CodeView record, with the reason
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+#[derive(Debug, Default)]
+pub struct IngestReport {
+    pub added: Vec<PathBuf>,
+    // PDBs that aren't in the manifest (wrong version, or not needed at all)
+    pub unexpected: Vec<PathBuf>,
+    // Manifest entries that are still not in the store
+    pub missing: Vec<ManifestEntry>,
+}
+
+/*
+Lists the files under a path.
+
+@path      => File or directory.
+@recursive => Also list files in sub directories.
+@filter    => Only files for which this returns true are listed.
+
+RETURN
+returns the matching files, sorted.
+*/
+pub fn list_files(path: &Path, recursive: bool, filter: &dyn Fn(&Path) -> bool) -> PyResult<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    let mut directories = vec![path.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        let entries = fs::read_dir(&directory).map_err(|e| IOError::py_err(format!("{}: {}", directory.display(), e)))?;
+
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            if entry_path.is_dir() {
+                if recursive {
+                    directories.push(entry_path);
+                }
+            } else if filter(&entry_path) {
+                files.push(entry_path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    match path.extension() {
+        Some(extension) => extensions.iter().any(|e| extension.to_string_lossy().eq_ignore_ascii_case(e)),
+        None => false
+    }
+}
+
+/*
+Scans images and collects the PDB each one needs.
+
+@paths     => Image files and / or directories of images (e.g a copy of System32).
+@recursive => Scan sub directories too.
+
+RETURN
+returns the manifest entries and the images that were skipped.
+*/
+pub fn scan_images(paths: &[PathBuf], recursive: bool) -> PyResult<ScanReport> {
+    let mut report = ScanReport::default();
+
+    for path in paths {
+        for image in list_files(path, recursive, &|file| has_extension(file, IMAGE_EXTENSIONS))? {
+            match pe::read_pdb_identity(&image) {
+                Ok(identity) => {
+                    // Recorded case, stores are looked up with the name the image has (KernelBase.pdb)
+                    report.entries.insert(ManifestEntry {
+                        pdb_name: identity.file_name().to_string(),
+                        key: identity.index_key(),
+                    });
+                },
+                Err(e) => report.skipped.push((image, e))
+            }
+        }
+    }
+    Ok(report)
+}
+
+/*
+Writes a symchk compatible manifest.
+*/
+pub fn write_manifest(manifest_path: &Path, entries: &BTreeSet<ManifestEntry>) -> PyResult<()> {
+    let mut manifest = String::new();
+    for entry in entries {
+        manifest.push_str(&format!("{},{},1\r\n", entry.pdb_name, entry.key));
+    }
+
+    fs::write(manifest_path, manifest).map_err(|e| IOError::py_err(format!("{}: {}", manifest_path.display(), e)))
+}
+
+/*
+Reads a symchk manifest (either ours or one written by symchk /om).
+*/
+pub fn read_manifest(manifest_path: &Path) -> PyResult<Vec<ManifestEntry>> {
+    let manifest = fs::read_to_string(manifest_path).map_err(|e| IOError::py_err(format!("{}: {}", manifest_path.display(), e)))?;
+    let mut entries = Vec::new();
+
+    for (number, line) in manifest.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split(',').map(str::trim);
+        match (fields.next(), fields.next()) {
+            (Some(pdb_name), Some(key)) if !pdb_name.is_empty() && !key.is_empty() => {
+                entries.push(ManifestEntry {
+                    pdb_name: pdb_name.to_string(),
+                    key: key.to_ascii_uppercase(),
+                });
+            },
+            _ => {
+                return Err(ValueError::py_err(format!("[pymspdb] {}:{}: bad manifest line", manifest_path.display(), number + 1)));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/*
+Adds fetched PDBs into a local symbol store, only accepting the ones the manifest asks for.
+
+@manifest      => Manifest entries.
+@pdb_directory => Directory holding the fetched PDBs (flat, or a symbol store itself).
+@store         => Local symbol store to add them to.
+
+RETURN
+returns what was added, what didn't match the manifest and what is still missing.
+*/
+pub fn ingest(manifest: &[ManifestEntry], pdb_directory: &Path, store: &Path) -> PyResult<IngestReport> {
+    let mut report = IngestReport::default();
+
+    for pdb_path in list_files(pdb_directory, true, &|file| has_extension(file, &["pdb"]))? {
+        let pdb_name = pdb_path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let key = match symstore::pdb_index_key(&pdb_path) {
+            Ok(key) => key,
+            Err(_) => {
+                report.unexpected.push(pdb_path);
+                continue;
+            }
+        };
+
+        match manifest.iter().find(|entry| entry.matches(&pdb_name, &key)) {
+            Some(entry) => {
+                let contents = fs::read(&pdb_path).map_err(|e| IOError::py_err(e.to_string()))?;
+                report.added.push(symstore::add_file(store, &entry.pdb_name, &entry.key, &contents)?);
+            },
+            None => report.unexpected.push(pdb_path)
+        }
+    }
+
+    for entry in manifest {
+        if symstore::find_in_store(store, &entry.pdb_name, &entry.key)?.is_none() {
+            report.missing.push(entry.clone());
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fixture, scratch_directory};
+
+    #[test]
+    fn manifests_round_trip_through_ingestion() {
+        let scratch = scratch_directory("manifest");
+        let report = scan_images(&[PathBuf::from(fixture(""))], false).unwrap();
+        let key = symstore::pdb_index_key(Path::new(&fixture("symbols.pdb"))).unwrap();
+        assert_eq!(report.entries.iter().collect::<Vec<_>>(), vec![&ManifestEntry { pdb_name: String::from("symbols.pdb"), key: key.clone() }]);
+
+        let manifest_path = scratch.join("manifest.txt");
+        write_manifest(&manifest_path, &report.entries).unwrap();
+        assert_eq!(fs::read_to_string(&manifest_path).unwrap(), format!("symbols.pdb,{},1\r\n", key));
+
+        let mut manifest = read_manifest(&manifest_path).unwrap();
+        manifest.push(ManifestEntry { pdb_name: String::from("ntkrnlmp.pdb"), key: String::from("0123") });
+
+        let store = scratch.join("store");
+        let report = ingest(&manifest, Path::new(&fixture("")), &store).unwrap();
+        assert_eq!(report.added, vec![symstore::store_path(&store, "symbols.pdb", &key)]);
+        let mut unexpected = report.unexpected.clone();
+        unexpected.sort();
+        assert_eq!(unexpected, vec![PathBuf::from(fixture("kernel.pdb")), PathBuf::from(fixture("kernel_new.pdb"))]);
+        assert_eq!(report.missing, vec![manifest[1].clone()]);
+    }
+
+    #[test]
+    fn keeps_the_case_of_pdb_names() {
+        let scratch = scratch_directory("manifest-case");
+        let images = scratch.join("images");
+        let fetched = scratch.join("fetched");
+        fs::create_dir_all(&images).unwrap();
+        fs::create_dir_all(&fetched).unwrap();
+
+        // The same image, built as Symbols.pdb
+        let mut image = fs::read(fixture("symbols.dll")).unwrap();
+        let at = image.windows(12).position(|window| window == b"/symbols.pdb").unwrap();
+        image[at + 1] = b'S';
+        fs::write(images.join("symbols.dll"), image).unwrap();
+        fs::copy(fixture("symbols.pdb"), fetched.join("symbols.pdb")).unwrap();
+
+        let report = scan_images(&[images], false).unwrap();
+        let entry = report.entries.iter().next().unwrap().clone();
+        assert_eq!(entry.pdb_name, "Symbols.pdb");
+
+        let store = scratch.join("store");
+        let report = ingest(std::slice::from_ref(&entry), &fetched, &store).unwrap();
+        assert_eq!(report.added, vec![symstore::store_path(&store, "Symbols.pdb", &entry.key)]);
+        assert!(report.missing.is_empty());
+        assert!(symstore::find_in_store(&store, "Symbols.pdb", &entry.key).unwrap().is_some());
+    }
+
+    #[test]
+    fn rejects_bad_manifest_lines() {
+        let scratch = scratch_directory("manifest-bad");
+        let manifest_path = scratch.join("manifest.txt");
+        fs::write(&manifest_path, "a.pdb,0123abc,1\n\nb.pdb\n").unwrap();
+
+        assert!(read_manifest(&manifest_path).is_err());
+        fs::write(&manifest_path, "a.pdb,0123abc,1\n").unwrap();
+        assert_eq!(read_manifest(&manifest_path).unwrap()[0].key, "0123ABC");
+    }
+}
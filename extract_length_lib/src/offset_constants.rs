@@ -0,0 +1,209 @@
+use crate::layout::{Field, TypeLayouts};
+
+/*
+ *
+ * OFFSET CONSTANTS
+ *
+ * Writes the offsets of a few fields as constants, for code that only needs a handful of them
+ * (_EPROCESS.ActiveProcessLinks, _EPROCESS.Token) instead of whole type definitions:
+ *      c       #define EPROCESS_TOKEN_OFFSET 0x4b8
+ *      cpp     constexpr unsigned int EPROCESS_TOKEN_OFFSET = 0x4b8;
+ *      rust    pub const EPROCESS_TOKEN_OFFSET: usize = 0x4b8;
+ *      python  EPROCESS_TOKEN_OFFSET = 0x4b8
+ *
+ * Every field gets _OFFSET (from the start of the outermost type) and _SIZE, bitfields also
+ * _BIT_POSITION and _BIT_LENGTH. Names are the path in upper snake case, without the leading
+ * underscores of the type. The file records the GUID / age of the PDB the numbers come from, as
+ * comments and as <PDB>_PDB_GUID / <PDB>_PDB_AGE.
+ *
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    C,
+    Cpp,
+    Rust,
+    Python,
+}
+
+impl Language {
+    pub fn parse(name: &str) -> Option<Language> {
+        match name.to_lowercase().as_str() {
+            "c" => Some(Language::C),
+            "cpp" | "c++" => Some(Language::Cpp),
+            "rust" | "rs" => Some(Language::Rust),
+            "python" | "py" => Some(Language::Python),
+            _ => None
+        }
+    }
+}
+
+/*
+Converts a name to upper snake case (ActiveProcessLinks => ACTIVE_PROCESS_LINKS, PEBLock => PEB_LOCK).
+*/
+pub fn constant_name(name: &str) -> String {
+    let chars: Vec<char> = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    let mut constant = String::new();
+    for (position, &c) in chars.iter().enumerate() {
+        if position > 0 && c.is_ascii_uppercase() {
+            let previous = chars[position - 1];
+            let next_lower = chars.get(position + 1).is_some_and(|next| next.is_ascii_lowercase());
+            if previous.is_ascii_lowercase() || previous.is_ascii_digit() || (previous.is_ascii_uppercase() && next_lower) {
+                constant.push('_');
+            }
+        }
+        constant.push(c.to_ascii_uppercase());
+    }
+
+    // Collapse the runs of _ the separators and the conversion left
+    let constant: Vec<&str> = constant.split('_').filter(|part| !part.is_empty()).collect();
+    let constant = constant.join("_");
+    match constant.chars().next() {
+        Some(c) if c.is_ascii_digit() => format!("_{}", constant),
+        _ => constant
+    }
+}
+
+struct ConstantWriter {
+    language: Language,
+    lines: Vec<String>,
+}
+
+impl ConstantWriter {
+    fn comment(&mut self, text: &str) {
+        let line = match self.language {
+            Language::C => format!("/* {} */", text),
+            Language::Cpp | Language::Rust => format!("// {}", text),
+            Language::Python => format!("# {}", text),
+        };
+        self.lines.push(line);
+    }
+
+    fn integer(&mut self, name: &str, value: u64, hex: bool) {
+        let value = match hex {
+            true => format!("{:#x}", value),
+            false => value.to_string()
+        };
+        let line = match self.language {
+            Language::C => format!("#define {} {}", name, value),
+            Language::Cpp => format!("constexpr unsigned int {} = {};", name, value),
+            Language::Rust => format!("pub const {}: usize = {};", name, value),
+            Language::Python => format!("{} = {}", name, value),
+        };
+        self.lines.push(line);
+    }
+
+    fn string(&mut self, name: &str, value: &str) {
+        let line = match self.language {
+            Language::C => format!("#define {} \"{}\"", name, value),
+            Language::Cpp => format!("constexpr const char {}[] = \"{}\";", name, value),
+            Language::Rust => format!("pub const {}: &str = \"{}\";", name, value),
+            Language::Python => format!("{} = \"{}\"", name, value),
+        };
+        self.lines.push(line);
+    }
+
+    fn field(&mut self, layouts: &TypeLayouts, field: &Field) {
+        let name = constant_name(&field.name);
+        let size = layouts.size_of(&field.typ);
+        match field.bitfield {
+            Some(bitfield) => self.comment(&format!("{}: offset {:#x}, {} bits from bit {} of a {} byte unit", field.name, field.offset,
+                                                    bitfield.length, bitfield.position, size)),
+            None => self.comment(&format!("{}: offset {:#x}, {} bytes", field.name, field.offset, size))
+        }
+
+        self.integer(&format!("{}_OFFSET", name), field.offset, true);
+        self.integer(&format!("{}_SIZE", name), size, true);
+        if let Some(bitfield) = field.bitfield {
+            self.integer(&format!("{}_BIT_POSITION", name), u64::from(bitfield.position), false);
+            self.integer(&format!("{}_BIT_LENGTH", name), u64::from(bitfield.length), false);
+        }
+    }
+}
+
+/*
+Generates the offset constants of fields.
+
+@layouts  => Type layouts of the PDB.
+@fields   => Resolved fields (layout::field_path), named by their path.
+@language => Language to write.
+@pdb_name => File name of the PDB (e.g ntkrnlmp.pdb).
+
+RETURN
+returns the source text.
+*/
+pub fn generate(layouts: &TypeLayouts, fields: &[Field], language: Language, pdb_name: &str) -> String {
+    let mut writer = ConstantWriter {
+        language,
+        lines: Vec::new(),
+    };
+
+    let source = format!("{} GUID {} age {}", pdb_name, layouts.guid(), layouts.age());
+    writer.comment(&format!("Field offsets generated by pymspdb from {}", source));
+    writer.comment("Only valid for binaries built with this PDB");
+    if let Language::C | Language::Cpp = language {
+        writer.lines.push(String::from("#pragma once"));
+    }
+    writer.lines.push(String::new());
+
+    let pdb_constant = constant_name(pdb_name.rsplit_once('.').map_or(pdb_name, |(stem, _)| stem));
+    writer.string(&format!("{}_PDB_GUID", pdb_constant), layouts.guid());
+    writer.integer(&format!("{}_PDB_AGE", pdb_constant), u64::from(layouts.age()), false);
+
+    for field in fields {
+        writer.lines.push(String::new());
+        writer.field(layouts, field);
+    }
+
+    writer.lines.push(String::new());
+    writer.lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::kernel_layouts;
+
+    fn constants(paths: &[&str], language: Language) -> String {
+        let layouts = kernel_layouts();
+        let fields: Vec<Field> = paths.iter().map(|path| layouts.field_path(path).unwrap()).collect();
+        generate(&layouts, &fields, language, "kernel.pdb")
+    }
+
+    #[test]
+    fn converts_names_to_upper_snake_case() {
+        assert_eq!(constant_name("ActiveProcessLinks"), "ACTIVE_PROCESS_LINKS");
+        assert_eq!(constant_name("PEBLock"), "PEB_LOCK");
+        assert_eq!(constant_name("_EPROCESS.Pcb.Header"), "EPROCESS_PCB_HEADER");
+        assert_eq!(constant_name("Cid[3]"), "CID_3");
+        assert_eq!(constant_name("64Bit"), "_64_BIT");
+    }
+
+    #[test]
+    fn parses_languages() {
+        assert_eq!(Language::parse("C++"), Some(Language::Cpp));
+        assert_eq!(Language::parse("rs"), Some(Language::Rust));
+        assert_eq!(Language::parse("go"), None);
+    }
+
+    #[test]
+    fn writes_offsets_and_bit_positions() {
+        let c = constants(&["_EPROCESS.ActiveProcessLinks.Blink", "_EPROCESS.Spare"], Language::C);
+        assert!(c.contains("#pragma once\n\n#define KERNEL_PDB_GUID \""));
+        assert!(c.contains("\n#define KERNEL_PDB_AGE 2\n"));
+        assert!(c.contains("\n/* _EPROCESS.ActiveProcessLinks.Blink: offset 0x40, 8 bytes */\n#define EPROCESS_ACTIVE_PROCESS_LINKS_BLINK_OFFSET 0x40\n"));
+        assert!(c.contains("\n#define EPROCESS_SPARE_OFFSET 0x88\n#define EPROCESS_SPARE_SIZE 0x4\n#define EPROCESS_SPARE_BIT_POSITION 1\n#define EPROCESS_SPARE_BIT_LENGTH 31\n"));
+    }
+
+    #[test]
+    fn writes_every_language() {
+        let paths = ["_EPROCESS.Token"];
+        assert!(constants(&paths, Language::Cpp).contains("constexpr unsigned int EPROCESS_TOKEN_OFFSET = 0x48;"));
+        assert!(constants(&paths, Language::Rust).contains("pub const EPROCESS_TOKEN_OFFSET: usize = 0x48;"));
+
+        let python = constants(&paths, Language::Python);
+        assert!(python.starts_with("# Field offsets generated by pymspdb from kernel.pdb GUID "));
+        assert!(python.contains("\nEPROCESS_TOKEN_SIZE = 0x8\n"));
+        assert!(!python.contains("#pragma once"));
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use pyo3::exceptions::*;
+use pyo3::PyResult;
+use crate::layout::TypeLayouts;
+use crate::manifest;
+use crate::struct_diff::{self, Member};
+use crate::symsrv;
+
+/*
+ *
+ * OFFSET HISTORY
+ *
+ * The offsets of a struct / union's fields across many builds (a directory of ntkrnlmp.pdb from
+ * every Windows release): a row per field, a column per build.
+ *
+ *      Field              | 10.0.19041.1 | 10.0.22621.1 | 10.0.26100.1
+ *      sizeof             | 0xa40        | 0xb80        | 0xb80
+ *      UniqueProcessId    | 0x440        | 0x440        | 0x1d0
+ *      Flags3             | -            | 0x87c        | 0x87c
+ *
+ * Columns are named by the labels given for the PDBs (PE versions, the PDB doesn't know which
+ * build it belongs to), the symstore index key (GUID digits followed by the age) otherwise.
+ * Rows are in layout order, fields that only exist in some builds next to their neighbours.
+ * Bitfields read "0x87c Pos 3". Markdown and HTML mark the cells that differ from the build
+ * before them.
+ *
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Markdown,
+    Html,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name.to_lowercase().as_str() {
+            "csv" => Some(Format::Csv),
+            "markdown" | "md" => Some(Format::Markdown),
+            "html" => Some(Format::Html),
+            _ => None
+        }
+    }
+}
+
+pub struct Build {
+    pub pdb_name: String,
+    pub label: String,
+    // None when the build doesn't have the type
+    pub size: Option<u64>,
+}
+
+pub struct HistoryRow {
+    pub path: String,
+    // A cell per build, None where the field doesn't exist
+    pub cells: Vec<Option<Member>>,
+}
+
+pub struct OffsetHistory {
+    pub name: String,
+    pub builds: Vec<Build>,
+    pub rows: Vec<HistoryRow>,
+}
+
+/*
+Lists the PDBs to compare.
+
+@paths     => PDB files and / or directories of PDBs (a symbol store works).
+@recursive => Look in sub directories too.
+
+RETURN
+returns the PDB files, directories listed in sorted order.
+*/
+pub fn list_pdbs(paths: &[PathBuf], recursive: bool) -> PyResult<Vec<PathBuf>> {
+    let mut pdbs = Vec::new();
+    for path in paths {
+        pdbs.extend(manifest::list_files(path, recursive, &|file: &Path| manifest::has_extension(file, &["pdb"]))?);
+    }
+    Ok(pdbs)
+}
+
+/*
+Collects the offsets of a struct / union's fields in every PDB.
+
+@name   => Struct / union name.
+@pdbs   => PDB files, in column order.
+@labels => Column names by PDB path or by index key, the index key is used for the others.
+
+RETURN
+returns the history, KeyError if none of the PDBs has the type.
+*/
+pub fn collect(name: &str, pdbs: &[PathBuf], labels: &HashMap<String, String>) -> PyResult<OffsetHistory> {
+    let mut builds = Vec::new();
+    let mut rows: Vec<HistoryRow> = Vec::new();
+
+    for (column, pdb) in pdbs.iter().enumerate() {
+        let pdb_name = pdb.to_string_lossy().into_owned();
+        let layouts = TypeLayouts::load(&pdb_name)?;
+        let key = symsrv::index_key(layouts.guid(), layouts.age())?;
+        let label = labels.get(&pdb_name).or_else(|| labels.get(&key)).cloned().unwrap_or(key);
+
+        let version = struct_diff::struct_version(&layouts, &pdb_name, name);
+        builds.push(Build {
+            pdb_name: pdb_name.clone(),
+            label,
+            size: version.as_ref().map(|version| version.size),
+        });
+        for row in &mut rows {
+            row.cells.push(None);
+        }
+
+        // New fields go right after the field before them in this build
+        let mut insert_at = 0;
+        for member in version.map(|version| version.members).unwrap_or_default() {
+            match rows.iter().position(|row| row.path == member.path) {
+                Some(position) => {
+                    rows[position].cells[column] = Some(member);
+                    insert_at = position + 1;
+                },
+                None => {
+                    let mut cells = vec![None; column + 1];
+                    let path = member.path.clone();
+                    cells[column] = Some(member);
+                    rows.insert(insert_at, HistoryRow { path, cells });
+                    insert_at += 1;
+                }
+            }
+        }
+    }
+
+    if builds.iter().all(|build| build.size.is_none()) {
+        return Err(KeyError::py_err(format!("[pymspdb] No struct or union named {} in any of the {} PDBs", name, builds.len())));
+    }
+    Ok(OffsetHistory { name: String::from(name), builds, rows })
+}
+
+fn member_text(member: &Member) -> String {
+    match member.bitfield {
+        Some(bitfield) => format!("{:#x} Pos {}", member.offset, bitfield.position),
+        None => format!("{:#x}", member.offset)
+    }
+}
+
+/*
+One rendered cell: its text (None where missing) and whether it differs from the build before it.
+*/
+struct Cell {
+    text: Option<String>,
+    changed: bool,
+}
+
+/*
+Every line of the table as cells, the sizes first then the fields.
+*/
+fn table_cells(history: &OffsetHistory) -> Vec<(String, Vec<Cell>)> {
+    let mut lines = vec![(String::from("sizeof"), history.builds.iter().map(|build| build.size.map(|size| format!("{:#x}", size))).collect::<Vec<_>>())];
+    lines.extend(history.rows.iter().map(|row| (row.path.clone(), row.cells.iter().map(|cell| cell.as_ref().map(member_text)).collect())));
+
+    lines.into_iter().map(|(path, texts)| {
+        let mut previous: Option<String> = None;
+        let cells = texts.into_iter().map(|text| {
+            // Compared to the last build that has the field, so builds without it don't mark anything
+            let changed = matches!((&previous, &text), (Some(previous), Some(text)) if previous != text);
+            if text.is_some() {
+                previous = text.clone();
+            }
+            Cell { text, changed }
+        }).collect();
+        (path, cells)
+    }).collect()
+}
+
+fn csv_field(text: &str) -> String {
+    match text.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => String::from(text)
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn to_csv(history: &OffsetHistory) -> Vec<String> {
+    let mut lines = vec![std::iter::once(String::from("Field")).chain(history.builds.iter().map(|build| csv_field(&build.label))).collect::<Vec<_>>().join(",")];
+    for (path, cells) in table_cells(history) {
+        let mut line = vec![csv_field(&path)];
+        line.extend(cells.into_iter().map(|cell| cell.text.unwrap_or_default()));
+        lines.push(line.join(","));
+    }
+    lines
+}
+
+fn to_markdown(history: &OffsetHistory) -> Vec<String> {
+    let markdown = |text: &str| text.replace('|', "\\|");
+    let mut lines = vec![
+        format!("| Field | {} |", history.builds.iter().map(|build| markdown(&build.label)).collect::<Vec<_>>().join(" | ")),
+        format!("|---|{}", "---|".repeat(history.builds.len())),
+    ];
+    for (path, cells) in table_cells(history) {
+        let cells: Vec<String> = cells.into_iter().map(|cell| match (cell.text, cell.changed) {
+            (Some(text), true) => format!("**{}**", text),
+            (Some(text), false) => text,
+            (None, _) => String::from("-")
+        }).collect();
+        lines.push(format!("| {} | {} |", markdown(&path), cells.join(" | ")));
+    }
+    lines
+}
+
+fn to_html(history: &OffsetHistory) -> Vec<String> {
+    let mut lines = vec![
+        String::from("<!DOCTYPE html>"),
+        format!("<html><head><meta charset=\"utf-8\"><title>{} offsets</title>", html_escape(&history.name)),
+        String::from("<style>table { border-collapse: collapse; font-family: Consolas, monospace; } th, td { border: 1px solid #ccc; padding: 2px 8px; } \
+                      td.changed { background: #ffe08a; } td.missing { color: #aaa; }</style>"),
+        format!("</head><body><h1>{}</h1>", html_escape(&history.name)),
+        String::from("<table>"),
+        String::from("<tr><th>Field</th>"),
+    ];
+    for build in &history.builds {
+        lines.push(format!("<th title=\"{}\">{}</th>", html_escape(&build.pdb_name), html_escape(&build.label)));
+    }
+    lines.push(String::from("</tr>"));
+
+    for (path, cells) in table_cells(history) {
+        let mut line = format!("<tr><th>{}</th>", html_escape(&path));
+        for cell in cells {
+            line.push_str(&match (cell.text, cell.changed) {
+                (Some(text), true) => format!("<td class=\"changed\">{}</td>", text),
+                (Some(text), false) => format!("<td>{}</td>", text),
+                (None, _) => String::from("<td class=\"missing\">-</td>")
+            });
+        }
+        line.push_str("</tr>");
+        lines.push(line);
+    }
+    lines.push(String::from("</table>"));
+    lines.push(String::from("</body></html>"));
+    lines
+}
+
+/*
+Renders the history as a table.
+
+@history => History to render.
+@format  => CSV, Markdown or HTML.
+
+RETURN
+returns the table text.
+*/
+pub fn to_table(history: &OffsetHistory, format: Format) -> String {
+    let mut lines = match format {
+        Format::Csv => to_csv(history),
+        Format::Markdown => to_markdown(history),
+        Format::Html => to_html(history),
+    };
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fixture;
+
+    fn kernel_history(labels: &[(&str, &str)]) -> OffsetHistory {
+        let pdbs = [PathBuf::from(fixture("kernel.pdb")), PathBuf::from(fixture("kernel_new.pdb"))];
+        let labels = labels.iter().map(|(pdb, label)| (String::from(*pdb), String::from(*label))).collect();
+        collect("_EPROCESS", &pdbs, &labels).unwrap()
+    }
+
+    #[test]
+    fn lists_pdbs_of_directories() {
+        let pdbs = list_pdbs(&[PathBuf::from(fixture("")), PathBuf::from(fixture("kernel.pdb"))], false).unwrap();
+        assert_eq!(pdbs, ["kernel.pdb", "kernel_new.pdb", "symbols.pdb", "kernel.pdb"].iter().map(|pdb| PathBuf::from(fixture(pdb))).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn collects_offsets_of_every_build() {
+        let kernel = fixture("kernel.pdb");
+        let history = kernel_history(&[(&kernel, "10.0.19041.1"), ("111122223333444455556666777799993", "10.0.22621.1")]);
+        let labels: Vec<_> = history.builds.iter().map(|build| (build.label.as_str(), build.size)).collect();
+        assert_eq!(labels, vec![("10.0.19041.1", Some(0x98)), ("10.0.22621.1", Some(0xa0))]);
+
+        // Fields of the new build right after Spare, the one before them
+        let paths: Vec<_> = history.rows.iter().map(|row| row.path.as_str()).collect();
+        assert_eq!(&paths[paths.len() - 5..], &["Terminated", "Spare", "Exiting", "Flags3", "ExitTime"]);
+        let priority_class = history.rows.iter().find(|row| row.path == "PriorityClass").unwrap();
+        assert!(priority_class.cells[0].is_some() && priority_class.cells[1].is_none());
+
+        // Unlabeled builds are named by their index key
+        assert_eq!(kernel_history(&[]).builds[0].label, "111122223333444455556666777788882");
+    }
+
+    #[test]
+    fn renders_tables() {
+        let history = kernel_history(&[]);
+        let csv = to_table(&history, Format::Csv);
+        assert!(csv.starts_with("Field,111122223333444455556666777788882,111122223333444455556666777799993\nsizeof,0x98,0xa0\n"));
+        assert!(csv.contains("\nSpare,0x88 Pos 1,0x88 Pos 1\nExiting,,0x88 Pos 31\n"));
+
+        let markdown = to_table(&history, Format::Markdown);
+        assert!(markdown.contains("\n|---|---|---|\n| sizeof | 0x98 | **0xa0** |\n"));
+        assert!(markdown.contains("\n| PriorityClass | 0x5f | - |\n"));
+        assert!(markdown.ends_with("\n| ExitTime | 0x90 | **0x98** |\n"));
+
+        let html = to_table(&history, Format::Html);
+        assert!(html.contains("<tr><th>Flags3</th><td class=\"missing\">-</td><td>0x90</td></tr>"));
+        assert!(html.contains("<tr><th>ExitTime</th><td>0x90</td><td class=\"changed\">0x98</td></tr>"));
+    }
+
+    #[test]
+    fn rejects_types_no_build_has() {
+        let pdbs = [PathBuf::from(fixture("kernel.pdb")), PathBuf::from(fixture("symbols.pdb"))];
+        assert!(collect("_KTHREAD", &pdbs, &HashMap::new()).is_err());
+        // Builds without the type are still columns
+        let history = collect("_EPROCESS", &pdbs, &HashMap::new()).unwrap();
+        assert_eq!(history.builds[1].size, None);
+        assert!(history.rows.iter().all(|row| row.cells[1].is_none()));
+    }
+}
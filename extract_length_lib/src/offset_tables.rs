@@ -0,0 +1,343 @@
+use pyo3::exceptions::*;
+use pyo3::{PyErr, PyResult};
+use crate::layout::{Field, TypeLayouts};
+use crate::offset_constants::{self, Language};
+
+/*
+ *
+ * OFFSET TABLES
+ *
+ * Offsets of a few fields for every Windows build a driver supports, picked at runtime by build
+ * number (RtlGetVersion's dwBuildNumber) instead of compiled in for a single PDB:
+ *
+ *      static const KERNEL_OFFSETS KERNEL_OFFSETS_TABLE[] = {
+ *          {
+ *              19041, 19045,
+ *              0x4b8, /* eprocess_token */
+ *          },
+ *          ...
+ *      const KERNEL_OFFSETS *offsets = kernel_offsets_lookup(build);
+ *
+ * Consecutive builds with the same offsets collapse into one range, running from the first to
+ * the last build a PDB was given for. Builds inside a range are interpolated: with PDBs of 19041
+ * and 19045 only, 19042 - 19044 are assumed to share their offsets. Builds between two ranges
+ * aren't covered (lookup returns NULL / None), nothing says which layout they have. Fields a
+ * build doesn't have are <NAME>_MISSING in C and None in Rust, and are listed at the top of the
+ * file. Bitfields also get their bit position.
+ *
+ */
+
+/*
+A value of each entry: a field's offset or bit position.
+*/
+struct Column {
+    // snake case member name
+    name: String,
+    path: String,
+    // Offsets are written in hex, bit positions in decimal
+    hex: bool,
+}
+
+impl Column {
+    fn text(&self, value: u64) -> String {
+        match self.hex {
+            true => format!("{:#x}", value),
+            false => value.to_string()
+        }
+    }
+}
+
+pub struct BuildRange {
+    pub first: u32,
+    pub last: u32,
+    pub pdbs: Vec<String>,
+    // A value per column, None where the field doesn't exist
+    values: Vec<Option<u64>>,
+}
+
+pub struct OffsetTable {
+    columns: Vec<Column>,
+    pub ranges: Vec<BuildRange>,
+    // Field paths with the builds that don't have them
+    pub missing: Vec<(String, Vec<u32>)>,
+}
+
+/*
+Collects the offsets of fields in the PDB of every build.
+
+@paths => Field paths (_EPROCESS.Token, _EPROCESS.Pcb.DirectoryTableBase).
+@pdbs  => PDB paths with the build number they belong to.
+
+RETURN
+returns the table, the error of the last PDB for fields no PDB has, KeyError / IndexError for
+paths that aren't plain fields of a struct (->, a field of a pointer or integer), ValueError if two
+PDBs of the same build disagree.
+*/
+pub fn collect(paths: &[String], pdbs: &[(String, u32)]) -> PyResult<OffsetTable> {
+    let mut pdbs = pdbs.to_vec();
+    // By path within a build too, so which two PDBs a conflict names doesn't depend on the caller's order
+    pdbs.sort_by(|(first_pdb, first_build), (second_pdb, second_build)| (first_build, first_pdb).cmp(&(second_build, second_pdb)));
+
+    // fields[pdb][path]
+    let mut fields: Vec<Vec<Option<Field>>> = Vec::new();
+    let mut errors: Vec<Option<PyErr>> = paths.iter().map(|_| None).collect();
+    for (pdb_name, _) in &pdbs {
+        let layouts = TypeLayouts::load(pdb_name)?;
+        let mut pdb_fields = Vec::new();
+        for (position, path) in paths.iter().enumerate() {
+            // Only a type or field this build lacks is missing, a path no build can resolve is an error
+            match layouts.find_field_path(path)? {
+                Ok(field) => pdb_fields.push(Some(field)),
+                Err(e) => {
+                    errors[position] = Some(e);
+                    pdb_fields.push(None);
+                }
+            }
+        }
+        fields.push(pdb_fields);
+    }
+
+    let mut columns = Vec::new();
+    let mut rows: Vec<Vec<Option<u64>>> = fields.iter().map(|_| Vec::new()).collect();
+    let mut missing = Vec::new();
+    for (position, path) in paths.iter().enumerate() {
+        let found: Vec<Option<&Field>> = fields.iter().map(|pdb_fields| pdb_fields[position].as_ref()).collect();
+        if found.iter().all(Option::is_none) {
+            if let Some(e) = errors[position].take() {
+                return Err(e);
+            }
+        }
+
+        let name = offset_constants::constant_name(path).to_lowercase();
+        columns.push(Column { name: name.clone(), path: path.clone(), hex: true });
+        for (row, field) in rows.iter_mut().zip(&found) {
+            row.push(field.map(|field| field.offset));
+        }
+        if found.iter().flatten().any(|field| field.bitfield.is_some()) {
+            columns.push(Column { name: format!("{}_bit_position", name), path: format!("{} (bit position)", path), hex: false });
+            for (row, field) in rows.iter_mut().zip(&found) {
+                row.push(field.and_then(|field| field.bitfield).map(|bitfield| u64::from(bitfield.position)));
+            }
+        }
+
+        let mut builds: Vec<u32> = pdbs.iter().zip(&found).filter(|(_, field)| field.is_none()).map(|((_, build), _)| *build).collect();
+        builds.dedup();
+        if !builds.is_empty() {
+            missing.push((path.clone(), builds));
+        }
+    }
+
+    let mut ranges: Vec<BuildRange> = Vec::new();
+    for ((pdb_name, build), values) in pdbs.into_iter().zip(rows) {
+        match ranges.last_mut() {
+            Some(range) if range.last == build && range.values != values => {
+                return Err(ValueError::py_err(format!("[pymspdb] {} and {} are both build {} but have different offsets", range.pdbs.last().unwrap(), pdb_name, build)));
+            },
+            Some(range) if range.values == values => {
+                range.last = build;
+                range.pdbs.push(pdb_name);
+            },
+            _ => ranges.push(BuildRange { first: build, last: build, pdbs: vec![pdb_name], values })
+        }
+    }
+
+    Ok(OffsetTable { columns, ranges, missing })
+}
+
+/*
+CamelCase of a name (kernel_offsets => KernelOffsets).
+*/
+fn type_name(name: &str) -> String {
+    offset_constants::constant_name(name).split('_').map(|part| {
+        let mut chars = part.chars();
+        match chars.next() {
+            Some(first) => first.to_string() + &chars.as_str().to_lowercase(),
+            None => String::new()
+        }
+    }).collect()
+}
+
+fn builds_text(builds: &[u32]) -> String {
+    builds.iter().map(u32::to_string).collect::<Vec<_>>().join(", ")
+}
+
+/*
+Comment lines at the top of the file: where the numbers come from and what's missing.
+*/
+fn header(table: &OffsetTable) -> Vec<String> {
+    let mut lines = vec![String::from("Field offsets by Windows build generated by pymspdb")];
+    for range in &table.ranges {
+        lines.push(format!("  {}-{}: {}", range.first, range.last, range.pdbs.join(", ")));
+    }
+    for (path, builds) in &table.missing {
+        lines.push(format!("{} doesn't exist in build{} {}", path, if builds.len() == 1 { "" } else { "s" }, builds_text(builds)));
+    }
+    lines
+}
+
+fn generate_c(table: &OffsetTable, name: &str) -> Vec<String> {
+    let constant = offset_constants::constant_name(name);
+    let function = format!("{}_lookup", constant.to_lowercase());
+    let mut lines: Vec<String> = header(table).iter().map(|line| format!("/* {} */", line)).collect();
+    lines.push(String::from("#pragma once"));
+    lines.push(String::new());
+    lines.push(format!("#define {}_MISSING 0xffffffffUL", constant));
+    lines.push(String::new());
+
+    lines.push(format!("typedef struct _{} {{", constant));
+    lines.push(String::from("    unsigned long min_build;"));
+    lines.push(String::from("    unsigned long max_build;"));
+    for column in &table.columns {
+        lines.push(format!("    unsigned long {}; /* {} */", column.name, column.path));
+    }
+    lines.push(format!("}} {};", constant));
+    lines.push(String::new());
+
+    lines.push(format!("static const {} {}_TABLE[] = {{", constant, constant));
+    for range in &table.ranges {
+        lines.push(String::from("    {"));
+        lines.push(format!("        {}, {},", range.first, range.last));
+        for (column, value) in table.columns.iter().zip(&range.values) {
+            let value = match value {
+                Some(value) => column.text(*value),
+                None => format!("{}_MISSING", constant)
+            };
+            lines.push(format!("        {}, /* {} */", value, column.name));
+        }
+        lines.push(String::from("    },"));
+    }
+    lines.push(String::from("};"));
+    lines.push(String::new());
+
+    lines.push(format!("static __inline const {} *{}(unsigned long build)", constant, function));
+    lines.push(String::from("{"));
+    lines.push(String::from("    unsigned long i;"));
+    lines.push(format!("    for (i = 0; i < sizeof({}_TABLE) / sizeof({}_TABLE[0]); i++) {{", constant, constant));
+    lines.push(format!("        if (build >= {}_TABLE[i].min_build && build <= {}_TABLE[i].max_build) {{", constant, constant));
+    lines.push(format!("            return &{}_TABLE[i];", constant));
+    lines.push(String::from("        }"));
+    lines.push(String::from("    }"));
+    lines.push(String::from("    return 0;"));
+    lines.push(String::from("}"));
+    lines
+}
+
+fn generate_rust(table: &OffsetTable, name: &str) -> Vec<String> {
+    let constant = offset_constants::constant_name(name);
+    let type_name = type_name(name);
+    let mut lines: Vec<String> = header(table).iter().map(|line| format!("// {}", line)).collect();
+    lines.push(String::new());
+
+    lines.push(String::from("#[derive(Debug, Clone, Copy, PartialEq, Eq)]"));
+    lines.push(format!("pub struct {} {{", type_name));
+    lines.push(String::from("    pub min_build: u32,"));
+    lines.push(String::from("    pub max_build: u32,"));
+    for column in &table.columns {
+        lines.push(format!("    // {}", column.path));
+        lines.push(format!("    pub {}: Option<u32>,", column.name));
+    }
+    lines.push(String::from("}"));
+    lines.push(String::new());
+
+    lines.push(format!("pub const {}_TABLE: &[{}] = &[", constant, type_name));
+    for range in &table.ranges {
+        lines.push(format!("    {} {{", type_name));
+        lines.push(format!("        min_build: {},", range.first));
+        lines.push(format!("        max_build: {},", range.last));
+        for (column, value) in table.columns.iter().zip(&range.values) {
+            let value = match value {
+                Some(value) => format!("Some({})", column.text(*value)),
+                None => String::from("None")
+            };
+            lines.push(format!("        {}: {},", column.name, value));
+        }
+        lines.push(String::from("    },"));
+    }
+    lines.push(String::from("];"));
+    lines.push(String::new());
+
+    lines.push(format!("pub fn {}_lookup(build: u32) -> Option<&'static {}> {{", constant.to_lowercase(), type_name));
+    lines.push(format!("    {}_TABLE.iter().find(|entry| (entry.min_build..=entry.max_build).contains(&build))", constant));
+    lines.push(String::from("}"));
+    lines
+}
+
+/*
+Writes the table as C (a header) or Rust.
+
+@table    => Collected offsets.
+@language => C / C++ (the same header) or Rust.
+@name     => Name of the entry type, the table and the lookup function (kernel_offsets).
+
+RETURN
+returns the source text, ValueError for the languages without tables.
+*/
+pub fn generate(table: &OffsetTable, language: Language, name: &str) -> PyResult<String> {
+    let mut lines = match language {
+        Language::C | Language::Cpp => generate_c(table, name),
+        Language::Rust => generate_rust(table, name),
+        Language::Python => return Err(ValueError::py_err("[pymspdb] Offset tables are written in c, cpp or rust"))
+    };
+    lines.push(String::new());
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fixture;
+
+    fn paths(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| String::from(*path)).collect()
+    }
+
+    fn kernel_table() -> OffsetTable {
+        let pdbs = [(fixture("kernel_new.pdb"), 22621), (fixture("kernel.pdb"), 19041), (fixture("kernel.pdb"), 19045)];
+        collect(&paths(&["_EPROCESS.Token", "_EPROCESS.ExitTime", "_EPROCESS.PriorityClass", "_EPROCESS.Spare"]), &pdbs).unwrap()
+    }
+
+    #[test]
+    fn collapses_builds_into_ranges() {
+        let table = kernel_table();
+        let ranges: Vec<_> = table.ranges.iter().map(|range| (range.first, range.last, range.pdbs.len())).collect();
+        assert_eq!(ranges, vec![(19041, 19045, 2), (22621, 22621, 1)]);
+        assert_eq!(table.ranges[1].values, vec![Some(0x48), Some(0x98), None, Some(0x88), Some(1)]);
+        assert_eq!(table.missing, vec![(String::from("_EPROCESS.PriorityClass"), vec![22621])]);
+
+        // A field no build has is the error of the PDB
+        let pdbs = [(fixture("kernel.pdb"), 19041)];
+        assert!(collect(&paths(&["_EPROCESS.Nope"]), &pdbs).is_err());
+        // So is a path that can't be a field of any build, even next to fields some builds lack
+        let pdbs = [(fixture("kernel_new.pdb"), 22621), (fixture("kernel.pdb"), 19041)];
+        assert!(collect(&paths(&["_EPROCESS.PriorityClass", "_EPROCESS.Peb->Ldr"]), &pdbs).is_err());
+    }
+
+    #[test]
+    fn orders_pdbs_of_a_build_by_path() {
+        let fields = paths(&["_EPROCESS.Token"]);
+        let forward = [(fixture("kernel.pdb"), 19041), (fixture("kernel_new.pdb"), 19041)];
+        let backward = [(fixture("kernel_new.pdb"), 19041), (fixture("kernel.pdb"), 19041)];
+        assert_eq!(collect(&fields, &forward).unwrap().ranges[0].pdbs, vec![fixture("kernel.pdb"), fixture("kernel_new.pdb")]);
+        assert_eq!(collect(&fields, &backward).unwrap().ranges[0].pdbs, vec![fixture("kernel.pdb"), fixture("kernel_new.pdb")]);
+
+        // Same build, different offsets
+        assert!(collect(&paths(&["_EPROCESS.ExitTime"]), &backward).is_err());
+    }
+
+    #[test]
+    fn generates_c_and_rust_tables() {
+        let table = kernel_table();
+        let c = generate(&table, Language::C, "kernel_offsets").unwrap();
+        assert!(c.contains("/* _EPROCESS.PriorityClass doesn't exist in build 22621 */\n#pragma once\n"));
+        assert!(c.contains("    unsigned long eprocess_spare_bit_position; /* _EPROCESS.Spare (bit position) */\n} KERNEL_OFFSETS;"));
+        assert!(c.contains("        22621, 22621,\n        0x48, /* eprocess_token */\n        0x98, /* eprocess_exit_time */\n        KERNEL_OFFSETS_MISSING, /* eprocess_priority_class */\n"));
+        assert!(c.contains("static __inline const KERNEL_OFFSETS *kernel_offsets_lookup(unsigned long build)"));
+
+        let rust = generate(&table, Language::Rust, "kernel_offsets").unwrap();
+        assert!(rust.contains("pub const KERNEL_OFFSETS_TABLE: &[KernelOffsets] = &[\n    KernelOffsets {\n        min_build: 19041,\n        max_build: 19045,\n"));
+        assert!(rust.contains("        eprocess_priority_class: None,\n        eprocess_spare: Some(0x88),\n        eprocess_spare_bit_position: Some(1),\n"));
+        assert!(rust.contains("pub fn kernel_offsets_lookup(build: u32) -> Option<&'static KernelOffsets> {"));
+
+        assert!(generate(&table, Language::Python, "kernel_offsets").is_err());
+    }
+}
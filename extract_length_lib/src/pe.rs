@@ -0,0 +1,282 @@
+use std::fs;
+use std::path::Path;
+use crate::bytes;
+
+/*
+ *
+ * Minimal PE reader, only what's needed in order to find the PDB an image was built with.
+ * Errors are plain strings so callers scanning many images can report them per image.
+ *
+ */
+
+const IMAGE_DOS_SIGNATURE: &[u8] = b"MZ";
+const IMAGE_NT_SIGNATURE: &[u8] = b"PE\0\0";
+const IMAGE_NT_OPTIONAL_HDR32_MAGIC: u16 = 0x10b;
+const IMAGE_NT_OPTIONAL_HDR64_MAGIC: u16 = 0x20b;
+const IMAGE_DIRECTORY_ENTRY_DEBUG: usize = 6;
+const IMAGE_DEBUG_TYPE_CODEVIEW: u32 = 2;
+const IMAGE_DEBUG_DIRECTORY_SIZE: usize = 28;
+
+/*
+Everything needed to find the matching PDB of an image in a symbol store.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct PdbIdentity {
+    // PDB path as written by the linker (usually a full build path)
+    pub name: String,
+    // GUID digits (RSDS), or the timestamp signature digits for old NB10 records
+    pub signature: String,
+    pub age: u32,
+}
+
+impl PdbIdentity {
+    /*
+    Symstore index key: signature digits followed by the age in hex.
+    */
+    pub fn index_key(&self) -> String {
+        format!("{}{:X}", self.signature, self.age)
+    }
+
+    /*
+    File name of the PDB without the build directory.
+    */
+    pub fn file_name(&self) -> &str {
+        crate::symsrv::file_name_only(&self.name)
+    }
+}
+
+struct Section {
+    virtual_address: u32,
+    virtual_size: u32,
+    raw_offset: u32,
+    raw_size: u32,
+}
+
+fn pe_error(message: &str) -> String {
+    format!("Invalid PE image: {}", message)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, String> {
+    bytes::read_u16(data, offset).ok_or_else(|| pe_error("unexpected end of file"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    bytes::read_u32(data, offset).ok_or_else(|| pe_error("unexpected end of file"))
+}
+
+/*
+Converts an RVA into a file offset using the section table.
+*/
+fn rva_to_offset(sections: &[Section], rva: u32) -> Option<usize> {
+    let section = sections.iter().find(|section| {
+        let size = section.virtual_size.max(section.raw_size);
+        // Sections of corrupt images may wrap around the address space
+        rva >= section.virtual_address && section.virtual_address.checked_add(size).is_some_and(|end| rva < end)
+    })?;
+
+    (rva - section.virtual_address).checked_add(section.raw_offset).map(|offset| offset as usize)
+}
+
+/*
+Parses a CodeView debug record (RSDS / NB10).
+
+@record => Raw record bytes.
+
+RETURN
+returns the PDB identity, or None for unknown record types.
+*/
+fn parse_codeview_record(record: &[u8]) -> Result<Option<PdbIdentity>, String> {
+    let read_name = |start: usize| -> String {
+        let name = record.get(start..).unwrap_or(&[]);
+        let length = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        String::from_utf8_lossy(&name[..length]).into_owned()
+    };
+
+    if record.starts_with(b"RSDS") {
+        let guid = record.get(4..20).ok_or_else(|| pe_error("truncated RSDS record"))?;
+        let signature = format!("{:08X}{:04X}{:04X}{}",
+                                read_u32(guid, 0)?,
+                                read_u16(guid, 4)?,
+                                read_u16(guid, 6)?,
+                                guid[8..].iter().map(|b| format!("{:02X}", b)).collect::<String>());
+
+        return Ok(Some(PdbIdentity {
+            name: read_name(24),
+            signature,
+            age: read_u32(record, 20)?,
+        }));
+    }
+
+    if record.starts_with(b"NB10") {
+        return Ok(Some(PdbIdentity {
+            name: read_name(16),
+            signature: format!("{:08X}", read_u32(record, 8)?),
+            age: read_u32(record, 12)?,
+        }));
+    }
+    Ok(None)
+}
+
+/*
+Reads the PDB identities out of an image's debug directory.
+
+@data => PE image contents.
+
+RETURN
+returns every CodeView record's PDB identity (usually exactly one).
+*/
+pub fn pdb_identities(data: &[u8]) -> Result<Vec<PdbIdentity>, String> {
+    if !data.starts_with(IMAGE_DOS_SIGNATURE) {
+        return Err(pe_error("bad DOS signature"));
+    }
+
+    let nt_offset = read_u32(data, 0x3c)? as usize;
+    if data.get(nt_offset..nt_offset + 4) != Some(IMAGE_NT_SIGNATURE) {
+        return Err(pe_error("bad NT signature"));
+    }
+
+    let file_header = nt_offset + 4;
+    let section_count = read_u16(data, file_header + 2)? as usize;
+    let optional_header_size = read_u16(data, file_header + 16)? as usize;
+    let optional_header = file_header + 20;
+
+    let data_directories = match read_u16(data, optional_header)? {
+        IMAGE_NT_OPTIONAL_HDR32_MAGIC => optional_header + 96,
+        IMAGE_NT_OPTIONAL_HDR64_MAGIC => optional_header + 112,
+        magic => return Err(pe_error(&format!("unknown optional header magic {:#x}", magic)))
+    };
+    let directory_count = read_u32(data, data_directories - 4)? as usize;
+    if directory_count <= IMAGE_DIRECTORY_ENTRY_DEBUG {
+        return Ok(Vec::new());
+    }
+
+    let mut sections = Vec::with_capacity(section_count);
+    let section_table = optional_header + optional_header_size;
+    for index in 0..section_count {
+        let section = section_table + index * 40;
+        sections.push(Section {
+            virtual_size: read_u32(data, section + 8)?,
+            virtual_address: read_u32(data, section + 12)?,
+            raw_size: read_u32(data, section + 16)?,
+            raw_offset: read_u32(data, section + 20)?,
+        });
+    }
+
+    let debug_directory = data_directories + IMAGE_DIRECTORY_ENTRY_DEBUG * 8;
+    let debug_rva = read_u32(data, debug_directory)?;
+    let debug_size = read_u32(data, debug_directory + 4)? as usize;
+    if debug_rva == 0 || debug_size == 0 {
+        return Ok(Vec::new());
+    }
+    let debug_offset = rva_to_offset(&sections, debug_rva).ok_or_else(|| pe_error("debug directory outside of sections"))?;
+
+    let mut identities = Vec::new();
+    for index in 0..(debug_size / IMAGE_DEBUG_DIRECTORY_SIZE) {
+        let entry = debug_offset + index * IMAGE_DEBUG_DIRECTORY_SIZE;
+        if read_u32(data, entry + 12)? != IMAGE_DEBUG_TYPE_CODEVIEW {
+            continue;
+        }
+
+        let record_size = read_u32(data, entry + 16)? as usize;
+        let record_offset = read_u32(data, entry + 24)? as usize;
+        let record = data.get(record_offset..record_offset + record_size)
+                         .ok_or_else(|| pe_error("CodeView record out of bounds"))?;
+
+        if let Some(identity) = parse_codeview_record(record)? {
+            identities.push(identity);
+        }
+    }
+    Ok(identities)
+}
+
+/*
+Reads the PDB identity of an image file.
+
+@image_path => Path to the PE file (exe / dll / sys).
+
+RETURN
+returns the identity of the PDB the image was linked with.
+*/
+pub fn read_pdb_identity(image_path: &Path) -> Result<PdbIdentity, String> {
+    let data = fs::read(image_path).map_err(|e| e.to_string())?;
+
+    match pdb_identities(&data)?.into_iter().next() {
+        Some(identity) => Ok(identity),
+        None => Err(String::from("no CodeView debug record"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUID: [u8; 16] = [0xb9, 0xdb, 0x44, 0x38, 0x17, 0x20, 0x67, 0x49, 0xbe, 0x7a, 0xa4, 0xa2, 0xc2, 0x04, 0x30, 0xfa];
+
+    fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    // 64 bit image with a single section (RVA 0x1000, file offset 0x200) holding the debug directory
+    fn image(record: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; 0x400];
+        data[..2].copy_from_slice(IMAGE_DOS_SIGNATURE);
+        write_u32(&mut data, 0x3c, 0x40);
+        data[0x40..0x44].copy_from_slice(IMAGE_NT_SIGNATURE);
+        data[0x46..0x48].copy_from_slice(&1u16.to_le_bytes());
+        data[0x54..0x56].copy_from_slice(&240u16.to_le_bytes());
+        data[0x58..0x5a].copy_from_slice(&IMAGE_NT_OPTIONAL_HDR64_MAGIC.to_le_bytes());
+        write_u32(&mut data, 0x58 + 108, 16);
+
+        let debug_directory = 0x58 + 112 + IMAGE_DIRECTORY_ENTRY_DEBUG * 8;
+        write_u32(&mut data, debug_directory, 0x1000);
+        write_u32(&mut data, debug_directory + 4, IMAGE_DEBUG_DIRECTORY_SIZE as u32);
+
+        let section = 0x58 + 240;
+        write_u32(&mut data, section + 8, 0x200);
+        write_u32(&mut data, section + 12, 0x1000);
+        write_u32(&mut data, section + 16, 0x200);
+        write_u32(&mut data, section + 20, 0x200);
+
+        write_u32(&mut data, 0x200 + 12, IMAGE_DEBUG_TYPE_CODEVIEW);
+        write_u32(&mut data, 0x200 + 16, record.len() as u32);
+        write_u32(&mut data, 0x200 + 24, 0x220);
+        data[0x220..0x220 + record.len()].copy_from_slice(record);
+        data
+    }
+
+    #[test]
+    fn reads_rsds_records() {
+        let mut record = b"RSDS".to_vec();
+        record.extend_from_slice(&GUID);
+        record.extend_from_slice(&2u32.to_le_bytes());
+        record.extend_from_slice(b"d:\\build\\ntkrnlmp.pdb\0");
+
+        let identities = pdb_identities(&image(&record)).unwrap();
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].index_key(), "3844DBB920174967BE7AA4A2C20430FA2");
+        assert_eq!(identities[0].file_name(), "ntkrnlmp.pdb");
+    }
+
+    #[test]
+    fn rejects_bad_images() {
+        assert!(pdb_identities(b"MZ").is_err());
+        assert!(pdb_identities(&[0; 0x100]).is_err());
+
+        let mut data = image(b"RSDS");
+        assert!(pdb_identities(&data).is_err());
+        write_u32(&mut data, 0x200 + 24, 0xffff_fff0);
+        assert!(pdb_identities(&data).is_err());
+    }
+
+    #[test]
+    fn maps_rvas_without_overflowing() {
+        let sections = [
+            Section { virtual_address: 0xffff_f000, virtual_size: 0x2000, raw_offset: 0x400, raw_size: 0x200 },
+            Section { virtual_address: 0x1000, virtual_size: 0x1000, raw_offset: 0xffff_ff00, raw_size: 0x1000 },
+        ];
+        assert_eq!(rva_to_offset(&sections, 0x1010), Some(0xffff_ff10));
+        assert_eq!(rva_to_offset(&sections, 0x1f00), None);
+        assert_eq!(rva_to_offset(&sections, 0xffff_f010), None);
+        assert_eq!(rva_to_offset(&sections, 0x3000), None);
+    }
+}
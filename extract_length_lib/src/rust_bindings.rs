@@ -0,0 +1,522 @@
+use std::collections::{HashSet, VecDeque};
+use pdb::{PrimitiveKind, TypeIndex};
+use crate::c_header::c_identifier;
+use crate::codegen::{self, UniqueNames, INDENT};
+use crate::layout::{self, Field, Member, TypeLayouts, TypeRef, Udt, UdtKind};
+
+/*
+ *
+ * RUST BINDING GENERATION
+ *
+ * Emits #[repr(C)] definitions for a set of root types and everything they reference, with
+ * const asserts (core::mem::offset_of! / size_of) on every member offset and type size.
+ *
+ * Rust has no bitfields, anonymous unions or anonymous structs, so:
+ *      bitfields           one integer per storage unit (_bitfield_0x..), and a getter / setter
+ *                          per bitfield
+ *      anonymous unions    a _union_0x.. member of a named <Parent>_union_0x.. type, whose
+ *                          anonymous struct alternatives are <Union>_struct<N> types
+ *      enums               #[repr(transparent)] newtypes with a constant per enumerate, since
+ *                          memory can hold values a Rust enum isn't allowed to have
+ *
+ * Pointers are either raw pointers (memory of the current process) or integers of the target's
+ * pointer size (addresses in another process or a memory dump).
+ *
+ */
+
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop",
+    "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "static",
+    "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual",
+    "where", "while", "yield",
+];
+
+fn rust_primitive(kind: PrimitiveKind) -> Option<&'static str> {
+    match kind {
+        PrimitiveKind::NoType | PrimitiveKind::Void => Some("core::ffi::c_void"),
+        PrimitiveKind::Char | PrimitiveKind::RChar | PrimitiveKind::I8 => Some("i8"),
+        PrimitiveKind::UChar | PrimitiveKind::U8 | PrimitiveKind::Bool8 => Some("u8"),
+        PrimitiveKind::Short | PrimitiveKind::I16 => Some("i16"),
+        PrimitiveKind::UShort | PrimitiveKind::U16 | PrimitiveKind::WChar | PrimitiveKind::RChar16 |
+        PrimitiveKind::Bool16 | PrimitiveKind::F16 => Some("u16"),
+        PrimitiveKind::Long | PrimitiveKind::I32 | PrimitiveKind::HRESULT => Some("i32"),
+        PrimitiveKind::ULong | PrimitiveKind::U32 | PrimitiveKind::RChar32 | PrimitiveKind::Bool32 => Some("u32"),
+        PrimitiveKind::Quad | PrimitiveKind::I64 => Some("i64"),
+        PrimitiveKind::UQuad | PrimitiveKind::U64 | PrimitiveKind::Bool64 => Some("u64"),
+        PrimitiveKind::F32 | PrimitiveKind::F32PP => Some("f32"),
+        PrimitiveKind::F64 => Some("f64"),
+        _ => None
+    }
+}
+
+fn is_signed(kind: PrimitiveKind) -> bool {
+    rust_primitive(kind).is_some_and(|name| name.starts_with('i'))
+}
+
+fn is_integer(kind: PrimitiveKind) -> bool {
+    rust_primitive(kind).is_some_and(|name| name.starts_with('i') || name.starts_with('u'))
+}
+
+/*
+Turns a type / member name into a Rust identifier (keywords become raw identifiers).
+*/
+pub fn rust_identifier(name: &str) -> String {
+    let identifier = c_identifier(name);
+    match identifier.as_str() {
+        "self" | "Self" | "super" | "crate" | "_" => format!("{}_", identifier),
+        keyword if KEYWORDS.contains(&keyword) => format!("r#{}", identifier),
+        _ => identifier
+    }
+}
+
+// Anonymous struct / union members of a type, as laid out in it
+enum Shape<'f> {
+    Struct(Vec<Member<'f>>),
+    // Every alternative is a single field or an anonymous struct
+    Union(Vec<Vec<Member<'f>>>),
+}
+
+struct Definition<'f> {
+    name: String,
+    shape: Shape<'f>,
+    // Offset of the definition in the PDB type it comes from
+    base: u64,
+    // Size of PDB types, the helper types for anonymous members get what the compiler gives them
+    size: Option<u64>,
+}
+
+#[derive(Default)]
+struct Body {
+    members: Vec<String>,
+    // Member => offset
+    offsets: Vec<(String, u64)>,
+    // Lines of the bitfield getters / setters
+    accessors: Vec<String>,
+    names: UniqueNames,
+}
+
+struct BindingsWriter<'l> {
+    layouts: &'l TypeLayouts,
+    raw_pointers: bool,
+    out: String,
+}
+
+impl<'l> BindingsWriter<'l> {
+    fn line(&mut self, indent: usize, text: &str) {
+        codegen::line(&mut self.out, indent, text);
+    }
+
+    fn type_identifier(&self, index: TypeIndex) -> String {
+        codegen::type_identifier(self.layouts, index, rust_identifier)
+    }
+
+    fn type_name(&self, typ: &TypeRef) -> String {
+        match typ {
+            TypeRef::Primitive(kind) => match rust_primitive(*kind) {
+                Some(name) => String::from(name),
+                None => self.type_name(&codegen::primitive_bytes(*kind))
+            },
+            TypeRef::Udt(index) | TypeRef::Enum(index) => self.type_identifier(*index),
+            TypeRef::Declared { name, .. } => rust_identifier(name),
+            TypeRef::Pointer { pointee, size } => self.pointer_name(pointee, *size),
+            TypeRef::Array { element, count } => format!("[{}; {}]", self.type_name(element), count),
+            TypeRef::Modified { inner, .. } => self.type_name(inner),
+            TypeRef::Function | TypeRef::Unknown => String::from("[u8; 0]")
+        }
+    }
+
+    fn pointer_name(&self, pointee: &TypeRef, size: u32) -> String {
+        if !self.raw_pointers || size != self.layouts.pointer_size() {
+            return match size {
+                2 => String::from("u16"),
+                4 => String::from("u32"),
+                8 => String::from("u64"),
+                size => format!("[u8; {}]", size)
+            };
+        }
+
+        let (constant, pointee) = match pointee {
+            TypeRef::Modified { inner, constant, .. } => (*constant, inner.as_ref()),
+            pointee => (false, pointee)
+        };
+        let target = match pointee {
+            TypeRef::Primitive(PrimitiveKind::Void) | TypeRef::Primitive(PrimitiveKind::NoType) |
+            TypeRef::Function | TypeRef::Unknown => String::from("core::ffi::c_void"),
+            pointee => self.type_name(pointee)
+        };
+        match constant {
+            true => format!("*const {}", target),
+            false => format!("*mut {}", target)
+        }
+    }
+
+    fn pad(&self, body: &mut Body, offset: u64, alignment: u64, cursor: &mut u64, base: u64) {
+        if let Some(size) = layout::padding(*cursor, offset, alignment) {
+            let name = body.names.unique(format!("_padding_{:#x}", *cursor - base));
+            body.members.push(format!("pub {}: [u8; {:#x}],", name, size));
+            *cursor = offset;
+        }
+    }
+
+    fn add_accessors(&self, body: &mut Body, storage: &str, field: &Field, union: bool) {
+        let (kind, bitfield) = match (self.layouts.storage_kind(&field.typ), field.bitfield) {
+            (Some(kind), Some(bitfield)) if is_integer(kind) => (kind, bitfield),
+            _ => return
+        };
+
+        let integer = rust_primitive(kind).unwrap_or_default();
+        let bits = layout::primitive_size(kind) * 8;
+        let (position, length) = (u64::from(bitfield.position), u64::from(bitfield.length));
+        let mask = (u128::MAX >> (128 - length)) << position;
+        let mask = match is_signed(kind) {
+            true => format!("({:#x}u{} as {})", mask, bits, integer),
+            false => format!("{:#x}", mask)
+        };
+        let read = match union {
+            true => format!("unsafe {{ self.{} }}", storage),
+            false => format!("self.{}", storage)
+        };
+
+        // Shifting left then right sign extends signed bitfields
+        let mut value = String::from("storage");
+        if bits - position - length > 0 {
+            value = format!("({} << {})", value, bits - position - length);
+        }
+        if bits - length > 0 {
+            value = format!("{} >> {}", value, bits - length);
+        }
+        let (value_type, value, raw) = match field.typ {
+            TypeRef::Enum(index) => {
+                let name = self.type_identifier(index);
+                (name.clone(), format!("{}({})", name, value), "value.0")
+            },
+            _ => (String::from(integer), value, "value")
+        };
+        let shifted = match position {
+            0 => String::from(raw),
+            position => format!("({} << {})", raw, position)
+        };
+
+        let name = rust_identifier(&field.name);
+        if !body.accessors.is_empty() {
+            body.accessors.push(String::new());
+        }
+        body.accessors.extend(vec![
+            format!("pub fn {}(&self) -> {} {{", name, value_type),
+            format!("{}let storage = {};", INDENT, read),
+            format!("{}{}", INDENT, value),
+            String::from("}"),
+            String::new(),
+            format!("pub fn set_{}(&mut self, value: {}) {{", c_identifier(&field.name), value_type),
+            format!("{}let storage = {};", INDENT, read),
+            format!("{}self.{} = (storage & !{}) | ({} & {});", INDENT, storage, mask, shifted, mask),
+            String::from("}"),
+        ]);
+    }
+
+    /*
+    Declares a field (the storage unit for bitfields).
+
+    RETURN
+    returns the member name.
+    */
+    fn add_field(&self, body: &mut Body, field: &Field, base: u64, union: bool) -> String {
+        let offset = field.offset - base;
+        let storage_kind = field.bitfield.and_then(|_| self.layouts.storage_kind(&field.typ));
+        let (name, typ) = match storage_kind {
+            Some(kind) => (body.names.unique(format!("_bitfield_{:#x}", offset)), self.type_name(&TypeRef::Primitive(kind))),
+            None => (body.names.unique(rust_identifier(&field.name)), self.type_name(&field.typ))
+        };
+
+        body.members.push(format!("pub {}: {},", name, typ));
+        body.offsets.push((name.clone(), offset));
+        self.add_accessors(body, &name, field, union);
+        name
+    }
+
+    fn struct_body<'f>(&self, definition: &Definition<'f>, members: Vec<Member<'f>>, packing: Option<u64>, pending: &mut VecDeque<Definition<'f>>) -> Body {
+        let mut body = Body::default();
+        let alignment = self.layouts.members_alignment(&members, packing);
+        let base = definition.base;
+        let mut cursor = base;
+        let mut previous: Option<&Field> = None;
+        let mut storage = String::new();
+
+        for member in members {
+            match member {
+                Member::Field(field) => {
+                    if previous.is_some_and(|previous| layout::is_bitfield_continuation(previous, field)) {
+                        self.add_accessors(&mut body, &storage, field, false);
+                    } else {
+                        let alignment = self.layouts.align_of(&field.typ).min(packing.unwrap_or(u64::MAX));
+                        self.pad(&mut body, field.offset, alignment, &mut cursor, base);
+                        storage = self.add_field(&mut body, field, base, false);
+                    }
+                    cursor = cursor.max(self.layouts.field_end(field));
+                    previous = Some(field);
+                },
+                Member::Union(alternatives) => {
+                    let start = alternatives.first().map_or(cursor, |alternative| layout::members_start(alternative));
+                    let alignment = self.layouts.alternatives_alignment(&alternatives, packing);
+                    self.pad(&mut body, start, alignment, &mut cursor, base);
+
+                    let name = body.names.unique(format!("_union_{:#x}", start - base));
+                    let type_name = format!("{}{}", definition.name, name);
+                    body.members.push(format!("pub {}: {},", name, type_name));
+                    body.offsets.push((name, start - base));
+
+                    cursor = cursor.max(self.layouts.alternatives_end(&alternatives));
+                    pending.push_back(Definition {
+                        name: type_name,
+                        shape: Shape::Union(alternatives),
+                        base: start,
+                        size: None,
+                    });
+                    previous = None;
+                }
+            }
+        }
+
+        if let Some(size) = definition.size {
+            let end = cursor - base;
+            if let Some(padding) = layout::padding(end, size, alignment) {
+                let name = body.names.unique(format!("_padding_{:#x}", end));
+                body.members.push(format!("pub {}: [u8; {:#x}],", name, padding));
+            }
+        }
+        body
+    }
+
+    fn union_body<'f>(&self, definition: &Definition<'f>, alternatives: Vec<Vec<Member<'f>>>, packing: Option<u64>, pending: &mut VecDeque<Definition<'f>>) -> Body {
+        let mut body = Body::default();
+        let alignment = self.layouts.alternatives_alignment(&alternatives, packing);
+        let base = definition.base;
+        let mut end = base;
+
+        for (count, alternative) in alternatives.into_iter().enumerate() {
+            end = end.max(self.layouts.members_end(&alternative));
+            if let [Member::Field(field)] = alternative.as_slice() {
+                self.add_field(&mut body, field, base, true);
+                continue;
+            }
+
+            let name = body.names.unique(format!("_struct{}", count));
+            let type_name = format!("{}{}", definition.name, name);
+            body.members.push(format!("pub {}: {},", name, type_name));
+            body.offsets.push((name, 0));
+            pending.push_back(Definition {
+                name: type_name,
+                shape: Shape::Struct(alternative),
+                base,
+                size: None,
+            });
+        }
+
+        if let Some(size) = definition.size {
+            if layout::padding(end - base, size, alignment).is_some() {
+                let name = body.names.unique(String::from("_padding_0x0"));
+                body.members.push(format!("pub {}: [u8; {:#x}],", name, size));
+            }
+        }
+        body
+    }
+
+    fn write_definition<'f>(&mut self, mut definition: Definition<'f>, packing: Option<u64>, pending: &mut VecDeque<Definition<'f>>) {
+        let (keyword, body) = match std::mem::replace(&mut definition.shape, Shape::Struct(Vec::new())) {
+            Shape::Struct(members) => ("struct", self.struct_body(&definition, members, packing, pending)),
+            Shape::Union(alternatives) => ("union", self.union_body(&definition, alternatives, packing, pending))
+        };
+        let name = &definition.name;
+
+        match packing {
+            Some(1) => self.line(0, "#[repr(C, packed)]"),
+            Some(packing) => self.line(0, &format!("#[repr(C, packed({}))]", packing)),
+            None => self.line(0, "#[repr(C)]")
+        }
+        self.line(0, "#[derive(Clone, Copy)]");
+        self.line(0, &format!("pub {} {} {{", keyword, name));
+        for member in &body.members {
+            self.line(1, member);
+        }
+        self.line(0, "}");
+        self.line(0, "");
+
+        if !body.accessors.is_empty() {
+            self.line(0, &format!("impl {} {{", name));
+            for accessor in &body.accessors {
+                match accessor.as_str() {
+                    "" => self.line(0, ""),
+                    accessor => self.line(1, accessor)
+                }
+            }
+            self.line(0, "}");
+            self.line(0, "");
+        }
+
+        for (member, offset) in &body.offsets {
+            self.line(0, &format!("const _: () = assert!(core::mem::offset_of!({}, {}) == {:#x});", name, member, offset));
+        }
+        if let Some(size) = definition.size {
+            self.line(0, &format!("const _: () = assert!(core::mem::size_of::<{}>() == {:#x});", name, size));
+        }
+        self.line(0, "");
+    }
+
+    /*
+    Writes a struct / union, followed by the types of its anonymous members.
+    */
+    fn write_udt(&mut self, udt: &Udt) {
+        let fields = self.layouts.all_fields(udt);
+        let shape = match udt.kind {
+            UdtKind::Union => Shape::Union(self.layouts.union_alternatives(&fields)),
+            _ => Shape::Struct(self.layouts.group_members(&fields))
+        };
+
+        // Anonymous members are packed like the type they're declared in
+        let packing = self.layouts.packing(udt);
+        let mut pending = VecDeque::new();
+        pending.push_back(Definition {
+            name: self.type_identifier(udt.index),
+            shape,
+            base: 0,
+            size: Some(udt.size),
+        });
+        while let Some(definition) = pending.pop_front() {
+            self.write_definition(definition, packing, &mut pending);
+        }
+    }
+
+    fn write_enum(&mut self, index: TypeIndex) {
+        let enumeration = match self.layouts.enumeration(index) {
+            Some(enumeration) => enumeration,
+            None => return
+        };
+
+        let name = self.type_identifier(index);
+        self.line(0, "#[repr(transparent)]");
+        self.line(0, "#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]");
+        self.line(0, &format!("pub struct {}(pub {});", name, self.type_name(&TypeRef::Primitive(enumeration.underlying))));
+        self.line(0, "");
+
+        if !enumeration.values.is_empty() {
+            self.line(0, &format!("impl {} {{", name));
+            for (value_name, value) in &enumeration.values {
+                self.line(1, &format!("pub const {}: Self = Self({});", rust_identifier(value_name), layout::underlying_value(*value, enumeration.underlying)));
+            }
+            self.line(0, "}");
+            self.line(0, "");
+        }
+    }
+
+    fn write_opaque(&mut self, name: &str) {
+        self.line(0, "#[repr(C)]");
+        self.line(0, &format!("pub struct {} {{", name));
+        self.line(1, "_opaque: [u8; 0],");
+        self.line(0, "}");
+        self.line(0, "");
+    }
+}
+
+/*
+Generates #[repr(C)] Rust definitions for a set of types.
+
+@layouts         => Type layouts of the PDB.
+@roots           => Types to generate (and everything they reference).
+@follow_pointers => Also define the types that are only pointed at. Otherwise raw pointers point
+                    at opaque types.
+@raw_pointers    => Write pointers as raw pointers, or as integers of the target's pointer size
+                    (u64 / u32) when they hold addresses of another address space.
+@pdb_name        => PDB the types come from (for the header comment).
+
+RETURN
+returns the Rust source, meant to be a module of its own.
+*/
+pub fn generate(layouts: &TypeLayouts, roots: &[TypeIndex], follow_pointers: bool, raw_pointers: bool, pdb_name: &str) -> String {
+    let mut writer = BindingsWriter {
+        layouts,
+        raw_pointers,
+        out: String::new(),
+    };
+
+    let closure = layouts.type_closure(roots, follow_pointers, false);
+
+    writer.line(0, &format!("// Generated by pymspdb from {}", pdb_name));
+    writer.line(0, "");
+    writer.line(0, "#![allow(non_camel_case_types, non_snake_case, non_upper_case_globals, dead_code)]");
+    writer.line(0, "");
+    if raw_pointers {
+        writer.line(0, &format!("const _: () = assert!(core::mem::size_of::<usize>() == {});", layouts.pointer_size()));
+        writer.line(0, "");
+
+        // Opaque types for what's pointed at but not defined
+        let mut declared: HashSet<String> = closure.definitions.iter().map(|&index| writer.type_identifier(index)).collect();
+        for typ in &closure.pointed {
+            let name = match typ {
+                TypeRef::Udt(index) => writer.type_identifier(*index),
+                TypeRef::Declared { name, .. } => rust_identifier(name),
+                _ => continue
+            };
+            if declared.insert(name.clone()) {
+                writer.write_opaque(&name);
+            }
+        }
+    }
+
+    for &index in &closure.definitions {
+        match layouts.udt(index) {
+            Some(udt) => writer.write_udt(udt),
+            None => writer.write_enum(index)
+        }
+    }
+
+    writer.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{kernel_layouts, kernel_types};
+
+    fn bindings(roots: &[&str], follow_pointers: bool, raw_pointers: bool) -> String {
+        let layouts = kernel_layouts();
+        let roots = kernel_types(&layouts, roots);
+        generate(&layouts, &roots, follow_pointers, raw_pointers, "kernel.pdb")
+    }
+
+    #[test]
+    fn keeps_every_bitfield_storage_unit() {
+        let bindings = bindings(&["_MIX"], false, false);
+        assert!(bindings.contains("pub struct _MIX {\n    pub _bitfield_0x0: u8,\n    pub _bitfield_0x4: u32,\n    pub _bitfield_0x8: u8,\n}"));
+        assert!(bindings.contains("const _: () = assert!(core::mem::offset_of!(_MIX, _bitfield_0x4) == 0x4);"));
+        assert!(bindings.contains("const _: () = assert!(core::mem::size_of::<_MIX>() == 0xc);"));
+        assert!(bindings.contains("    pub fn set_b(&mut self, value: u32) {\n        let storage = self._bitfield_0x4;\n"));
+    }
+
+    #[test]
+    fn names_anonymous_unions() {
+        let bindings = bindings(&["_EPROCESS"], false, false);
+        assert!(bindings.contains("    pub _union_0x88: _EPROCESS_union_0x88,\n"));
+        assert!(bindings.contains("pub union _EPROCESS_union_0x88 {\n    pub CrossThreadFlags: u32,\n    pub _struct1: _EPROCESS_union_0x88_struct1,\n}"));
+        assert!(bindings.contains("    pub fn Spare(&self) -> u32 {\n        let storage = self._bitfield_0x0;\n        storage >> 1\n    }"));
+        assert!(bindings.contains("    pub Peb: u64,\n"));
+        assert!(bindings.contains("    pub Matrix: [[u16; 3]; 2],\n"));
+    }
+
+    #[test]
+    fn writes_packed_types_and_enums() {
+        let bindings = bindings(&["_PACKED_HEADER", "_PS_STATE"], false, false);
+        assert!(bindings.contains("#[repr(C, packed)]\n#[derive(Clone, Copy)]\npub struct _PACKED_HEADER {"));
+        assert!(bindings.contains("pub struct _PS_STATE(pub i32);"));
+        assert!(bindings.contains("    pub const PsTerminated: Self = Self(-2147483648);\n"));
+    }
+
+    #[test]
+    fn uses_raw_pointers_when_asked() {
+        let bindings = bindings(&["_ETHREAD"], false, true);
+        assert!(bindings.contains("const _: () = assert!(core::mem::size_of::<usize>() == 8);"));
+        assert!(bindings.contains("    pub ThreadsProcess: *mut _EPROCESS,\n"));
+        assert!(bindings.contains("pub struct _EPROCESS {\n    _opaque: [u8; 0],\n}"));
+        assert!(bindings.contains("    pub Flink: *mut _LIST_ENTRY,\n"));
+    }
+}
@@ -0,0 +1,850 @@
+use std::collections::HashSet;
+use pyo3::prelude::*;
+use pyo3::exceptions::*;
+use pyo3::types::{PyDict, PyList};
+use crate::ErrorHandler;
+use crate::build_info::{self, ModuleBuildInfo};
+use crate::c_header;
+use crate::compilands::{self, Compiland};
+use crate::ctypes_classes;
+use crate::dt_format;
+use crate::field_search;
+use crate::isf;
+use crate::layout::{PathSegment, TypeLayouts};
+use crate::lines::{LineRecord, LineTable};
+use crate::offset_constants;
+use crate::rust_bindings;
+use crate::symbolize::{SymbolMap, Symbolized};
+use crate::symbols::{self, GlobalSymbol, SymbolKind};
+use crate::type_export;
+use crate::type_graph;
+use crate::variables::{self, FunctionQuery, Variable};
+
+/*
+ *
+ * PDB SESSION
+ *
+ * Keeps whatever was parsed out of a PDB file around between calls, so lookups don't have to
+ * walk every stream again. The file itself is reopened when a new stream is needed.
+ *
+ */
+
+#[pyclass]
+pub struct Session {
+    pdb_name: String,
+    symbols: Option<Vec<GlobalSymbol>>,
+    symbol_map: Option<SymbolMap>,
+    line_table: Option<LineTable>,
+    compilands: Option<Vec<Compiland>>,
+    layouts: Option<TypeLayouts>,
+}
+
+impl Session {
+    fn cached_symbols(&mut self) -> PyResult<&[GlobalSymbol]> {
+        if self.symbols.is_none() {
+            self.symbols = Some(symbols::collect_symbols(&self.pdb_name)?);
+        }
+        Ok(self.symbols.as_deref().unwrap_or_default())
+    }
+
+    fn cached_symbol_map(&mut self) -> PyResult<&SymbolMap> {
+        if self.symbol_map.is_none() {
+            self.cached_symbols()?;
+            let symbol_map = SymbolMap::build(&self.pdb_name, self.symbols.as_deref().unwrap_or_default())?;
+            self.symbol_map = Some(symbol_map);
+        }
+        Ok(self.symbol_map.as_ref().unwrap())
+    }
+
+    fn cached_line_table(&mut self) -> PyResult<&LineTable> {
+        if self.line_table.is_none() {
+            self.line_table = Some(LineTable::build(&self.pdb_name)?);
+        }
+        Ok(self.line_table.as_ref().unwrap())
+    }
+
+    fn cached_compilands(&mut self) -> PyResult<&[Compiland]> {
+        if self.compilands.is_none() {
+            self.compilands = Some(compilands::collect_compilands(&self.pdb_name)?);
+        }
+        Ok(self.compilands.as_deref().unwrap_or_default())
+    }
+
+    fn pdb_file_name(&self) -> String {
+        std::path::Path::new(&self.pdb_name).file_name()
+                                            .map(|name| name.to_string_lossy().into_owned())
+                                            .unwrap_or_else(|| self.pdb_name.clone())
+    }
+
+    fn cached_layouts(&mut self) -> PyResult<&TypeLayouts> {
+        if self.layouts.is_none() {
+            self.layouts = Some(TypeLayouts::load(&self.pdb_name)?);
+        }
+        Ok(self.layouts.as_ref().unwrap())
+    }
+
+    /*
+    Resolves type names (a single name or a list of them) to their definitions.
+    */
+    fn type_indexes(&mut self, names: &PyAny) -> PyResult<Vec<pdb::TypeIndex>> {
+        let names: Vec<String> = match names.extract::<String>() {
+            Ok(name) => vec![name],
+            Err(_) => names.extract()?
+        };
+
+        let layouts = self.cached_layouts()?;
+        names.iter()
+             .map(|name| layouts.find(name).ok_or_else(|| KeyError::py_err(format!("[pymspdb] No type named {}", name))))
+             .collect()
+    }
+
+    /*
+    Global variables and functions with the types they use, for the type graph. Type only PDBs
+    have none.
+    */
+    fn symbol_uses(&mut self) -> PyResult<Vec<type_graph::SymbolUse>> {
+        if self.cached_symbols().is_err() {
+            return Ok(Vec::new());
+        }
+        let mut seen = HashSet::new();
+        let symbols: Vec<(String, SymbolKind, pdb::TypeIndex)> = self.symbols.as_deref().unwrap_or_default().iter()
+            .filter(|symbol| symbol.kind == SymbolKind::Data || symbol.kind == SymbolKind::Function)
+            .filter_map(|symbol| symbol.type_index.map(|type_index| (symbol.name.clone(), symbol.kind, type_index)))
+            .filter(|(name, kind, _)| seen.insert((name.clone(), *kind)))
+            .collect();
+
+        let pdb_name = self.pdb_name.clone();
+        let indexes: Vec<pdb::TypeIndex> = symbols.iter().map(|(_, _, type_index)| *type_index).collect();
+        let types = self.cached_layouts()?.symbol_types(&pdb_name, &indexes)?;
+        Ok(symbols.into_iter().zip(types).map(|((name, kind, _), types)| type_graph::SymbolUse { name, kind, types }).collect())
+    }
+
+    fn build_type_graph(&mut self, typ: &str, depth: usize, direction: &str, symbols: bool) -> PyResult<type_graph::TypeGraph> {
+        let direction = type_graph::Direction::parse(direction)
+                                             .ok_or_else(|| ValueError::py_err(format!("[pymspdb] Unknown direction {} (in, out, both)", direction)))?;
+        let symbol_uses = match symbols {
+            true => self.symbol_uses()?,
+            false => Vec::new()
+        };
+        let layouts = self.cached_layouts()?;
+        let root = layouts.find(typ).ok_or_else(|| KeyError::py_err(format!("[pymspdb] No type named {}", typ)))?;
+        Ok(type_graph::build(layouts, root, &symbol_uses, depth, direction))
+    }
+}
+
+/*
+Converts a symbol into the dict handed to python.
+*/
+fn symbol_to_dict<'p>(py: Python<'p>, symbol: &GlobalSymbol) -> &'p PyDict {
+    let dict = PyDict::new(py);
+    dict.set_item("name", &symbol.name).handle_properly();
+    dict.set_item("demangled", &symbol.demangled).handle_properly();
+    dict.set_item("kind", symbol.kind.name()).handle_properly();
+    dict.set_item("global", symbol.global).handle_properly();
+    dict.set_item("section", symbol.section).handle_properly();
+    dict.set_item("offset", symbol.offset).handle_properly();
+    dict.set_item("rva", symbol.rva).handle_properly();
+    dict.set_item("size", symbol.size).handle_properly();
+    dict.set_item("type", &symbol.type_name).handle_properly();
+    dict.set_item("value", symbol.value).handle_properly();
+    dict.set_item("module", &symbol.module).handle_properly();
+    dict
+}
+
+/*
+Converts a symbolize result into the dict handed to python (None if nothing matched).
+*/
+fn symbolized_to_object(py: Python, symbol_map: &SymbolMap, symbolized: Option<Symbolized>) -> PyObject {
+    let symbolized = match symbolized {
+        Some(symbolized) => symbolized,
+        None => return py.None()
+    };
+
+    let dict = PyDict::new(py);
+    dict.set_item("name", &symbolized.entry.name).handle_properly();
+    dict.set_item("kind", symbolized.entry.kind.name()).handle_properly();
+    dict.set_item("rva", symbolized.entry.rva).handle_properly();
+    dict.set_item("size", symbolized.entry.size).handle_properly();
+    dict.set_item("offset", symbolized.offset).handle_properly();
+    dict.set_item("display", symbol_map.display(&symbolized)).handle_properly();
+    dict.to_object(py)
+}
+
+fn lines_to_list<'p>(py: Python<'p>, records: Vec<&LineRecord>) -> &'p PyList {
+    let list = PyList::empty(py);
+    for record in records {
+        let dict = PyDict::new(py);
+        dict.set_item("rva", record.rva).handle_properly();
+        dict.set_item("length", record.length).handle_properly();
+        dict.set_item("file", &record.file).handle_properly();
+        dict.set_item("line_start", record.line_start).handle_properly();
+        dict.set_item("line_end", record.line_end).handle_properly();
+        dict.set_item("column_start", record.column_start).handle_properly();
+        dict.set_item("column_end", record.column_end).handle_properly();
+        dict.set_item("inlinee", &record.inlinee).handle_properly();
+        dict.set_item("depth", record.depth).handle_properly();
+        list.append(dict).handle_properly();
+    }
+    list
+}
+
+fn compiland_to_dict<'p>(py: Python<'p>, compiland: &Compiland) -> &'p PyDict {
+    let contributions = PyList::empty(py);
+    for contribution in &compiland.contributions {
+        let dict = PyDict::new(py);
+        dict.set_item("section", contribution.section).handle_properly();
+        dict.set_item("offset", contribution.offset).handle_properly();
+        dict.set_item("rva", contribution.rva).handle_properly();
+        dict.set_item("size", contribution.size).handle_properly();
+        dict.set_item("characteristics", contribution.characteristics).handle_properly();
+        contributions.append(dict).handle_properly();
+    }
+
+    let dict = PyDict::new(py);
+    dict.set_item("index", compiland.index).handle_properly();
+    dict.set_item("name", &compiland.name).handle_properly();
+    dict.set_item("library", &compiland.library).handle_properly();
+    dict.set_item("contributions", contributions).handle_properly();
+    dict.set_item("source_files", &compiland.source_files).handle_properly();
+    dict
+}
+
+fn variables_to_list<'p>(py: Python<'p>, variables: &[Variable]) -> &'p PyList {
+    let list = PyList::empty(py);
+    for variable in variables {
+        let locations = PyList::empty(py);
+        for location in &variable.locations {
+            let dict = PyDict::new(py);
+            dict.set_item("location", &location.description).handle_properly();
+            dict.set_item("rva", location.rva).handle_properly();
+            dict.set_item("length", location.length).handle_properly();
+            locations.append(dict).handle_properly();
+        }
+
+        let dict = PyDict::new(py);
+        dict.set_item("name", &variable.name).handle_properly();
+        dict.set_item("type", &variable.type_name).handle_properly();
+        dict.set_item("locations", locations).handle_properly();
+        list.append(dict).handle_properly();
+    }
+    list
+}
+
+fn build_info_to_dict<'p>(py: Python<'p>, module: &ModuleBuildInfo) -> &'p PyDict {
+    let compiler = match &module.compiler {
+        Some(compiler) => {
+            let dict = PyDict::new(py);
+            dict.set_item("language", &compiler.language).handle_properly();
+            dict.set_item("machine", &compiler.machine).handle_properly();
+            dict.set_item("frontend_version", &compiler.frontend_version).handle_properly();
+            dict.set_item("backend_version", &compiler.backend_version).handle_properly();
+            dict.set_item("version_string", &compiler.version_string).handle_properly();
+            dict.set_item("flags", &compiler.flags).handle_properly();
+            dict.to_object(py)
+        },
+        None => py.None()
+    };
+
+    let environment = PyDict::new(py);
+    for (key, value) in &module.environment {
+        environment.set_item(key, value).handle_properly();
+    }
+    let arguments = PyDict::new(py);
+    for (key, value) in &module.build_info {
+        arguments.set_item(key, value).handle_properly();
+    }
+
+    let dict = PyDict::new(py);
+    dict.set_item("module", &module.module).handle_properly();
+    dict.set_item("compiler", compiler).handle_properly();
+    dict.set_item("environment", environment).handle_properly();
+    dict.set_item("build_info", arguments).handle_properly();
+    dict
+}
+
+fn symbols_to_list<'p, 's>(py: Python<'p>, symbols: impl Iterator<Item = &'s GlobalSymbol>) -> &'p PyList {
+    let list = PyList::empty(py);
+    for symbol in symbols {
+        list.append(symbol_to_dict(py, symbol)).handle_properly();
+    }
+    list
+}
+
+#[pymethods]
+impl Session {
+    #[new]
+    fn new(pdb_name: String) -> PyResult<Self> {
+        // Fail right away on files that aren't PDBs
+        crate::open_pdb_and_parse(&pdb_name)?;
+
+        Ok(Session {
+            pdb_name,
+            symbols: None,
+            symbol_map: None,
+            line_table: None,
+            compilands: None,
+            layouts: None,
+        })
+    }
+
+    #[getter]
+    fn pdb_name(&self) -> &str {
+        &self.pdb_name
+    }
+
+    /*
+    Looks up symbols by name.
+
+    @name      => Symbol name (e.g PsActiveProcessHead).
+    @exact     => Exact (case sensitive) match, otherwise any symbol containing name (case insensitive).
+    @demangled => Match C++ symbols by their qualified name (e.g "Bar::Foo") instead of the decorated
+                  one. An exact match also accepts a trailing part of the qualified name, so "Foo"
+                  or "Bar::Foo" both find "Ns::Bar::Foo".
+
+    RETURN
+    returns a list of symbol dicts (name, demangled, kind, global, section, offset, rva, size, type,
+    value, module).
+    */
+    #[args(exact = "true", demangled = "false")]
+    fn find_symbols<'p>(&mut self, py: Python<'p>, name: &str, exact: bool, demangled: bool) -> PyResult<&'p PyList> {
+        let lowercase_name = name.to_lowercase();
+        let scoped_name = format!("::{}", name);
+        let symbols = self.cached_symbols()?;
+
+        Ok(symbols_to_list(py, symbols.iter().filter(|symbol| {
+            let symbol_name = match (demangled, &symbol.qualified_name) {
+                (true, Some(qualified_name)) => qualified_name,
+                _ => &symbol.name
+            };
+
+            if exact {
+                symbol_name == name || (demangled && symbol_name.ends_with(&scoped_name))
+            } else {
+                symbol_name.to_lowercase().contains(&lowercase_name)
+            }
+        })))
+    }
+
+    /*
+    Enumerates symbols.
+
+    @kind => One of "public", "function", "data", "constant". If none, every symbol is returned.
+    */
+    #[args(kind = "None")]
+    fn symbols<'p>(&mut self, py: Python<'p>, kind: Option<&str>) -> PyResult<&'p PyList> {
+        let kind = match kind {
+            Some(kind) => Some(SymbolKind::from_name(kind)
+                               .ok_or_else(|| ValueError::py_err(format!("[pymspdb] Unknown symbol kind {}", kind)))?),
+            None => None
+        };
+        let symbols = self.cached_symbols()?;
+
+        Ok(symbols_to_list(py, symbols.iter().filter(|symbol| kind.is_none() || Some(symbol.kind) == kind)))
+    }
+
+    /*
+    Resolves addresses into the nearest function / public symbol.
+
+    @rvas => A single RVA, or a list of them.
+
+    RETURN
+    returns a dict (name, kind, rva, size, offset, display) or None per address, as a list for
+    a list of addresses.
+    */
+    fn symbolize(&mut self, py: Python, rvas: &PyAny) -> PyResult<PyObject> {
+        let symbol_map = self.cached_symbol_map()?;
+
+        if let Ok(rva) = rvas.extract::<u32>() {
+            return Ok(symbolized_to_object(py, symbol_map, symbol_map.lookup(rva)));
+        }
+
+        let rvas: Vec<u32> = rvas.extract()
+                                 .map_err(|_| TypeError::py_err("[pymspdb] symbolize expects an RVA or a list of RVAs"))?;
+        let list = PyList::empty(py);
+        for rva in rvas {
+            list.append(symbolized_to_object(py, symbol_map, symbol_map.lookup(rva))).handle_properly();
+        }
+        Ok(list.to_object(py))
+    }
+
+    /*
+    Finds the source lines of an address, using the line information of the module streams.
+
+    @rva => Address relative to the image base.
+
+    RETURN
+    returns a list of line dicts (rva, length, file, line_start, line_end, column_start,
+    column_end, inlinee, depth), innermost inlined function first.
+    */
+    fn lines_at<'p>(&mut self, py: Python<'p>, rva: u32) -> PyResult<&'p PyList> {
+        let line_table = self.cached_line_table()?;
+
+        Ok(lines_to_list(py, line_table.lines_at(rva)))
+    }
+
+    /*
+    Finds the code ranges generated for a source line.
+
+    @file => Source file, the full path or only its trailing part (e.g "driver.c").
+    @line => Line number.
+
+    RETURN
+    returns a list of line dicts (see lines_at), sorted by RVA.
+    */
+    fn line_ranges<'p>(&mut self, py: Python<'p>, file: &str, line: u32) -> PyResult<&'p PyList> {
+        let line_table = self.cached_line_table()?;
+
+        Ok(lines_to_list(py, line_table.ranges_for_line(file, line)))
+    }
+
+    /*
+    Lists the compilands (DBI modules) the image was linked from.
+
+    RETURN
+    returns a list of dicts (index, name, library, contributions, source_files), where every
+    contribution is a dict (section, offset, rva, size, characteristics).
+    */
+    fn compilands<'p>(&mut self, py: Python<'p>) -> PyResult<&'p PyList> {
+        let list = PyList::empty(py);
+        for compiland in self.cached_compilands()? {
+            list.append(compiland_to_dict(py, compiland)).handle_properly();
+        }
+        Ok(list)
+    }
+
+    /*
+    Finds the compiland an address was produced by (e.g which .lib pulled in a function).
+
+    @rva => Address relative to the image base.
+
+    RETURN
+    returns a compiland dict (see compilands), or None.
+    */
+    fn compiland_at(&mut self, py: Python, rva: u32) -> PyResult<PyObject> {
+        let compilands = self.cached_compilands()?;
+
+        Ok(match compilands::compiland_at(compilands, rva) {
+            Some(compiland) => compiland_to_dict(py, compiland).to_object(py),
+            None => py.None()
+        })
+    }
+
+    /*
+    Lists the parameters and locals of a function.
+
+    @function => Function name (exact, as in find_symbols), or an RVA inside the function.
+
+    RETURN
+    returns a dict (function, rva, parameters, locals) where every variable is a dict
+    (name, type, locations) and every location a dict (location, rva, length). The location's
+    rva / length are None when it holds for the whole function. Returns None if there's no
+    such function (or the PDB has no private symbols).
+    */
+    fn variables(&self, py: Python, function: &PyAny) -> PyResult<PyObject> {
+        let name: Option<String> = function.extract().ok();
+        let query = match &name {
+            Some(name) => FunctionQuery::Name(name),
+            None => FunctionQuery::Rva(function.extract()
+                                               .map_err(|_| TypeError::py_err("[pymspdb] variables expects a function name or an RVA"))?)
+        };
+
+        let result = match variables::function_variables(&self.pdb_name, query)? {
+            Some(result) => result,
+            None => return Ok(py.None())
+        };
+
+        let dict = PyDict::new(py);
+        dict.set_item("function", &result.function).handle_properly();
+        dict.set_item("rva", result.rva).handle_properly();
+        dict.set_item("parameters", variables_to_list(py, &result.parameters)).handle_properly();
+        dict.set_item("locals", variables_to_list(py, &result.locals)).handle_properly();
+        Ok(dict.to_object(py))
+    }
+
+    /*
+    Lists how every module was built: compiler (S_COMPILE3), environment block (S_ENVBLOCK) and
+    build information (LF_BUILDINFO). Useful to check for /GS, /guard:cf or /Qspectre.
+
+    RETURN
+    returns a list of dicts (module, compiler, environment, build_info). compiler is None or a
+    dict (language, machine, frontend_version, backend_version, version_string, flags),
+    environment maps S_ENVBLOCK keys (cwd, exe, pdb, cmd, ...) to values and build_info holds
+    the LF_BUILDINFO arguments present (cwd, tool, source, pdb, args).
+    */
+    fn build_info<'p>(&self, py: Python<'p>) -> PyResult<&'p PyList> {
+        let list = PyList::empty(py);
+        for module in build_info::collect_build_info(&self.pdb_name)? {
+            list.append(build_info_to_dict(py, &module)).handle_properly();
+        }
+        Ok(list)
+    }
+
+    /*
+    Generates a compilable C header for types and everything they reference.
+
+    @types           => Type name, or a list of them (e.g ["_EPROCESS", "_ETHREAD"]).
+    @follow_pointers => Also define the types that are only pointed at (otherwise they're only
+                        forward declared).
+
+    RETURN
+    returns the header text.
+    */
+    #[args(follow_pointers = "true")]
+    fn c_header(&mut self, types: &PyAny, follow_pointers: bool) -> PyResult<String> {
+        let roots = self.type_indexes(types)?;
+        let pdb_file = self.pdb_file_name();
+        let layouts = self.cached_layouts()?;
+
+        Ok(c_header::generate(layouts, &roots, follow_pointers, &pdb_file))
+    }
+
+    /*
+    Prints a type like WinDbg's dt (   +0x440 UniqueProcessId  : Ptr64 Void).
+
+    @typ    => Struct / union / enum name, optionally prefixed with the module (nt!_EPROCESS).
+    @fields => Field name wildcard, or a list of them (e.g "Image*"), like dt nt!_EPROCESS Image*.
+    @depth  => Levels of embedded structs to expand, like dt -r<depth>.
+
+    RETURN
+    returns the dt text.
+    */
+    #[args(fields = "None", depth = "0")]
+    fn dt(&mut self, typ: &str, fields: Option<&PyAny>, depth: usize) -> PyResult<String> {
+        let patterns: Vec<String> = match fields {
+            Some(fields) => match fields.extract::<String>() {
+                Ok(field) => vec![field],
+                Err(_) => fields.extract()?
+            },
+            None => Vec::new()
+        };
+        let (module, name) = match typ.find('!') {
+            Some(bang) => (String::from(&typ[..bang]), &typ[bang + 1..]),
+            None => {
+                let pdb_file = self.pdb_file_name();
+                let stem = pdb_file.rsplit_once('.').map_or(pdb_file.as_str(), |(stem, _)| stem);
+                (String::from(stem), typ)
+            }
+        };
+
+        let layouts = self.cached_layouts()?;
+        let index = layouts.find(name).ok_or_else(|| KeyError::py_err(format!("[pymspdb] No type named {}", name)))?;
+        Ok(dt_format::format(layouts, index, &module, &patterns, depth))
+    }
+
+    /*
+    Resolves a field path: fields of embedded structs (_EPROCESS.Pcb.DirectoryTableBase), array
+    elements (_KPCR.Prcb.VectorToInterruptObject[3]) and fields behind pointers (->).
+
+    @path => Field path.
+
+    RETURN
+    returns {"path", "base", "offset", "size", "type", "bit_position", "bit_length", "breaks"}.
+    offset is from the start of base: the path's type, or the type the last -> points to. breaks
+    are the pointer fields the path dereferences, in order, each {"path", "base", "offset", "size",
+    "type"}; when it's empty offset is absolute.
+    */
+    fn resolve_path(&mut self, py: Python, path: &str) -> PyResult<PyObject> {
+        let layouts = self.cached_layouts()?;
+        let mut segments = layouts.resolve_path(path)?;
+        let target = segments.pop().unwrap();
+
+        let segment_to_dict = |segment: &PathSegment| {
+            let dict = PyDict::new(py);
+            dict.set_item("path", &segment.field.name).handle_properly();
+            dict.set_item("base", &segment.base).handle_properly();
+            dict.set_item("offset", segment.field.offset).handle_properly();
+            dict.set_item("size", layouts.size_of(&segment.field.typ)).handle_properly();
+            dict.set_item("type", dt_format::type_name(layouts, &segment.field.typ)).handle_properly();
+            dict
+        };
+
+        let breaks = PyList::empty(py);
+        for segment in &segments {
+            breaks.append(segment_to_dict(segment))?;
+        }
+        let dict = segment_to_dict(&target);
+        dict.set_item("bit_position", target.field.bitfield.map(|bitfield| bitfield.position)).handle_properly();
+        dict.set_item("bit_length", target.field.bitfield.map(|bitfield| bitfield.length)).handle_properly();
+        dict.set_item("breaks", breaks).handle_properly();
+        Ok(dict.to_object(py))
+    }
+
+    /*
+    Finds the fields covering a byte of a type (which _EPROCESS member is [rcx+2E8h]), through
+    nested structs, unions and arrays.
+
+    @typ    => Struct / union name.
+    @offset => Byte offset into the type.
+
+    RETURN
+    returns a list of {"path", "offset", "size", "type", "delta", "bit_position", "bit_length"}, one per
+    union alternative covering the byte (empty for padding). delta is the byte's offset into the
+    innermost field, offset the field's offset from the start of the type.
+    */
+    fn field_at(&mut self, py: Python, typ: &str, offset: u64) -> PyResult<PyObject> {
+        let layouts = self.cached_layouts()?;
+        let index = layouts.find(typ).ok_or_else(|| KeyError::py_err(format!("[pymspdb] No type named {}", typ)))?;
+
+        let list = PyList::empty(py);
+        for (field, delta) in layouts.fields_at(index, offset) {
+            let dict = PyDict::new(py);
+            dict.set_item("path", &field.name).handle_properly();
+            dict.set_item("offset", field.offset).handle_properly();
+            dict.set_item("size", layouts.size_of(&field.typ)).handle_properly();
+            dict.set_item("type", dt_format::type_name(layouts, &field.typ)).handle_properly();
+            dict.set_item("delta", delta).handle_properly();
+            dict.set_item("bit_position", field.bitfield.map(|bitfield| bitfield.position)).handle_properly();
+            dict.set_item("bit_length", field.bitfield.map(|bitfield| bitfield.length)).handle_properly();
+            list.append(dict)?;
+        }
+        Ok(list.to_object(py))
+    }
+
+    /*
+    Searches every struct / union for fields by name and / or by type, e.g every struct with an
+    ActiveProcessLinks member, or every struct embedding a _LIST_ENTRY or pointing to an _ETHREAD.
+
+    @name       => Field name (dt wildcards, e.g "*ProcessLinks").
+    @field_type => Struct / union / enum name the field embeds or points to (dt wildcards).
+    @embeds     => With field_type, match fields holding the type by value (or arrays of it).
+    @points     => With field_type, match fields pointing to the type.
+
+    RETURN
+    returns a list of {"type", "path", "offset", "field_type", "relation"} ("embeds" / "points",
+    None without field_type).
+    */
+    #[args(name = "None", field_type = "None", embeds = "true", points = "true")]
+    fn find_fields(&mut self, py: Python, name: Option<&str>, field_type: Option<&str>, embeds: bool, points: bool) -> PyResult<PyObject> {
+        if name.is_none() && field_type.is_none() {
+            return Err(ValueError::py_err("[pymspdb] find_fields needs a name and / or a field_type"));
+        }
+        let layouts = self.cached_layouts()?;
+        let query = field_search::FieldQuery { name, field_type, embeds, points };
+
+        let list = PyList::empty(py);
+        for found in field_search::search(layouts, &query) {
+            let dict = PyDict::new(py);
+            dict.set_item("type", &found.type_name).handle_properly();
+            dict.set_item("path", &found.field.name).handle_properly();
+            dict.set_item("offset", found.field.offset).handle_properly();
+            dict.set_item("field_type", dt_format::type_name(layouts, &found.field.typ)).handle_properly();
+            dict.set_item("relation", found.relation.map(|relation| relation.name())).handle_properly();
+            list.append(dict)?;
+        }
+        Ok(list.to_object(py))
+    }
+
+    /*
+    Generates constants for the offsets, sizes and bit positions of a few fields, annotated with
+    the PDB's GUID / age.
+
+    @fields   => Field path, or a list of them (e.g ["_EPROCESS.ActiveProcessLinks", "_EPROCESS.Token"]).
+    @language => "c" (#define), "cpp" (constexpr), "rust" (const) or "python".
+
+    RETURN
+    returns the source text.
+    */
+    #[args(language = "\"c\"")]
+    fn offset_constants(&mut self, fields: &PyAny, language: &str) -> PyResult<String> {
+        let paths: Vec<String> = match fields.extract::<String>() {
+            Ok(path) => vec![path],
+            Err(_) => fields.extract()?
+        };
+        let language = offset_constants::Language::parse(language)
+                                                   .ok_or_else(|| ValueError::py_err(format!("[pymspdb] Unknown language {} (c, cpp, rust, python)", language)))?;
+        let pdb_file = self.pdb_file_name();
+        let layouts = self.cached_layouts()?;
+
+        let resolved = paths.iter().map(|path| layouts.field_path(path)).collect::<PyResult<Vec<_>>>()?;
+        Ok(offset_constants::generate(layouts, &resolved, language, &pdb_file))
+    }
+
+    /*
+    Cross references of a type: the types embedding it / pointing to it, the ones it embeds /
+    points to, and the global variables and functions using them, out to depth hops.
+
+    @typ       => Struct / union / enum name.
+    @depth     => Hops to go from the type.
+    @direction => "in" (what reaches the type), "out" (what it reaches) or "both".
+    @symbols   => Include global variables and functions.
+
+    RETURN
+    returns {"root", "nodes": [{"name", "kind", "distance"}], "edges": [{"from", "from_symbol",
+    "to", "relation", "fields"}]}. kind is data / function for symbols, relation embeds / points.
+    */
+    #[args(depth = "1", direction = "\"both\"", symbols = "true")]
+    fn type_graph(&mut self, py: Python, typ: &str, depth: usize, direction: &str, symbols: bool) -> PyResult<PyObject> {
+        let graph = self.build_type_graph(typ, depth, direction, symbols)?;
+
+        let nodes = PyList::empty(py);
+        for node in &graph.nodes {
+            let dict = PyDict::new(py);
+            dict.set_item("name", &node.name).handle_properly();
+            dict.set_item("kind", node.kind).handle_properly();
+            dict.set_item("distance", node.distance).handle_properly();
+            nodes.append(dict)?;
+        }
+        let edges = PyList::empty(py);
+        for edge in &graph.edges {
+            let dict = PyDict::new(py);
+            dict.set_item("from", &edge.from).handle_properly();
+            dict.set_item("from_symbol", edge.from_symbol).handle_properly();
+            dict.set_item("to", &edge.to).handle_properly();
+            dict.set_item("relation", edge.relation.name()).handle_properly();
+            dict.set_item("fields", &edge.fields).handle_properly();
+            edges.append(dict)?;
+        }
+
+        let dict = PyDict::new(py);
+        dict.set_item("root", &graph.root).handle_properly();
+        dict.set_item("nodes", nodes).handle_properly();
+        dict.set_item("edges", edges).handle_properly();
+        Ok(dict.to_object(py))
+    }
+
+    /*
+    type_graph as Graphviz DOT or Mermaid source (embedding edges solid, pointer edges dashed).
+
+    @typ       => Struct / union / enum name.
+    @format    => "dot" or "mermaid".
+    @depth     => Hops to go from the type.
+    @direction => "in", "out" or "both".
+    @symbols   => Include global variables and functions.
+
+    RETURN
+    returns the graph source.
+    */
+    #[args(format = "\"dot\"", depth = "1", direction = "\"both\"", symbols = "true")]
+    fn type_graph_source(&mut self, typ: &str, format: &str, depth: usize, direction: &str, symbols: bool) -> PyResult<String> {
+        if format != "dot" && format != "mermaid" {
+            return Err(ValueError::py_err(format!("[pymspdb] Unknown graph format {} (dot, mermaid)", format)));
+        }
+        let graph = self.build_type_graph(typ, depth, direction, symbols)?;
+        Ok(match format {
+            "dot" => type_graph::to_dot(&graph),
+            _ => type_graph::to_mermaid(&graph)
+        })
+    }
+
+    /*
+    Exports type layouts as JSON (schema "pymspdb.types", see type_export.rs / the README).
+
+    @types           => Type name, a list of them, or None for every type of the TPI stream.
+    @closure         => Also export everything the types reference.
+    @follow_pointers => With closure, also export the types that are only pointed at.
+    @ndjson          => Newline delimited JSON: the document header, then a type per line.
+
+    RETURN
+    returns the JSON text.
+    */
+    #[args(types = "None", closure = "true", follow_pointers = "true", ndjson = "false")]
+    fn types_json(&mut self, types: Option<&PyAny>, closure: bool, follow_pointers: bool, ndjson: bool) -> PyResult<String> {
+        let roots = match types {
+            Some(types) => Some(self.type_indexes(types)?),
+            None => None
+        };
+        let pdb_file = self.pdb_file_name();
+        let layouts = self.cached_layouts()?;
+
+        let exported = match roots {
+            Some(roots) if closure => layouts.closure(&roots, follow_pointers),
+            Some(roots) => roots,
+            None => layouts.definitions()
+        };
+        Ok(type_export::export(layouts, &exported, &pdb_file, ndjson))
+    }
+
+    /*
+    Generates a Volatility 3 ISF (Intermediate Symbol Format) symbol table of the PDB: every user
+    type, enum and base type, the addresses of its global / public symbols, and the GUID / age
+    Volatility matches the image against.
+
+    RETURN
+    returns the ISF JSON text (save it as <pdb name>/<GUID><age>.json under volatility3/symbols/windows).
+    */
+    fn isf(&mut self) -> PyResult<String> {
+        let pdb_file = self.pdb_file_name();
+        // Type only PDBs have no symbol streams, their ISF just has no symbols
+        let has_symbols = self.cached_symbols().is_ok();
+        self.cached_layouts()?;
+
+        let symbols = match has_symbols {
+            true => self.symbols.as_deref().unwrap_or_default(),
+            false => &[]
+        };
+        let layouts = self.layouts.as_ref().unwrap();
+        Ok(isf::generate(layouts, symbols, &pdb_file))
+    }
+
+    /*
+    Generates python source defining ctypes.Structure / ctypes.Union classes for types and
+    everything they reference, laid out like the PDB's target (pointers are integers of its
+    pointer size).
+
+    @types           => Type name, or a list of them.
+    @follow_pointers => Also generate the types that are only pointed at.
+
+    RETURN
+    returns the source of a python module.
+    */
+    #[args(follow_pointers = "true")]
+    fn ctypes_source(&mut self, types: &PyAny, follow_pointers: bool) -> PyResult<String> {
+        let roots = self.type_indexes(types)?;
+        let pdb_file = self.pdb_file_name();
+        let layouts = self.cached_layouts()?;
+
+        Ok(ctypes_classes::generate(layouts, &roots, follow_pointers, &pdb_file))
+    }
+
+    /*
+    Builds the ctypes classes of ctypes_source in memory, e.g
+    session.ctypes_classes("_EPROCESS").from_buffer_copy(dump_bytes).
+
+    @types           => Type name, or a list of them.
+    @follow_pointers => Also generate the types that are only pointed at.
+
+    RETURN
+    returns the class of the type, as a list for a list of types.
+    */
+    #[args(follow_pointers = "true")]
+    fn ctypes_classes(&mut self, py: Python, types: &PyAny, follow_pointers: bool) -> PyResult<PyObject> {
+        let roots = self.type_indexes(types)?;
+        let pdb_file = self.pdb_file_name();
+        let layouts = self.cached_layouts()?;
+
+        let source = ctypes_classes::generate(layouts, &roots, follow_pointers, &pdb_file);
+        let globals = PyDict::new(py);
+        globals.set_item("__name__", "pymspdb_ctypes")?;
+        py.run(&source, Some(globals), None)?;
+
+        let classes: Vec<PyObject> = roots.iter()
+                                          .map(|&index| globals.get_item(ctypes_classes::class_name(layouts, index)).to_object(py))
+                                          .collect();
+        match types.extract::<String>() {
+            Ok(_) => Ok(classes[0].clone_ref(py)),
+            Err(_) => Ok(classes.to_object(py))
+        }
+    }
+
+    /*
+    Generates #[repr(C)] Rust definitions for types and everything they reference, with
+    offset_of! / size_of const asserts and getters / setters for bitfields.
+
+    @types           => Type name, or a list of them.
+    @follow_pointers => Also define the types that are only pointed at (otherwise raw pointers
+                        point at opaque types).
+    @raw_pointers    => Write pointers as raw pointers. Otherwise they're u64 (u32 for 32 bit
+                        PDBs), for addresses in another process or a dump.
+
+    RETURN
+    returns the Rust source of a module.
+    */
+    #[args(follow_pointers = "true", raw_pointers = "true")]
+    fn rust_bindings(&mut self, types: &PyAny, follow_pointers: bool, raw_pointers: bool) -> PyResult<String> {
+        let roots = self.type_indexes(types)?;
+        let pdb_file = self.pdb_file_name();
+        let layouts = self.cached_layouts()?;
+
+        Ok(rust_bindings::generate(layouts, &roots, follow_pointers, raw_pointers, &pdb_file))
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::{HashMap, HashSet};
+use pyo3::exceptions::*;
+use pyo3::PyResult;
+use crate::dt_format;
+use crate::layout::{self, Bitfield, TypeLayouts, TypeRef, Udt};
+
+/*
+ *
+ * STRUCT DIFF
+ *
+ * Compares the layout of a struct / union between two PDBs (two builds of the same binary):
+ * fields added, removed, moved (offset or bit position), resized (size or bit length) and retyped,
+ * and the change of the type's size. Fields are matched by name. Fields of unnamed embedded
+ * structs / unions are compared one by one (u.Value); MSVC already flattens anonymous ones into
+ * their parent, so a field moving in or out of an anonymous union shows up as moved at worst.
+ * Named embedded types are compared by name and size, not field by field.
+ *
+ * The text rendering is a unified diff of the dt style listings:
+ *
+ *      --- ntkrnlmp.pdb (old) _EPROCESS, 0x850 bytes
+ *      +++ ntkrnlmp.pdb (new) _EPROCESS, 0x858 bytes
+ *      @@ -0x438 +0x438 @@
+ *         +0x438 ProcessLock      : _EX_PUSH_LOCK
+ *      -  +0x440 UniqueProcessId  : Ptr64 Void
+ *      +  +0x448 UniqueProcessId  : Ptr64 Void
+ *
+ */
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub path: String,
+    pub offset: u64,
+    pub size: u64,
+    // dt spelling, comparable across PDBs
+    pub type_name: String,
+    pub bitfield: Option<Bitfield>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemberChange {
+    pub old: Member,
+    pub new: Member,
+    // moved / resized / retyped
+    pub changes: Vec<&'static str>,
+}
+
+pub struct StructVersion {
+    pub pdb_name: String,
+    pub guid: String,
+    pub age: u32,
+    pub size: u64,
+    pub members: Vec<Member>,
+}
+
+pub struct StructDiff {
+    pub name: String,
+    pub old: StructVersion,
+    pub new: StructVersion,
+    pub added: Vec<Member>,
+    pub removed: Vec<Member>,
+    pub changed: Vec<MemberChange>,
+}
+
+/*
+One line of the merged listing: ' ', '-' or '+' and the member.
+*/
+struct DiffLine<'d> {
+    mark: char,
+    member: &'d Member,
+}
+
+fn collect_members(layouts: &TypeLayouts, udt: &Udt, prefix: &str, start: u64, members: &mut Vec<Member>, names: &mut HashSet<String>) {
+    for field in layouts.all_fields(udt) {
+        let mut path = format!("{}{}", prefix, field.name);
+        let offset = start + field.offset;
+
+        if let TypeRef::Udt(index) = layout::strip_modifiers(&field.typ) {
+            if let Some(inner) = layouts.udt(*index).filter(|inner| layout::is_unnamed(&inner.name)) {
+                collect_members(layouts, inner, &format!("{}.", path), offset, members, names);
+                continue;
+            }
+        }
+
+        // Names only repeat in broken records, keep them apart
+        let mut duplicate = 1;
+        while !names.insert(path.clone()) {
+            duplicate += 1;
+            path = format!("{}{}#{}", prefix, field.name, duplicate);
+        }
+        members.push(Member {
+            path,
+            offset,
+            size: layouts.size_of(&field.typ),
+            type_name: dt_format::type_name(layouts, &field.typ),
+            bitfield: field.bitfield,
+        });
+    }
+}
+
+/*
+Collects the members of a struct / union.
+
+@layouts  => Type layouts of the PDB.
+@pdb_name => Path to the PDB (recorded in the version).
+@name     => Struct / union name.
+
+RETURN
+returns the struct's version in that PDB, None if the PDB doesn't have it.
+*/
+pub fn struct_version(layouts: &TypeLayouts, pdb_name: &str, name: &str) -> Option<StructVersion> {
+    let udt = layouts.find(name).and_then(|index| layouts.udt(index))?;
+
+    let mut members = Vec::new();
+    collect_members(layouts, udt, "", 0, &mut members, &mut HashSet::new());
+    Some(StructVersion {
+        pdb_name: String::from(pdb_name),
+        guid: String::from(layouts.guid()),
+        age: layouts.age(),
+        size: udt.size,
+        members,
+    })
+}
+
+fn load_version(pdb_name: &str, name: &str) -> PyResult<StructVersion> {
+    let layouts = TypeLayouts::load(pdb_name)?;
+    struct_version(&layouts, pdb_name, name).ok_or_else(|| KeyError::py_err(format!("[pymspdb] No struct or union named {} in {}", name, pdb_name)))
+}
+
+fn member_changes(old: &Member, new: &Member) -> Vec<&'static str> {
+    let mut changes = Vec::new();
+    if old.offset != new.offset || old.bitfield.map(|bits| bits.position) != new.bitfield.map(|bits| bits.position) {
+        changes.push("moved");
+    }
+    if old.size != new.size || old.bitfield.map(|bits| bits.length) != new.bitfield.map(|bits| bits.length) {
+        changes.push("resized");
+    }
+    if old.type_name != new.type_name {
+        changes.push("retyped");
+    }
+    changes
+}
+
+/*
+Diffs a struct / union between two PDBs.
+
+@name    => Struct / union name.
+@old_pdb => Path to the PDB of the old build.
+@new_pdb => Path to the PDB of the new build.
+
+RETURN
+returns the diff, fields in the order of the new layout (removed ones in the old order).
+*/
+pub fn diff(name: &str, old_pdb: &str, new_pdb: &str) -> PyResult<StructDiff> {
+    let old = load_version(old_pdb, name)?;
+    let new = load_version(new_pdb, name)?;
+
+    let old_members: HashMap<&str, &Member> = old.members.iter().map(|member| (member.path.as_str(), member)).collect();
+    let new_paths: HashSet<&str> = new.members.iter().map(|member| member.path.as_str()).collect();
+
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    for member in &new.members {
+        match old_members.get(member.path.as_str()) {
+            Some(old_member) => {
+                let changes = member_changes(old_member, member);
+                if !changes.is_empty() {
+                    changed.push(MemberChange { old: (*old_member).clone(), new: member.clone(), changes });
+                }
+            },
+            None => added.push(member.clone())
+        }
+    }
+    let removed = old.members.iter().filter(|member| !new_paths.contains(member.path.as_str())).cloned().collect();
+
+    Ok(StructDiff {
+        name: String::from(name),
+        old,
+        new,
+        added,
+        removed,
+        changed,
+    })
+}
+
+fn member_line(member: &Member) -> String {
+    dt_format::member_line(member.offset, &member.path, &member.type_name, member.bitfield)
+}
+
+impl StructDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() && self.old.size == self.new.size
+    }
+
+    /*
+    Both listings merged: unchanged members once, changed ones as the old line then the new one,
+    removed ones where they were relative to the members around them.
+    */
+    fn merged_lines(&self) -> Vec<DiffLine<'_>> {
+        let old_members: HashMap<&str, &Member> = self.old.members.iter().map(|member| (member.path.as_str(), member)).collect();
+        let new_paths: HashSet<&str> = self.new.members.iter().map(|member| member.path.as_str()).collect();
+
+        let mut lines = Vec::new();
+        let mut removed = self.old.members.iter().filter(|member| !new_paths.contains(member.path.as_str())).peekable();
+        for member in &self.new.members {
+            let old_member = old_members.get(member.path.as_str()).copied();
+            // Removed members come before the first member that followed them in the old layout
+            let anchor = old_member.map_or(member.offset, |old_member| old_member.offset);
+            while let Some(gone) = removed.next_if(|gone| gone.offset <= anchor) {
+                lines.push(DiffLine { mark: '-', member: gone });
+            }
+
+            match old_member {
+                Some(old_member) if old_member == member => lines.push(DiffLine { mark: ' ', member }),
+                Some(old_member) => {
+                    lines.push(DiffLine { mark: '-', member: old_member });
+                    lines.push(DiffLine { mark: '+', member });
+                },
+                None => lines.push(DiffLine { mark: '+', member })
+            }
+        }
+        lines.extend(removed.map(|gone| DiffLine { mark: '-', member: gone }));
+        lines
+    }
+
+    /*
+    Renders the diff as a unified diff of the dt style listings.
+
+    @context => Unchanged lines to show around each change.
+    */
+    pub fn to_text(&self, context: usize) -> String {
+        let mut out = vec![
+            format!("--- {} (old) {}, {:#x} bytes", self.old.pdb_name, self.name, self.old.size),
+            format!("+++ {} (new) {}, {:#x} bytes", self.new.pdb_name, self.name, self.new.size),
+        ];
+        if self.old.size != self.new.size {
+            out.push(format!("@@ size {:#x} -> {:#x} @@", self.old.size, self.new.size));
+        }
+
+        let lines = self.merged_lines();
+        let changed: Vec<usize> = lines.iter().enumerate().filter(|(_, line)| line.mark != ' ').map(|(position, _)| position).collect();
+
+        // Hunks: changed lines with their context, merged when the contexts touch
+        let mut hunks: Vec<(usize, usize)> = Vec::new();
+        for &position in &changed {
+            let start = position.saturating_sub(context);
+            let end = (position + context + 1).min(lines.len());
+            match hunks.last_mut() {
+                Some(hunk) if start <= hunk.1 => hunk.1 = hunk.1.max(end),
+                _ => hunks.push((start, end))
+            }
+        }
+
+        for (start, end) in hunks {
+            let hunk = &lines[start..end];
+            let first_offset = |mark: char| hunk.iter().find(|line| line.mark == ' ' || line.mark == mark).map_or(0, |line| line.member.offset);
+            out.push(format!("@@ -{:#x} +{:#x} @@", first_offset('-'), first_offset('+')));
+            for line in hunk {
+                out.push(format!("{}  {}", line.mark, member_line(line.member)));
+            }
+        }
+
+        out.push(String::new());
+        out.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fixture, kernel_layouts};
+
+    fn eprocess_diff() -> StructDiff {
+        diff("_EPROCESS", &fixture("kernel.pdb"), &fixture("kernel_new.pdb")).unwrap()
+    }
+
+    fn paths(members: &[Member]) -> Vec<&str> {
+        members.iter().map(|member| member.path.as_str()).collect()
+    }
+
+    #[test]
+    fn classifies_member_changes() {
+        let diff = eprocess_diff();
+        assert!(!diff.is_empty());
+        assert_eq!((diff.old.size, diff.new.size), (0x98, 0xa0));
+        assert_eq!(paths(&diff.added), vec!["Exiting", "Flags3"]);
+        assert_eq!(paths(&diff.removed), vec!["PriorityClass"]);
+
+        let changes: Vec<(&str, Vec<&str>)> = diff.changed.iter().map(|change| (change.new.path.as_str(), change.changes.clone())).collect();
+        assert_eq!(changes, vec![("Flags2", vec!["retyped"]), ("Spare", vec!["resized"]), ("ExitTime", vec!["moved"])]);
+    }
+
+    #[test]
+    fn compares_fields_of_unnamed_types_one_by_one() {
+        let layouts = kernel_layouts();
+        let version = struct_version(&layouts, "kernel.pdb", "_EPROCESS").unwrap();
+        assert!(paths(&version.members).contains(&"u.High"));
+        assert!(struct_version(&layouts, "kernel.pdb", "_NOPE").is_none());
+
+        let same = diff("_EPROCESS", &fixture("kernel.pdb"), &fixture("kernel.pdb")).unwrap();
+        assert!(same.is_empty());
+        assert!(diff("_NOPE", &fixture("kernel.pdb"), &fixture("kernel_new.pdb")).is_err());
+    }
+
+    #[test]
+    fn renders_a_unified_diff() {
+        let text = eprocess_diff().to_text(1);
+        assert!(text.contains(" (old) _EPROCESS, 0x98 bytes\n+++ "));
+        assert!(text.contains("\n@@ size 0x98 -> 0xa0 @@\n@@ -0x50 +0x50 @@\n   +0x050 ImageFileName    : [15] UChar\n-  +0x05f PriorityClass    : UChar\n"));
+        assert!(text.contains("\n-  +0x088 Spare            : Pos 1, 31 Bits\n+  +0x088 Spare            : Pos 1, 30 Bits\n+  +0x088 Exiting          : Pos 31, 1 Bit\n"));
+        assert!(text.ends_with("-  +0x090 ExitTime         : Int8B\n+  +0x098 ExitTime         : Int8B\n"));
+        // Without context, each change is a hunk of its own
+        assert_eq!(eprocess_diff().to_text(0).matches("\n@@ -").count(), 3);
+    }
+}
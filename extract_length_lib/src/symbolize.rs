@@ -0,0 +1,160 @@
+use std::path::Path;
+use pyo3::exceptions::*;
+use pyo3::PyResult;
+use crate::symbols::{GlobalSymbol, SymbolKind};
+
+/*
+ *
+ * ADDRESS TO SYMBOL RESOLUTION
+ *
+ * Function and public symbols sorted by RVA. Their RVAs already went through the section map
+ * (and OMAP, for images that were rearranged after linking), and the image section headers
+ * stop a lookup from running past the end of a section into the next one.
+ *
+ */
+
+#[derive(Debug, Clone)]
+pub struct MapEntry {
+    pub rva: u32,
+    // Only known for functions
+    pub size: Option<u32>,
+    pub name: String,
+    pub kind: SymbolKind,
+}
+
+#[derive(Debug)]
+pub struct Symbolized<'a> {
+    pub entry: &'a MapEntry,
+    pub offset: u32,
+}
+
+#[derive(Debug)]
+struct Section {
+    rva: u32,
+    size: u32,
+}
+
+#[derive(Debug)]
+pub struct SymbolMap {
+    // Image name used in "module!symbol+offset" strings (the PDB's file stem)
+    pub module: String,
+    entries: Vec<MapEntry>,
+    sections: Vec<Section>,
+}
+
+impl SymbolMap {
+    /*
+    Builds the map.
+
+    @pdb_name => Path to the PDB file (its section headers are read).
+    @symbols  => Symbols collected from the same PDB (see symbols::collect_symbols).
+    */
+    pub fn build(pdb_name: &str, symbols: &[GlobalSymbol]) -> PyResult<SymbolMap> {
+        let mut pdb = crate::open_pdb_and_parse(pdb_name)?;
+        let headers = pdb.sections().map_err(|e| Exception::py_err(e.to_string()))?.unwrap_or_default();
+
+        let mut sections: Vec<Section> = headers.iter()
+            .map(|header| Section {
+                rva: header.virtual_address,
+                // Misc.VirtualSize
+                size: header.physical_address.max(header.size_of_raw_data),
+            })
+            .collect();
+        sections.sort_by_key(|section| section.rva);
+
+        let mut entries: Vec<MapEntry> = symbols.iter()
+            .filter(|symbol| symbol.kind == SymbolKind::Function || symbol.kind == SymbolKind::Public)
+            .filter_map(|symbol| symbol.rva.map(|rva| MapEntry {
+                rva,
+                size: symbol.size,
+                name: symbol.name.clone(),
+                kind: symbol.kind,
+            }))
+            .collect();
+
+        // Functions first at the same address, they carry a size and a type (publics don't)
+        entries.sort_by_key(|entry| (entry.rva, entry.kind != SymbolKind::Function));
+        entries.dedup_by_key(|entry| entry.rva);
+
+        let module = Path::new(pdb_name).file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        Ok(SymbolMap {
+            module,
+            entries,
+            sections,
+        })
+    }
+
+    fn section_of(&self, rva: u32) -> Option<&Section> {
+        let index = self.sections.partition_point(|section| section.rva <= rva).checked_sub(1)?;
+        let section = &self.sections[index];
+
+        if rva - section.rva < section.size {
+            Some(section)
+        } else {
+            None
+        }
+    }
+
+    /*
+    Resolves an RVA into the nearest symbol at or before it.
+
+    @rva => Address relative to the image base.
+
+    RETURN
+    returns the symbol and the offset into it, or None if no symbol precedes rva in its section.
+    */
+    pub fn lookup(&self, rva: u32) -> Option<Symbolized<'_>> {
+        let index = self.entries.partition_point(|entry| entry.rva <= rva).checked_sub(1)?;
+        let entry = &self.entries[index];
+
+        // Without section headers, trust the nearest symbol
+        if !self.sections.is_empty() {
+            let section = self.section_of(rva)?;
+            if entry.rva < section.rva {
+                return None;
+            }
+        }
+
+        Some(Symbolized {
+            entry,
+            offset: rva - entry.rva,
+        })
+    }
+
+    /*
+    Formats a lookup result the way debuggers print it (ntkrnlmp!KeWaitForSingleObject+0x1a2).
+    */
+    pub fn display(&self, symbolized: &Symbolized) -> String {
+        if symbolized.offset == 0 {
+            format!("{}!{}", self.module, symbolized.entry.name)
+        } else {
+            format!("{}!{}+{:#x}", self.module, symbolized.entry.name, symbolized.offset)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::collect_symbols;
+    use crate::test_support::fixture;
+
+    #[test]
+    fn resolves_rvas_inside_their_section() {
+        let pdb_name = fixture("symbols.pdb");
+        let map = SymbolMap::build(&pdb_name, &collect_symbols(&pdb_name).unwrap()).unwrap();
+        let display = |rva: u32| map.lookup(rva).map(|symbolized| map.display(&symbolized));
+
+        // The function wins over the public at the same address
+        assert_eq!(display(0x1000).as_deref(), Some("symbols!symbols::KeWaitForSingleObject"));
+        assert_eq!(map.lookup(0x1000).unwrap().entry.size, Some(19));
+        assert_eq!(display(0x1005).as_deref(), Some("symbols!symbols::KeWaitForSingleObject+0x5"));
+        assert_eq!(display(0x1021).as_deref(), Some("symbols!symbols::_DllMainCRTStartup+0x1"));
+        assert_eq!(display(0x300c).as_deref(), Some("symbols!PsActiveProcessHead+0x4"));
+
+        // Nothing before it in its own section, or no section at all
+        assert!(map.lookup(0x2010).is_none());
+        assert!(map.lookup(0x6000).is_none());
+        assert!(map.lookup(0x10).is_none());
+    }
+}
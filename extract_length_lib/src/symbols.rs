@@ -0,0 +1,308 @@
+use std::collections::HashSet;
+use fallible_iterator::FallibleIterator;
+use pdb::{IdFinder, TypeFinder, TypeIndex};
+use pyo3::exceptions::*;
+use pyo3::{PyErr, PyResult};
+
+/*
+ *
+ * GLOBAL SYMBOLS
+ *
+ * Publics, data and constants come from the global symbol stream, procedures only live in
+ * the module streams (the global stream just holds S_PROCREF records pointing at them).
+ *
+ */
+
+// Procedure records whose type is an IPI function id rather than a TPI type
+const S_LPROC32_ID: u16 = 0x1146;
+const S_GPROC32_ID: u16 = 0x1147;
+const S_LPROC32_DPC_ID: u16 = 0x1156;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    Public,
+    Function,
+    Data,
+    Constant,
+}
+
+impl SymbolKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            SymbolKind::Public => "public",
+            SymbolKind::Function => "function",
+            SymbolKind::Data => "data",
+            SymbolKind::Constant => "constant",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SymbolKind> {
+        [SymbolKind::Public, SymbolKind::Function, SymbolKind::Data, SymbolKind::Constant]
+            .iter()
+            .copied()
+            .find(|kind| kind.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GlobalSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // False for file statics (S_LDATA32 / S_LPROC32)
+    pub global: bool,
+    // Section index and offset inside it, None for constants
+    pub section: Option<u16>,
+    pub offset: Option<u32>,
+    pub rva: Option<u32>,
+    // Only known for functions
+    pub size: Option<u32>,
+    pub type_name: Option<String>,
+    // TPI type of data / constants, procedure type of functions
+    pub type_index: Option<TypeIndex>,
+    // Only set for constants
+    pub value: Option<i128>,
+    // Compiland the symbol was defined in (module stream symbols only)
+    pub module: Option<String>,
+    // Undecorated form of C++ names, and only their qualified name (e.g "Bar::Foo")
+    pub demangled: Option<String>,
+    pub qualified_name: Option<String>,
+}
+
+impl GlobalSymbol {
+    fn new(kind: SymbolKind, name: pdb::RawString, global: bool, module: Option<&str>) -> GlobalSymbol {
+        let name = name.to_string().into_owned();
+        let demangled = crate::demangle::demangle(&name);
+
+        GlobalSymbol {
+            demangled: demangled.as_ref().map(|demangled| demangled.full.clone()),
+            qualified_name: demangled.map(|demangled| demangled.qualified_name),
+            name,
+            kind,
+            global,
+            section: None,
+            offset: None,
+            rva: None,
+            size: None,
+            type_name: None,
+            type_index: None,
+            value: None,
+            module: module.map(String::from),
+        }
+    }
+}
+
+fn pdb_error(e: pdb::Error) -> PyErr {
+    Exception::py_err(e.to_string())
+}
+
+pub fn variant_value(value: pdb::Variant) -> i128 {
+    match value {
+        pdb::Variant::U8(value) => value.into(),
+        pdb::Variant::U16(value) => value.into(),
+        pdb::Variant::U32(value) => value.into(),
+        pdb::Variant::U64(value) => value.into(),
+        pdb::Variant::I8(value) => value.into(),
+        pdb::Variant::I16(value) => value.into(),
+        pdb::Variant::I32(value) => value.into(),
+        pdb::Variant::I64(value) => value.into(),
+    }
+}
+
+/*
+Renders a type as a string, without panicking on types that can't be parsed.
+
+@type_finder => Filled type finder.
+@type_index  => Type to render.
+
+RETURN
+returns the type string, or None for missing / unparsable types.
+*/
+pub fn type_name(type_finder: &TypeFinder, type_index: TypeIndex) -> Option<String> {
+    if type_index.0 == 0 {
+        return None;
+    }
+
+    crate::try_extract_type_string(type_finder, type_index, None, None).map(|(_, name)| name)
+}
+
+/*
+Resolves the type of a S_*PROC32_ID record, which points at a function id in the IPI stream.
+*/
+fn function_id_type(id_finder: &IdFinder, id_index: u32) -> Option<TypeIndex> {
+    let id = id_finder.find(pdb::IdIndex(id_index)).ok()?;
+    match id.parse().ok()? {
+        pdb::IdData::Function(pdb::FunctionId {function_type, ..}) => Some(function_type),
+        pdb::IdData::MemberFunction(pdb::MemberFunctionId {function_type, ..}) => Some(function_type),
+        _ => None
+    }
+}
+
+/*
+Type of a procedure record.
+
+@id_finder  => Filled IPI finder.
+@raw_kind   => Record kind (S_GPROC32, S_GPROC32_ID, ...).
+@type_index => The record's type field.
+
+RETURN
+returns the TPI function type, which S_*PROC32_ID records only reference through a function id.
+*/
+pub fn procedure_type(id_finder: &IdFinder, raw_kind: u16, type_index: TypeIndex) -> Option<TypeIndex> {
+    match raw_kind {
+        S_LPROC32_ID | S_GPROC32_ID | S_LPROC32_DPC_ID => function_id_type(id_finder, type_index.0),
+        _ => Some(type_index)
+    }
+}
+
+struct Collector<'a, 't> {
+    address_map: &'a pdb::AddressMap<'t>,
+    type_finder: &'a TypeFinder<'a>,
+    id_finder: &'a IdFinder<'a>,
+    seen: HashSet<(SymbolKind, String, Option<u16>, Option<u32>)>,
+    symbols: Vec<GlobalSymbol>,
+}
+
+impl<'a, 't> Collector<'a, 't> {
+    fn push(&mut self, mut symbol: GlobalSymbol, offset: Option<pdb::PdbInternalSectionOffset>) {
+        if let Some(offset) = offset {
+            symbol.section = Some(offset.section);
+            symbol.offset = Some(offset.offset);
+            symbol.rva = offset.to_rva(self.address_map).map(|rva| rva.0);
+        }
+
+        if self.seen.insert((symbol.kind, symbol.name.clone(), symbol.section, symbol.offset)) {
+            self.symbols.push(symbol);
+        }
+    }
+
+    /*
+    Adds every interesting record of a symbol stream.
+
+    @iter   => Symbols of the global stream or of a module stream.
+    @module => Module name, None for the global stream.
+    */
+    fn collect(&mut self, mut iter: pdb::SymbolIter, module: Option<&str>) -> PyResult<()> {
+        while let Some(symbol) = iter.next().map_err(pdb_error)? {
+            let raw_kind = symbol.raw_kind();
+
+            match symbol.parse() {
+                Ok(pdb::SymbolData::Public(public)) => {
+                    self.push(GlobalSymbol::new(SymbolKind::Public, public.name, true, module), Some(public.offset));
+                },
+                Ok(pdb::SymbolData::Procedure(procedure)) => {
+                    let type_index = procedure_type(self.id_finder, raw_kind, procedure.type_index);
+
+                    self.push(GlobalSymbol {
+                        size: Some(procedure.len),
+                        type_name: type_index.and_then(|type_index| type_name(self.type_finder, type_index)),
+                        type_index,
+                        ..GlobalSymbol::new(SymbolKind::Function, procedure.name, procedure.global, module)
+                    }, Some(procedure.offset));
+                },
+                Ok(pdb::SymbolData::Data(data)) => {
+                    self.push(GlobalSymbol {
+                        type_name: type_name(self.type_finder, data.type_index),
+                        type_index: Some(data.type_index),
+                        ..GlobalSymbol::new(SymbolKind::Data, data.name, data.global, module)
+                    }, Some(data.offset));
+                },
+                Ok(pdb::SymbolData::Constant(constant)) => {
+                    self.push(GlobalSymbol {
+                        type_name: type_name(self.type_finder, constant.type_index),
+                        type_index: Some(constant.type_index),
+                        value: Some(variant_value(constant.value)),
+                        ..GlobalSymbol::new(SymbolKind::Constant, constant.name, true, module)
+                    }, None);
+                },
+                // Records the pdb crate can't parse (yet) are simply not interesting here
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+Collects the public, function, data and constant symbols of a PDB file.
+
+@pdb_name => Path to the PDB file.
+
+RETURN
+returns every symbol once, in stream order (global stream first, then each module).
+*/
+pub fn collect_symbols(pdb_name: &str) -> PyResult<Vec<GlobalSymbol>> {
+    let mut pdb = crate::open_pdb_and_parse(pdb_name)?;
+
+    let type_information = pdb.type_information().map_err(pdb_error)?;
+    let type_finder = crate::fill_item_finder(&type_information)?;
+    let id_information = pdb.id_information().map_err(pdb_error)?;
+    let id_finder = crate::fill_item_finder(&id_information)?;
+    let address_map = pdb.address_map().map_err(pdb_error)?;
+
+    let mut collector = Collector {
+        address_map: &address_map,
+        type_finder: &type_finder,
+        id_finder: &id_finder,
+        seen: HashSet::new(),
+        symbols: Vec::new(),
+    };
+
+    let global_symbols = pdb.global_symbols().map_err(pdb_error)?;
+    collector.collect(global_symbols.iter(), None)?;
+
+    let debug_information = pdb.debug_information().map_err(pdb_error)?;
+    let mut modules = debug_information.modules().map_err(pdb_error)?;
+    while let Some(module) = modules.next().map_err(pdb_error)? {
+        let module_info = match pdb.module_info(&module).map_err(pdb_error)? {
+            Some(module_info) => module_info,
+            None => continue
+        };
+        let module_name = module.module_name();
+        collector.collect(module_info.symbols().map_err(pdb_error)?, Some(&module_name))?;
+    }
+
+    Ok(collector.symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fixture;
+
+    #[test]
+    fn renders_type_names_without_panicking() {
+        let mut pdb = crate::open_pdb_and_parse(&fixture("kernel.pdb")).unwrap();
+        let type_information = pdb.type_information().unwrap();
+        let type_finder = crate::fill_item_finder(&type_information).unwrap();
+
+        assert_eq!(type_name(&type_finder, TypeIndex(0x22)).as_deref(), Some("ULONG"));
+        assert_eq!(type_name(&type_finder, TypeIndex(0x1001)).as_deref(), Some("struct _LIST_ENTRY*"));
+        assert_eq!(type_name(&type_finder, TypeIndex(0)), None);
+        assert_eq!(type_name(&type_finder, TypeIndex(0x7000)), None);
+        // Pointer to a type that doesn't exist
+        assert_eq!(type_name(&type_finder, TypeIndex(0x1028)), None);
+    }
+
+    #[test]
+    fn collects_symbols() {
+        let symbols = collect_symbols(&fixture("symbols.pdb")).unwrap();
+        let find = |kind: SymbolKind, name: &str| symbols.iter().find(|symbol| symbol.kind == kind && symbol.name == name).unwrap();
+
+        let public = find(SymbolKind::Public, "KeWaitForSingleObject");
+        assert_eq!((public.section, public.offset, public.rva), (Some(1), Some(0), Some(0x1000)));
+
+        let function = find(SymbolKind::Function, "symbols::KeWaitForSingleObject");
+        assert_eq!(function.rva, Some(0x1000));
+        assert_eq!(function.size, Some(19));
+        assert_eq!(function.type_name.as_deref(), Some("U32 (struct symbols::Eprocess*, U32)"));
+        assert!(function.module.as_deref().unwrap().ends_with("symbols.o"));
+
+        let data = find(SymbolKind::Data, "symbols::PsActiveProcessHead");
+        assert_eq!(data.rva, Some(0x3008));
+        assert_eq!(data.type_name.as_deref(), Some("struct symbols::ListEntry"));
+        assert_eq!(data.module, None);
+
+        assert_eq!(SymbolKind::from_name("FUNCTION"), Some(SymbolKind::Function));
+        assert_eq!(SymbolKind::from_name("label"), None);
+    }
+}
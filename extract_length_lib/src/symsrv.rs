@@ -0,0 +1,567 @@
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::Duration;
+use pyo3::exceptions::*;
+use pyo3::PyResult;
+use crate::cab;
+use crate::pe;
+use crate::symstore;
+use crate::symstore::StoreFile;
+
+/*
+ *
+ * SYMBOL SERVER CLIENT
+ *
+ * Understands _NT_SYMBOL_PATH style paths (srv*cache*url;cache*dir;dir) and stores files
+ * in the symstore layout: <name>/<GUID><AGE>/<name> (see symstore.rs)
+ *
+ */
+
+pub const DEFAULT_SYMBOL_SERVER: &str = "https://msdl.microsoft.com/download/symbols";
+pub const DEFAULT_CACHE: &str = "resources";
+const USER_AGENT: &str = "Microsoft-Symbol-Server/6.12.0002.633";
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+const RETRY_DELAY_MS: u64 = 500;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymbolStore {
+    // Symbol store (srv* / cache* element)
+    Local(PathBuf),
+    // Plain directory from the symbol path, either flat or a symbol store
+    Directory(PathBuf),
+    Http(String),
+}
+
+/*
+One element of a symbol path. Stores are searched in order, and whenever a file is found
+further down the chain it is copied into every local store before it.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolChain {
+    pub stores: Vec<SymbolStore>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolPath {
+    pub chains: Vec<SymbolChain>,
+}
+
+fn parse_store(store: &str) -> SymbolStore {
+    let store = store.trim();
+    let lowered = store.to_ascii_lowercase();
+
+    if lowered.starts_with("http://") || lowered.starts_with("https://") {
+        SymbolStore::Http(store.trim_end_matches('/').to_string())
+    } else if store.is_empty() {
+        // srv**url means "use the default downstream store"
+        SymbolStore::Local(PathBuf::from(DEFAULT_CACHE))
+    } else {
+        SymbolStore::Local(PathBuf::from(store))
+    }
+}
+
+impl SymbolPath {
+    /*
+    Parses a symbol path.
+
+    @symbol_path => _NT_SYMBOL_PATH style string (e.g srv*c:\symbols*https://msdl.microsoft.com/download/symbols)
+
+    RETURN
+    returns the parsed symbol path.
+    */
+    pub fn parse(symbol_path: &str) -> SymbolPath {
+        let mut chains = Vec::new();
+        let mut caches: Vec<SymbolStore> = Vec::new();
+
+        for element in symbol_path.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let lowered = element.to_ascii_lowercase();
+            let mut stores = caches.clone();
+
+            if lowered.starts_with("srv*") {
+                stores.extend(element[4..].split('*').map(parse_store));
+            } else if lowered.starts_with("symsrv*") {
+                // symsrv*<dll>*stores..., the dll part is meaningless for us
+                stores.extend(element[7..].split('*').skip(1).map(parse_store));
+            } else if lowered.starts_with("cache*") {
+                // Caches everything coming from the elements after it
+                caches.push(parse_store(&element[6..]));
+                continue;
+            } else {
+                stores.push(SymbolStore::Directory(PathBuf::from(element)));
+            }
+            chains.push(SymbolChain { stores });
+        }
+        SymbolPath { chains }
+    }
+
+    /*
+    Builds the symbol path from the _NT_SYMBOL_PATH environment variable. When it isn't set,
+    the default cache is placed in front of any existing WinDbg / Visual Studio caches and
+    Microsoft's public symbol server.
+    */
+    pub fn from_env() -> SymbolPath {
+        SymbolPath::from_variable(env::var("_NT_SYMBOL_PATH").ok().as_deref())
+    }
+
+    /*
+    Builds the symbol path from the value of _NT_SYMBOL_PATH (None when it isn't set).
+    */
+    fn from_variable(variable: Option<&str>) -> SymbolPath {
+        match variable {
+            Some(symbol_path) if !symbol_path.trim().is_empty() => SymbolPath::parse(symbol_path),
+            _ => {
+                let mut stores = vec![PathBuf::from(DEFAULT_CACHE)];
+                stores.extend(known_local_caches());
+
+                let stores: Vec<String> = stores.iter().map(|store| store.to_string_lossy().into_owned()).collect();
+                SymbolPath::parse(&format!("srv*{}*{}", stores.join("*"), DEFAULT_SYMBOL_SERVER))
+            }
+        }
+    }
+
+    /*
+    Looks for a file in every chain of the symbol path, downloading it if needed.
+
+    @client    => Client used for http stores (None in order to only search local stores).
+    @file_name => File name (e.g ntkrnlmp.pdb).
+    @key       => Index key (see index_key).
+
+    RETURN
+    returns the local path of the file, or None if no store has it. A store that fails doesn't
+    stop the search, its error is only returned if no other store has the file.
+    */
+    pub fn find(&self, client: Option<&SymbolServerClient>, file_name: &str, key: &str) -> PyResult<Option<PathBuf>> {
+        let mut first_error = None;
+
+        for chain in &self.chains {
+            match chain.find(client, file_name, key) {
+                Ok(Some(path)) => return Ok(Some(path)),
+                Ok(None) => {},
+                Err(e) => { first_error.get_or_insert(e); }
+            }
+        }
+        first_error.map_or(Ok(None), Err)
+    }
+}
+
+impl SymbolChain {
+    fn find(&self, client: Option<&SymbolServerClient>, file_name: &str, key: &str) -> PyResult<Option<PathBuf>> {
+        let mut first_error = None;
+
+        for (index, store) in self.stores.iter().enumerate() {
+            let downstream = &self.stores[..index];
+
+            let found = match (store, client) {
+                (SymbolStore::Local(directory), _) => symstore::find_in_store(directory, file_name, key),
+                (SymbolStore::Directory(directory), _) => symstore::find_in_directory(directory, file_name, key),
+                (SymbolStore::Http(url), Some(client)) => client.download(url, file_name, key).map(|contents| contents.map(StoreFile::Contents)),
+                (SymbolStore::Http(_), None) => Ok(None)
+            };
+
+            // A broken store (unreachable server, corrupt file) shouldn't hide the stores after it
+            let found = match found {
+                Ok(found) => found,
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                    continue;
+                }
+            };
+
+            match found {
+                Some(StoreFile::Path(path)) => {
+                    if !downstream.iter().any(|store| matches!(store, SymbolStore::Local(_))) {
+                        return Ok(Some(path));
+                    }
+                    let contents = fs::read(&path).map_err(|e| IOError::py_err(e.to_string()))?;
+                    return store_downstream(downstream, file_name, key, &contents);
+                },
+                Some(StoreFile::Contents(contents)) => {
+                    if let Some(path) = store_downstream(downstream, file_name, key, &contents)? {
+                        return Ok(Some(path));
+                    }
+                    // Nowhere to put it, use the downstream store of the default symbol path
+                    let store = default_downstream_store(env::var("_NT_SYMBOL_PATH").ok().as_deref());
+                    return Ok(Some(symstore::add_file(&store, file_name, key, &contents)?));
+                },
+                None => {}
+            }
+        }
+        first_error.map_or(Ok(None), Err)
+    }
+}
+
+/*
+Local store files are put in when their symbol path element has none: the first one of the
+default symbol path.
+
+@variable => Value of _NT_SYMBOL_PATH, None when it isn't set.
+
+RETURN
+returns the store directory, the default cache when the symbol path has no local store
+(srv*https://msdl.microsoft.com/download/symbols).
+*/
+fn default_downstream_store(variable: Option<&str>) -> PathBuf {
+    let symbol_path = SymbolPath::from_variable(variable);
+    let store = symbol_path.chains.iter().flat_map(|chain| &chain.stores).find_map(|store| match store {
+        SymbolStore::Local(directory) => Some(directory.clone()),
+        _ => None
+    });
+
+    store.unwrap_or_else(|| PathBuf::from(DEFAULT_CACHE))
+}
+
+/*
+Copies a file into every local store before the one it was found in.
+
+RETURN
+returns the path inside the first local store, or None if there are no local stores.
+*/
+fn store_downstream(downstream: &[SymbolStore], file_name: &str, key: &str, contents: &[u8]) -> PyResult<Option<PathBuf>> {
+    let mut first_path = None;
+
+    for store in downstream {
+        if let SymbolStore::Local(directory) = store {
+            let path = symstore::add_file(directory, file_name, key, contents)?;
+            first_path.get_or_insert(path);
+        }
+    }
+    Ok(first_path)
+}
+
+/*
+Well known symbol caches of the debugging tools, the ones that exist on this machine.
+*/
+fn known_local_caches() -> Vec<PathBuf> {
+    let mut caches = Vec::new();
+
+    if let Ok(program_data) = env::var("ProgramData") {
+        caches.push(Path::new(&program_data).join("dbg").join("sym"));
+    }
+    if let Ok(local_app_data) = env::var("LOCALAPPDATA") {
+        caches.push(Path::new(&local_app_data).join("dbg").join("sym"));
+    }
+    if let Ok(temp) = env::var("TEMP") {
+        caches.push(Path::new(&temp).join("SymbolCache"));
+    }
+
+    caches.retain(|cache| cache.is_dir());
+    caches
+}
+
+/*
+Strips any directory from a file name (CodeView records usually hold the full build path).
+*/
+pub fn file_name_only(file_name: &str) -> &str {
+    file_name.rsplit(['\\', '/']).next().unwrap_or(file_name)
+}
+
+/*
+Builds the symstore index key of a PDB.
+
+@guid => PDB GUID, with or without braces / dashes.
+@age  => PDB age.
+
+RETURN
+returns the key (uppercase GUID digits followed by the age in hex, e.g 3844DBB920174967BE7AA4A2C20430FA2).
+*/
+pub fn index_key(guid: &str, age: u32) -> PyResult<String> {
+    let digits: String = guid.chars().filter(|c| !matches!(c, '{' | '}' | '-')).collect();
+
+    if digits.len() != 32 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ValueError::py_err(format!("[pymspdb] Invalid GUID: {}", guid)));
+    }
+    Ok(format!("{}{:X}", digits.to_ascii_uppercase(), age))
+}
+
+pub struct SymbolServerClient {
+    agent: ureq::Agent,
+    attempts: u32,
+    // Wait before the first retry, doubled for every retry after it
+    retry_delay: Duration,
+}
+
+impl SymbolServerClient {
+    /*
+    Creates the http client. The proxy is taken from _NT_SYMBOL_PROXY, and then from the usual
+    HTTPS_PROXY / HTTP_PROXY / ALL_PROXY variables.
+    */
+    pub fn new() -> PyResult<SymbolServerClient> {
+        let mut builder = ureq::AgentBuilder::new()
+            .user_agent(USER_AGENT)
+            .timeout_connect(Duration::from_secs(30))
+            .timeout_read(Duration::from_secs(120))
+            .try_proxy_from_env(true);
+
+        if let Ok(proxy) = env::var("_NT_SYMBOL_PROXY") {
+            let proxy = ureq::Proxy::new(proxy.trim())
+                .map_err(|e| ValueError::py_err(format!("[pymspdb] Invalid _NT_SYMBOL_PROXY: {}", e)))?;
+            builder = builder.proxy(proxy);
+        }
+
+        Ok(SymbolServerClient {
+            agent: builder.build(),
+            attempts: DOWNLOAD_ATTEMPTS,
+            retry_delay: Duration::from_millis(RETRY_DELAY_MS),
+        })
+    }
+
+    /*
+    Downloads a single url, retrying on connection errors and server side failures.
+
+    RETURN
+    returns the body, or None if the server doesn't have the file.
+    */
+    fn get(&self, url: &str) -> PyResult<Option<Vec<u8>>> {
+        let mut last_error = String::new();
+
+        for attempt in 0..self.attempts {
+            if attempt != 0 {
+                sleep(self.retry_delay * (1 << (attempt - 1)));
+            }
+
+            match self.agent.get(url).call() {
+                Ok(response) => {
+                    let mut body = Vec::new();
+                    match response.into_reader().read_to_end(&mut body) {
+                        Ok(_) => return Ok(Some(body)),
+                        Err(e) => last_error = e.to_string()
+                    }
+                },
+                Err(ureq::Error::Status(code, _)) if code == 408 || code == 429 || code >= 500 => {
+                    last_error = format!("{} returned status {}", url, code);
+                },
+                Err(ureq::Error::Status(_, _)) => return Ok(None),
+                Err(ureq::Error::Transport(e)) => last_error = e.to_string()
+            }
+        }
+        Err(ConnectionError::py_err(format!("[pymspdb] Download failed: {}", last_error)))
+    }
+
+    /*
+    Downloads a file from a symbol server, trying the uncompressed file first and then the
+    cabinet compressed one (name.pd_).
+
+    @url       => Symbol server url.
+    @file_name => File name (e.g ntkrnlmp.pdb).
+    @key       => Index key (see index_key).
+
+    RETURN
+    returns the file contents, or None if the server doesn't have it.
+    */
+    pub fn download(&self, url: &str, file_name: &str, key: &str) -> PyResult<Option<Vec<u8>>> {
+        let file_url = format!("{}/{}/{}/{}", url, file_name, key, file_name);
+        if let Some(contents) = self.get(&file_url)? {
+            return Ok(Some(contents));
+        }
+
+        let compressed_name = symstore::compressed_file_name(file_name);
+        let compressed_url = format!("{}/{}/{}/{}", url, file_name, key, compressed_name);
+        match self.get(&compressed_url)? {
+            Some(contents) if cab::is_supported_cabinet(&contents) => {
+                Ok(Some(cab::extract_file(&contents, None)?.1))
+            },
+            // Anything else (usually an html error page, or an LZX cabinet) isn't usable
+            _ => Ok(None)
+        }
+    }
+}
+
+fn parse_symbol_path(symbol_path: Option<&str>) -> SymbolPath {
+    match symbol_path {
+        Some(symbol_path) => SymbolPath::parse(symbol_path),
+        None => SymbolPath::from_env()
+    }
+}
+
+/*
+Finds a PDB in the symbol path (downloading it if needed).
+
+@pdb_name    => PDB file name, as written in the image's debug directory.
+@guid        => PDB GUID.
+@age         => PDB age.
+@symbol_path => Symbol path to search. Defaults to _NT_SYMBOL_PATH / the public symbol server.
+@offline     => Only search local stores, never touch the network.
+
+RETURN
+returns the local path of the PDB, or None if it couldn't be found.
+*/
+pub fn fetch_pdb(pdb_name: &str, guid: &str, age: u32, symbol_path: Option<&str>, offline: bool) -> PyResult<Option<PathBuf>> {
+    let key = index_key(guid, age)?;
+    let client = if offline { None } else { Some(SymbolServerClient::new()?) };
+
+    parse_symbol_path(symbol_path).find(client.as_ref(), file_name_only(pdb_name), &key)
+}
+
+/*
+Finds the PDB matching a PE image (downloading it if needed).
+
+@image_path  => Path to the image (exe / dll / sys).
+@symbol_path => Symbol path to search. Defaults to _NT_SYMBOL_PATH / the public symbol server.
+@offline     => Only search local stores, never touch the network.
+
+RETURN
+returns the local path of the PDB, or None if it couldn't be found.
+*/
+pub fn fetch_pdb_for_image(image_path: &Path, symbol_path: Option<&str>, offline: bool) -> PyResult<Option<PathBuf>> {
+    let identity = pe::read_pdb_identity(image_path)
+        .map_err(|e| ValueError::py_err(format!("[pymspdb] {}: {}", image_path.display(), e)))?;
+    let client = if offline { None } else { Some(SymbolServerClient::new()?) };
+
+    parse_symbol_path(symbol_path).find(client.as_ref(), identity.file_name(), &identity.index_key())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{cabinet, fixture, scratch_directory, stand_in};
+
+    const KEY: &str = "3844DBB920174967BE7AA4A2C20430FA2";
+
+    fn client() -> SymbolServerClient {
+        SymbolServerClient {
+            agent: ureq::AgentBuilder::new().build(),
+            attempts: 3,
+            retry_delay: Duration::from_millis(1),
+        }
+    }
+
+    fn pdb_path(file_name: &str) -> String {
+        format!("/{}/{}/{}", file_name, KEY, file_name)
+    }
+
+    fn compressed_path(file_name: &str) -> String {
+        format!("/{}/{}/{}", file_name, KEY, symstore::compressed_file_name(file_name))
+    }
+
+    #[test]
+    fn parses_symbol_paths() {
+        let symbol_path = SymbolPath::parse("cache*c:\\cache;srv*c:\\symbols*https://msdl.microsoft.com/download/symbols/;d:\\flat");
+        assert_eq!(symbol_path.chains, vec![
+            SymbolChain { stores: vec![
+                SymbolStore::Local(PathBuf::from("c:\\cache")),
+                SymbolStore::Local(PathBuf::from("c:\\symbols")),
+                SymbolStore::Http(String::from("https://msdl.microsoft.com/download/symbols")),
+            ] },
+            SymbolChain { stores: vec![
+                SymbolStore::Local(PathBuf::from("c:\\cache")),
+                SymbolStore::Directory(PathBuf::from("d:\\flat")),
+            ] },
+        ]);
+    }
+
+    #[test]
+    fn builds_index_keys() {
+        assert_eq!(index_key("{3844dbb9-2017-4967-be7a-a4a2c20430fa}", 2).unwrap(), KEY);
+        assert_eq!(index_key("3844DBB920174967BE7AA4A2C20430FA", 0x1f).unwrap(), "3844DBB920174967BE7AA4A2C20430FA1F");
+        assert!(index_key("3844DBB9", 1).is_err());
+        assert_eq!(file_name_only("d:\\build\\ntkrnlmp.pdb"), "ntkrnlmp.pdb");
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let server = stand_in(vec![(&pdb_path("a.pdb"), vec![(503, Vec::new()), (500, Vec::new()), (200, b"pdb".to_vec())])]);
+
+        let contents = client().download(&server.url, "a.pdb", KEY).unwrap();
+        assert_eq!(contents.as_deref(), Some(&b"pdb"[..]));
+        assert_eq!(server.requests.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn fails_after_the_last_attempt() {
+        let server = stand_in(vec![(&pdb_path("a.pdb"), vec![(500, Vec::new())])]);
+
+        assert!(client().download(&server.url, "a.pdb", KEY).is_err());
+        assert_eq!(server.requests.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn falls_back_to_the_compressed_file() {
+        let server = stand_in(vec![(&compressed_path("a.pdb"), vec![(200, cabinet("a.pdb", b"compressed pdb", 1))])]);
+
+        let contents = client().download(&server.url, "a.pdb", KEY).unwrap();
+        assert_eq!(contents.as_deref(), Some(&b"compressed pdb"[..]));
+        assert_eq!(*server.requests.lock().unwrap(), vec![pdb_path("a.pdb"), compressed_path("a.pdb")]);
+    }
+
+    #[test]
+    fn ignores_compressed_files_that_are_not_cabinets() {
+        let server = stand_in(vec![(&compressed_path("a.pdb"), vec![(200, b"<html>Not found</html>".to_vec())])]);
+
+        assert_eq!(client().download(&server.url, "a.pdb", KEY).unwrap(), None);
+    }
+
+    #[test]
+    fn missing_files_are_not_found() {
+        let server = stand_in(Vec::new());
+
+        assert_eq!(client().download(&server.url, "a.pdb", KEY).unwrap(), None);
+        // 404 isn't retried
+        assert_eq!(server.requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn skips_stores_that_fail() {
+        let server = stand_in(vec![(&pdb_path("a.pdb"), vec![(200, b"pdb".to_vec())])]);
+        let broken = stand_in(vec![(&pdb_path("a.pdb"), vec![(500, Vec::new())])]);
+        let cache = scratch_directory("symsrv-broken");
+
+        // Truncated compressed file in the cache
+        let compressed = cabinet("a.pdb", b"compressed pdb", 1);
+        let key_directory = cache.join("a.pdb").join(KEY);
+        fs::create_dir_all(&key_directory).unwrap();
+        fs::write(key_directory.join("a.pd_"), &compressed[..compressed.len() - 2]).unwrap();
+
+        let symbol_path = SymbolPath::parse(&format!("srv*{}*{};srv*{}*{}", cache.display(), broken.url, cache.display(), server.url));
+        let path = symbol_path.find(Some(&client()), "a.pdb", KEY).unwrap();
+        assert_eq!(path, Some(cache.join("a.pdb").join(KEY).join("a.pdb")));
+        assert_eq!(broken.requests.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn reports_errors_when_no_store_has_the_file() {
+        let broken = stand_in(vec![(&pdb_path("a.pdb"), vec![(500, Vec::new())])]);
+        let symbol_path = SymbolPath::parse(&format!("srv*{}", broken.url));
+
+        assert!(symbol_path.find(Some(&client()), "a.pdb", KEY).is_err());
+    }
+
+    #[test]
+    fn picks_the_default_downstream_store() {
+        assert_eq!(default_downstream_store(Some(&format!("srv*c:\\symbols*{}", DEFAULT_SYMBOL_SERVER))), PathBuf::from("c:\\symbols"));
+        assert_eq!(default_downstream_store(Some(&format!("{};cache*c:\\cache;srv*{}", "c:\\flat", DEFAULT_SYMBOL_SERVER))), PathBuf::from("c:\\cache"));
+        // No local store, or no symbol path at all
+        assert_eq!(default_downstream_store(Some(&format!("srv*{}", DEFAULT_SYMBOL_SERVER))), PathBuf::from(DEFAULT_CACHE));
+        assert_eq!(default_downstream_store(Some(" ")), PathBuf::from(DEFAULT_CACHE));
+        assert_eq!(default_downstream_store(None), PathBuf::from(DEFAULT_CACHE));
+    }
+
+    #[test]
+    fn downloads_into_the_downstream_stores() {
+        let server = stand_in(vec![(&pdb_path("a.pdb"), vec![(200, b"pdb".to_vec())])]);
+        let cache = scratch_directory("symsrv-downstream");
+        let symbol_path = SymbolPath::parse(&format!("srv*{}*{}", cache.display(), server.url));
+
+        let path = symbol_path.find(Some(&client()), "a.pdb", KEY).unwrap().unwrap();
+        assert_eq!(path, cache.join("a.pdb").join(KEY).join("a.pdb"));
+        assert_eq!(fs::read(&path).unwrap(), b"pdb");
+
+        // Second lookup is served from the cache
+        let path = symbol_path.find(None, "a.pdb", KEY).unwrap();
+        assert_eq!(path, Some(cache.join("a.pdb").join(KEY).join("a.pdb")));
+        assert_eq!(server.requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn finds_the_pdb_of_an_image() {
+        let testdata = fixture("");
+        let path = fetch_pdb_for_image(Path::new(&fixture("symbols.dll")), Some(&testdata), true).unwrap();
+        assert_eq!(path, Some(PathBuf::from(&testdata).join("symbols.pdb")));
+
+        // Flat directories only count when the PDB is the right version
+        let identity = pe::read_pdb_identity(Path::new(&fixture("symbols.dll"))).unwrap();
+        assert_eq!(fetch_pdb(&identity.name, "3844DBB920174967BE7AA4A2C20430FA", 2, Some(&testdata), true).unwrap(), None);
+    }
+}
@@ -0,0 +1,273 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use pyo3::exceptions::*;
+use pyo3::PyResult;
+use crate::cab;
+
+/*
+ *
+ * LOCAL SYMBOL STORES
+ *
+ * Reads (and writes) directories in the layout symstore / symsrv use:
+ *      <store>/<name>/<key>/<name>              single tier
+ *      <store>/<na>/<name>/<key>/<name>         two tier (the store has an index2.txt file)
+ * Each key directory may also hold a compressed file (<name>.pd_) or a file.ptr redirection.
+ *
+ */
+
+const TWO_TIER_MARKER: &str = "index2.txt";
+const FILE_POINTER: &str = "file.ptr";
+
+pub enum StoreFile {
+    // File already exists on disk
+    Path(PathBuf),
+    // File had to be decompressed (or downloaded), and has no path yet
+    Contents(Vec<u8>),
+}
+
+/*
+Checks whether a store uses the two tier layout.
+*/
+fn is_two_tier(directory: &Path) -> bool {
+    directory.join(TWO_TIER_MARKER).is_file()
+}
+
+/*
+Directory holding every version of a file inside a store.
+
+@directory => Store directory.
+@file_name => File name (e.g ntkrnlmp.pdb).
+
+RETURN
+returns <store>/<name> or <store>/<na>/<name> for two tier stores.
+*/
+fn file_directory(directory: &Path, file_name: &str) -> PathBuf {
+    if is_two_tier(directory) {
+        let prefix: String = file_name.chars().take(2).collect();
+        directory.join(prefix).join(file_name)
+    } else {
+        directory.join(file_name)
+    }
+}
+
+/*
+Path of a file inside a symbol store.
+
+@directory => Store directory.
+@file_name => File name (e.g ntkrnlmp.pdb).
+@key       => Index key (see symsrv::index_key).
+
+RETURN
+returns the path the file has (or would have) in the store.
+*/
+pub fn store_path(directory: &Path, file_name: &str, key: &str) -> PathBuf {
+    file_directory(directory, file_name).join(key).join(file_name)
+}
+
+/*
+Name of the compressed version of a file (ntkrnlmp.pdb => ntkrnlmp.pd_)
+*/
+pub fn compressed_file_name(file_name: &str) -> String {
+    let mut compressed = file_name.to_string();
+    compressed.pop();
+    compressed.push('_');
+    compressed
+}
+
+/*
+Follows a file.ptr redirection. The file holds either "PATH:<path>", a bare path, or
+"MSG:<reason>" when the file isn't available.
+
+RETURN
+returns the path it points to, if that path exists.
+*/
+fn follow_file_pointer(pointer_path: &Path) -> PyResult<Option<PathBuf>> {
+    let pointer = fs::read_to_string(pointer_path).map_err(|e| IOError::py_err(e.to_string()))?;
+    let pointer = pointer.trim();
+
+    if pointer.starts_with("MSG:") {
+        return Ok(None);
+    }
+
+    let target = PathBuf::from(pointer.strip_prefix("PATH:").unwrap_or(pointer));
+    if target.is_file() {
+        Ok(Some(target))
+    } else {
+        Ok(None)
+    }
+}
+
+/*
+Looks for a file in a symstore layout directory.
+
+@directory => Store directory.
+@file_name => File name (e.g ntkrnlmp.pdb).
+@key       => Index key (see symsrv::index_key).
+
+RETURN
+returns the file, or None if the store doesn't have it.
+*/
+pub fn find_in_store(directory: &Path, file_name: &str, key: &str) -> PyResult<Option<StoreFile>> {
+    let key_directory = file_directory(directory, file_name).join(key);
+    if !key_directory.is_dir() {
+        return Ok(None);
+    }
+
+    let path = key_directory.join(file_name);
+    if path.is_file() {
+        return Ok(Some(StoreFile::Path(path)));
+    }
+
+    let pointer_path = key_directory.join(FILE_POINTER);
+    if pointer_path.is_file() {
+        if let Some(target) = follow_file_pointer(&pointer_path)? {
+            return Ok(Some(StoreFile::Path(target)));
+        }
+    }
+
+    let compressed_path = key_directory.join(compressed_file_name(file_name));
+    if compressed_path.is_file() {
+        let contents = fs::read(&compressed_path).map_err(|e| IOError::py_err(e.to_string()))?;
+        // LZX / Quantum compressed files count as missing, another store may have them stored
+        if cab::is_supported_cabinet(&contents) {
+            return Ok(Some(StoreFile::Contents(cab::extract_file(&contents, None)?.1)));
+        }
+    }
+    Ok(None)
+}
+
+/*
+Looks for a file in a plain symbol path directory: first directly inside it (only accepted
+if it's the right version), and then as a symbol store.
+
+@directory => Directory from the symbol path.
+@file_name => File name (e.g ntkrnlmp.pdb).
+@key       => Index key (see symsrv::index_key).
+
+RETURN
+returns the file, or None if it isn't there.
+*/
+pub fn find_in_directory(directory: &Path, file_name: &str, key: &str) -> PyResult<Option<StoreFile>> {
+    let path = directory.join(file_name);
+    if path.is_file() && pdb_index_key(&path).ok().as_deref() == Some(key) {
+        return Ok(Some(StoreFile::Path(path)));
+    }
+
+    find_in_store(directory, file_name, key)
+}
+
+/*
+Adds a file into a symbol store (atomically, through a temporary file).
+
+@directory => Store directory.
+@file_name => File name (e.g ntkrnlmp.pdb).
+@key       => Index key (see symsrv::index_key).
+@contents  => File contents.
+
+RETURN
+returns the path of the file inside the store.
+*/
+pub fn add_file(directory: &Path, file_name: &str, key: &str, contents: &[u8]) -> PyResult<PathBuf> {
+    let path = store_path(directory, file_name, key);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| IOError::py_err(e.to_string()))?;
+    }
+
+    let temporary_path = path.with_extension("partial");
+    fs::write(&temporary_path, contents).map_err(|e| IOError::py_err(e.to_string()))?;
+    fs::rename(&temporary_path, &path).map_err(|e| IOError::py_err(e.to_string()))?;
+    Ok(path)
+}
+
+/*
+Computes the index key of an existing PDB file.
+
+@pdb_path => Path to the PDB file.
+
+RETURN
+returns the key (GUID digits followed by the DBI age), the same one its image points to.
+*/
+pub fn pdb_index_key(pdb_path: &Path) -> PyResult<String> {
+    let mut pdb = crate::open_pdb_and_parse(&pdb_path.to_string_lossy())?;
+
+    let information = pdb.pdb_information().map_err(|e| Exception::py_err(e.to_string()))?;
+    let age = match pdb.debug_information() {
+        Ok(debug_information) => debug_information.age().unwrap_or(information.age),
+        Err(_) => information.age
+    };
+    Ok(format!("{:X}{:X}", information.guid.to_simple(), age))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{cabinet, scratch_directory};
+
+    const KEY: &str = "3844DBB920174967BE7AA4A2C20430FA2";
+
+    fn found_path(found: Option<StoreFile>) -> Option<PathBuf> {
+        match found {
+            Some(StoreFile::Path(path)) => Some(path),
+            _ => None
+        }
+    }
+
+    #[test]
+    fn uses_the_single_and_two_tier_layouts() {
+        let store = scratch_directory("symstore-tiers");
+        assert_eq!(store_path(&store, "ntkrnlmp.pdb", KEY), store.join("ntkrnlmp.pdb").join(KEY).join("ntkrnlmp.pdb"));
+
+        fs::write(store.join(TWO_TIER_MARKER), "").unwrap();
+        assert_eq!(store_path(&store, "ntkrnlmp.pdb", KEY), store.join("nt").join("ntkrnlmp.pdb").join(KEY).join("ntkrnlmp.pdb"));
+        assert_eq!(compressed_file_name("ntkrnlmp.pdb"), "ntkrnlmp.pd_");
+    }
+
+    #[test]
+    fn finds_added_files() {
+        let store = scratch_directory("symstore-add");
+        assert!(find_in_store(&store, "a.pdb", KEY).unwrap().is_none());
+
+        let path = add_file(&store, "a.pdb", KEY, b"pdb").unwrap();
+        assert_eq!(found_path(find_in_store(&store, "a.pdb", KEY).unwrap()), Some(path));
+        assert!(find_in_store(&store, "a.pdb", "0123").unwrap().is_none());
+    }
+
+    #[test]
+    fn follows_file_pointers() {
+        let store = scratch_directory("symstore-pointer");
+        let target = store.join("elsewhere.pdb");
+        fs::write(&target, b"pdb").unwrap();
+
+        let key_directory = store.join("a.pdb").join(KEY);
+        fs::create_dir_all(&key_directory).unwrap();
+        fs::write(key_directory.join(FILE_POINTER), format!("PATH:{}", target.display())).unwrap();
+        assert_eq!(found_path(find_in_store(&store, "a.pdb", KEY).unwrap()), Some(target));
+
+        fs::write(key_directory.join(FILE_POINTER), "MSG:not available").unwrap();
+        assert!(find_in_store(&store, "a.pdb", KEY).unwrap().is_none());
+    }
+
+    #[test]
+    fn decompresses_compressed_files() {
+        let store = scratch_directory("symstore-compressed");
+        let key_directory = store.join("a.pdb").join(KEY);
+        fs::create_dir_all(&key_directory).unwrap();
+        fs::write(key_directory.join("a.pd_"), cabinet("a.pdb", b"compressed pdb", 1)).unwrap();
+
+        match find_in_store(&store, "a.pdb", KEY).unwrap() {
+            Some(StoreFile::Contents(contents)) => assert_eq!(contents, b"compressed pdb"),
+            _ => panic!("compressed file not found")
+        }
+
+        fs::write(key_directory.join("a.pd_"), cabinet("a.pdb", b"compressed pdb", 3)).unwrap();
+        assert!(find_in_store(&store, "a.pdb", KEY).unwrap().is_none());
+    }
+
+    #[test]
+    fn ignores_flat_files_of_other_versions() {
+        let directory = scratch_directory("symstore-flat");
+        // Not a PDB, so it can't have the right key
+        fs::write(directory.join("a.pdb"), b"pdb").unwrap();
+        assert!(find_in_directory(&directory, "a.pdb", KEY).unwrap().is_none());
+    }
+}
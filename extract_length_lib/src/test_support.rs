@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use flate2::{Compress, Compression, FlushCompress};
+use pdb::TypeIndex;
+use crate::layout::TypeLayouts;
+
+/*
+ *
+ * TEST SUPPORT
+ *
+ * Scratch directories, cabinets built in memory and a local HTTP stand-in for a symbol server.
+ *
+ */
+
+/*
+Directory under the system temporary directory, removed with everything in it when dropped.
+*/
+pub struct ScratchDirectory(PathBuf);
+
+impl Deref for ScratchDirectory {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScratchDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/*
+Fresh empty scratch directory.
+
+@name => Unique name for the test using it.
+*/
+pub fn scratch_directory(name: &str) -> ScratchDirectory {
+    let directory = env::temp_dir().join(format!("pymspdb-test-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    ScratchDirectory(directory)
+}
+
+/*
+Builds a single folder, single file cabinet.
+
+@name        => Name of the file inside the cabinet.
+@contents    => File contents.
+@compression => Folder compression type (0 stored, 1 MSZIP, others are written as stored data).
+*/
+pub fn cabinet(name: &str, contents: &[u8], compression: u16) -> Vec<u8> {
+    // Blocks hold at most 32K of uncompressed data
+    let blocks: Vec<(Vec<u8>, usize)> = contents.chunks(0x8000).map(|chunk| match compression {
+        1 => {
+            let mut deflater = Compress::new(Compression::default(), false);
+            let mut block = b"CK".to_vec();
+            block.reserve(chunk.len() + 64);
+            deflater.compress_vec(chunk, &mut block, FlushCompress::Finish).unwrap();
+            (block, chunk.len())
+        },
+        _ => (chunk.to_vec(), chunk.len())
+    }).collect();
+
+    let files_offset = 36 + 8;
+    let data_offset = files_offset + 16 + name.len() + 1;
+
+    let mut cabinet = Vec::new();
+    cabinet.extend_from_slice(b"MSCF");
+    cabinet.extend_from_slice(&0u32.to_le_bytes());
+    cabinet.extend_from_slice(&0u32.to_le_bytes());
+    cabinet.extend_from_slice(&0u32.to_le_bytes());
+    cabinet.extend_from_slice(&(files_offset as u32).to_le_bytes());
+    cabinet.extend_from_slice(&0u32.to_le_bytes());
+    cabinet.extend_from_slice(&[3, 1]);
+    cabinet.extend_from_slice(&1u16.to_le_bytes());
+    cabinet.extend_from_slice(&1u16.to_le_bytes());
+    cabinet.extend_from_slice(&0u16.to_le_bytes());
+    cabinet.extend_from_slice(&0u16.to_le_bytes());
+    cabinet.extend_from_slice(&0u16.to_le_bytes());
+
+    cabinet.extend_from_slice(&(data_offset as u32).to_le_bytes());
+    cabinet.extend_from_slice(&(blocks.len() as u16).to_le_bytes());
+    cabinet.extend_from_slice(&compression.to_le_bytes());
+
+    cabinet.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+    cabinet.extend_from_slice(&0u32.to_le_bytes());
+    cabinet.extend_from_slice(&0u16.to_le_bytes());
+    cabinet.extend_from_slice(&[0; 6]);
+    cabinet.extend_from_slice(name.as_bytes());
+    cabinet.push(0);
+
+    for (block, uncompressed_size) in blocks {
+        cabinet.extend_from_slice(&0u32.to_le_bytes());
+        cabinet.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        cabinet.extend_from_slice(&(uncompressed_size as u16).to_le_bytes());
+        cabinet.extend_from_slice(&block);
+    }
+    let size = cabinet.len() as u32;
+    cabinet[8..12].copy_from_slice(&size.to_le_bytes());
+    cabinet
+}
+
+// Status code and body
+type Response = (u16, Vec<u8>);
+
+/*
+Local HTTP server answering GETs from a fixed script. Every path has a list of responses, each
+request takes the next one and the last one repeats. Unknown paths get a 404.
+*/
+pub struct StandIn {
+    pub url: String,
+    // Paths requested, in order
+    pub requests: Arc<Mutex<Vec<String>>>,
+}
+
+pub fn stand_in(script: Vec<(&str, Vec<Response>)>) -> StandIn {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+
+    let mut script: HashMap<String, Vec<Response>> = script.into_iter().map(|(path, responses)| (String::from(path), responses)).collect();
+    let log = Arc::clone(&requests);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue
+            };
+
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                match stream.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => request.extend_from_slice(&buffer[..read])
+                }
+            }
+            let request = String::from_utf8_lossy(&request);
+            let path = request.split_whitespace().nth(1).unwrap_or("").to_string();
+            log.lock().unwrap().push(path.clone());
+
+            let (status, body) = match script.get_mut(&path) {
+                Some(responses) if responses.len() > 1 => responses.remove(0),
+                Some(responses) if !responses.is_empty() => responses[0].clone(),
+                _ => (404, Vec::new())
+            };
+            let header = format!("HTTP/1.1 {} Scripted\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len());
+            let _ = stream.write_all(header.as_bytes());
+            let _ = stream.write_all(&body);
+        }
+    });
+
+    StandIn { url, requests }
+}
+
+/*
+Path of a file in testdata (see the generators there for what each PDB holds).
+*/
+pub fn fixture(name: &str) -> String {
+    format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/*
+Type layouts of kernel.pdb.
+*/
+pub fn kernel_layouts() -> TypeLayouts {
+    TypeLayouts::load(&fixture("kernel.pdb")).unwrap()
+}
+
+/*
+Indexes of kernel.pdb definitions, by name.
+*/
+pub fn kernel_types(layouts: &TypeLayouts, names: &[&str]) -> Vec<TypeIndex> {
+    names.iter().map(|name| layouts.find(name).unwrap()).collect()
+}
+
+/*
+A field found by a lookup: its path, its offset and whatever else the lookup tells about it.
+*/
+pub fn hit<T>(name: &str, offset: u64, extra: T) -> (String, u64, T) {
+    (String::from(name), offset, extra)
+}
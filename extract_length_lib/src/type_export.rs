@@ -0,0 +1,265 @@
+use std::convert::TryFrom;
+use pdb::{PrimitiveKind, TypeIndex};
+use serde_json::{json, Value};
+use crate::layout::{self, Enumeration, TypeLayouts, TypeRef, Udt, UdtKind};
+
+/*
+ *
+ * TYPE EXPORT
+ *
+ * Writes type layouts as JSON, under a versioned schema (bump SCHEMA_VERSION on any change that
+ * breaks readers):
+ *
+ *      {"schema": "pymspdb.types", "version": 1,
+ *       "pdb": {"file", "guid", "age", "pointer_size"},
+ *       "types": [type, ...]}
+ *
+ * A type is a struct / class / interface / union / enum definition, identified by its TPI index
+ * ("id", stable for a given PDB):
+ *      {"id", "kind", "name", "size", "alignment", "packing" (null when natural),
+ *       "has_vfptr", "bases": [{"offset", "type"}],
+ *       "fields": [{"name", "offset", "size", "type", "bitfield": null or {"position", "length"}}]}
+ *      {"id", "kind": "enum", "name", "size", "alignment", "underlying",
+ *       "values": [{"name", "value"}]}
+ *
+ * Field / base / underlying types are references:
+ *      {"kind": "primitive", "name", "size"}                   name as the pdb crate calls it (ULong, U64, WChar, ...)
+ *      {"kind": "struct" / "class" / "interface" / "union" / "enum", "id", "name"}
+ *      {"kind": "declared", "udt_kind", "name"}                forward declared only, not in "types"
+ *      {"kind": "pointer", "size", "pointee"}
+ *      {"kind": "array", "count", "size", "element"}
+ *      {"kind": "modifier", "const", "volatile", "type"}
+ *      {"kind": "function"} / {"kind": "unknown"}
+ *
+ * Newline delimited JSON has the document without "types" on the first line, then a type per line.
+ *
+ */
+
+pub const SCHEMA: &str = "pymspdb.types";
+pub const SCHEMA_VERSION: u32 = 1;
+
+pub fn udt_kind_name(kind: UdtKind) -> &'static str {
+    match kind {
+        UdtKind::Struct => "struct",
+        UdtKind::Class => "class",
+        UdtKind::Interface => "interface",
+        UdtKind::Union => "union",
+    }
+}
+
+/*
+Enumerate values as JSON numbers (signed when they fit, 64 bit unsigned otherwise, strings past
+64 bits).
+*/
+fn integer(value: i128) -> Value {
+    match (i64::try_from(value), u64::try_from(value)) {
+        (Ok(value), _) => json!(value),
+        (_, Ok(value)) => json!(value),
+        _ => json!(value.to_string())
+    }
+}
+
+fn primitive_ref(kind: PrimitiveKind) -> Value {
+    json!({
+        "kind": "primitive",
+        "name": format!("{:?}", kind),
+        "size": layout::primitive_size(kind),
+    })
+}
+
+pub fn type_ref(layouts: &TypeLayouts, typ: &TypeRef) -> Value {
+    match typ {
+        TypeRef::Primitive(kind) => primitive_ref(*kind),
+        TypeRef::Udt(index) => json!({
+            "kind": layouts.udt(*index).map_or("struct", |udt| udt_kind_name(udt.kind)),
+            "id": index.0,
+            "name": layouts.name(*index),
+        }),
+        TypeRef::Enum(index) => json!({
+            "kind": "enum",
+            "id": index.0,
+            "name": layouts.name(*index),
+        }),
+        TypeRef::Declared { kind, name } => json!({
+            "kind": "declared",
+            "udt_kind": udt_kind_name(*kind),
+            "name": name,
+        }),
+        TypeRef::Pointer { pointee, size } => json!({
+            "kind": "pointer",
+            "size": size,
+            "pointee": type_ref(layouts, pointee),
+        }),
+        TypeRef::Array { element, count } => json!({
+            "kind": "array",
+            "count": count,
+            "size": layouts.size_of(typ),
+            "element": type_ref(layouts, element),
+        }),
+        TypeRef::Modified { inner, constant, volatile } => json!({
+            "kind": "modifier",
+            "const": constant,
+            "volatile": volatile,
+            "type": type_ref(layouts, inner),
+        }),
+        TypeRef::Function => json!({"kind": "function"}),
+        TypeRef::Unknown => json!({"kind": "unknown"}),
+    }
+}
+
+fn udt_value(layouts: &TypeLayouts, udt: &Udt) -> Value {
+    let fields: Vec<Value> = udt.fields.iter().map(|field| json!({
+        "name": field.name,
+        "offset": field.offset,
+        "size": layouts.size_of(&field.typ),
+        "type": type_ref(layouts, &field.typ),
+        "bitfield": field.bitfield.map(|bitfield| json!({
+            "position": bitfield.position,
+            "length": bitfield.length,
+        })),
+    })).collect();
+
+    let bases: Vec<Value> = udt.bases.iter().map(|base| json!({
+        "offset": base.offset,
+        "type": type_ref(layouts, &base.typ),
+    })).collect();
+
+    json!({
+        "id": udt.index.0,
+        "kind": udt_kind_name(udt.kind),
+        "name": udt.name,
+        "size": udt.size,
+        "alignment": layouts.alignment(udt),
+        "packing": layouts.packing(udt),
+        "has_vfptr": udt.has_vfptr,
+        "bases": bases,
+        "fields": fields,
+    })
+}
+
+fn enum_value(index: TypeIndex, enumeration: &Enumeration) -> Value {
+    let values: Vec<Value> = enumeration.values.iter().map(|(name, value)| json!({
+        "name": name,
+        "value": integer(layout::underlying_value(*value, enumeration.underlying)),
+    })).collect();
+
+    let size = layout::primitive_size(enumeration.underlying);
+    json!({
+        "id": index.0,
+        "kind": "enum",
+        "name": enumeration.name,
+        "size": size,
+        "alignment": size.max(1),
+        "underlying": primitive_ref(enumeration.underlying),
+        "values": values,
+    })
+}
+
+pub fn type_value(layouts: &TypeLayouts, index: TypeIndex) -> Value {
+    match (layouts.udt(index), layouts.enumeration(index)) {
+        (Some(udt), _) => udt_value(layouts, udt),
+        (_, Some(enumeration)) => enum_value(index, enumeration),
+        _ => Value::Null
+    }
+}
+
+fn document(layouts: &TypeLayouts, pdb_name: &str) -> serde_json::Map<String, Value> {
+    let mut document = serde_json::Map::new();
+    document.insert(String::from("schema"), json!(SCHEMA));
+    document.insert(String::from("version"), json!(SCHEMA_VERSION));
+    document.insert(String::from("pdb"), json!({
+        "file": pdb_name,
+        "guid": layouts.guid(),
+        "age": layouts.age(),
+        "pointer_size": layouts.pointer_size(),
+    }));
+    document
+}
+
+/*
+Exports types as JSON.
+
+@layouts  => Type layouts of the PDB.
+@types    => Definitions to export, in order.
+@pdb_name => PDB the types come from.
+@ndjson   => Write newline delimited JSON (the document header, then a type per line) instead of
+             a single document.
+
+RETURN
+returns the JSON text.
+*/
+pub fn export(layouts: &TypeLayouts, types: &[TypeIndex], pdb_name: &str, ndjson: bool) -> String {
+    let mut document = document(layouts, pdb_name);
+    let types = types.iter().map(|&index| type_value(layouts, index));
+
+    if ndjson {
+        let mut lines = vec![Value::Object(document).to_string()];
+        lines.extend(types.map(|typ| typ.to_string()));
+        lines.push(String::new());
+        return lines.join("\n");
+    }
+
+    document.insert(String::from("types"), Value::Array(types.collect()));
+    serde_json::to_string_pretty(&Value::Object(document)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{kernel_layouts, kernel_types};
+
+    fn exported(names: &[&str], ndjson: bool) -> String {
+        let layouts = kernel_layouts();
+        let types = kernel_types(&layouts, names);
+        export(&layouts, &types, "kernel.pdb", ndjson)
+    }
+
+    fn field<'v>(typ: &'v Value, name: &str) -> &'v Value {
+        typ["fields"].as_array().unwrap().iter().find(|field| field["name"] == name).unwrap()
+    }
+
+    #[test]
+    fn exports_a_versioned_document() {
+        let document: Value = serde_json::from_str(&exported(&["_EPROCESS"], false)).unwrap();
+        assert_eq!(document["schema"], SCHEMA);
+        assert_eq!(document["version"], SCHEMA_VERSION);
+        assert_eq!(document["pdb"]["file"], "kernel.pdb");
+        assert_eq!(document["pdb"]["pointer_size"], 8);
+
+        let eprocess = &document["types"][0];
+        assert_eq!(eprocess["kind"], "struct");
+        assert_eq!(eprocess["size"], 0x98);
+        assert_eq!(eprocess["alignment"], 8);
+        assert_eq!(eprocess["packing"], Value::Null);
+        assert_eq!(field(eprocess, "Spare")["bitfield"], json!({"position": 1, "length": 31}));
+        assert_eq!(field(eprocess, "Peb")["type"]["kind"], "pointer");
+        assert_eq!(field(eprocess, "Peb")["type"]["pointee"], json!({"kind": "declared", "udt_kind": "struct", "name": "_PEB"}));
+
+        let matrix = &field(eprocess, "Matrix")["type"];
+        assert_eq!((matrix["kind"].clone(), matrix["count"].clone(), matrix["size"].clone()), (json!("array"), json!(2), json!(12)));
+        assert_eq!(matrix["element"]["element"]["kind"], "modifier");
+        assert_eq!(matrix["element"]["element"]["const"], true);
+    }
+
+    #[test]
+    fn exports_enums_and_packing() {
+        let document: Value = serde_json::from_str(&exported(&["_PS_STATE", "_PACKED_HEADER"], false)).unwrap();
+        let state = &document["types"][0];
+        assert_eq!(state["kind"], "enum");
+        assert_eq!(state["size"], 4);
+        assert_eq!(state["values"][2], json!({"name": "PsTerminated", "value": -2147483648i64}));
+        assert_eq!(document["types"][1]["packing"], 1);
+    }
+
+    #[test]
+    fn writes_a_type_per_line() {
+        let text = exported(&["_LIST_ENTRY", "_MIX"], true);
+        let lines: Vec<Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["schema"], SCHEMA);
+        assert!(lines[0].get("types").is_none());
+        assert_eq!(lines[1]["name"], "_LIST_ENTRY");
+        assert_eq!(lines[2]["name"], "_MIX");
+        assert!(text.ends_with('\n'));
+    }
+}
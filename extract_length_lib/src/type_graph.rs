@@ -0,0 +1,428 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use pdb::TypeIndex;
+use crate::field_search::Relation;
+use crate::layout::{self, TypeLayouts, TypeRef, Udt};
+use crate::symbols::SymbolKind;
+use crate::type_export;
+
+/*
+ *
+ * TYPE GRAPH
+ *
+ * Cross references between types: a struct / union embeds a type (by value, as array elements or
+ * as a base class) or points to it. Global variables and functions (their return and argument
+ * types) use types the same way. The graph of a type is everything within depth hops of it, in
+ * the directions asked for: "in" (what reaches the type, e.g everything that leads to _TOKEN),
+ * "out" (what the type reaches) or "both". Symbols only ever start edges, they're never expanded.
+ *
+ * Fields of unnamed structs / unions belong to the named type embedding them (u.Value). Exports
+ * as Graphviz DOT or Mermaid, pointer edges dashed.
+ *
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+    Both,
+}
+
+impl Direction {
+    pub fn parse(name: &str) -> Option<Direction> {
+        match name {
+            "in" => Some(Direction::In),
+            "out" => Some(Direction::Out),
+            "both" => Some(Direction::Both),
+            _ => None
+        }
+    }
+}
+
+/*
+A global variable or function, with the types it uses (GlobalSymbol::type_index through
+TypeLayouts::symbol_types).
+*/
+pub struct SymbolUse {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub types: Vec<TypeRef>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: String,
+    // struct / class / interface / union / enum / declared, or data / function for symbols
+    pub kind: &'static str,
+    // Hops from the root
+    pub distance: usize,
+}
+
+impl Node {
+    pub fn is_symbol(&self) -> bool {
+        self.kind == "data" || self.kind == "function"
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub from: String,
+    // Edges from a symbol start at a data / function node, never at a type named the same
+    pub from_symbol: bool,
+    pub to: String,
+    pub relation: Relation,
+    // Fields making the reference, empty for symbols
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TypeGraph {
+    pub root: String,
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+// (is a symbol, name)
+type NodeKey = (bool, String);
+
+/*
+Every reference in the PDB.
+*/
+struct References {
+    // (from, to type, through a pointer) => fields
+    edges: BTreeMap<(NodeKey, String, bool), Vec<String>>,
+    kinds: HashMap<NodeKey, &'static str>,
+}
+
+impl References {
+    fn type_kind(layouts: &TypeLayouts, typ: &TypeRef, name: &str) -> &'static str {
+        let index = match layout::strip_modifiers(typ) {
+            TypeRef::Udt(index) | TypeRef::Enum(index) => Some(*index),
+            _ => layouts.find(name)
+        };
+        match index.map(|index| (layouts.udt(index), layouts.enumeration(index))) {
+            Some((Some(udt), _)) => type_export::udt_kind_name(udt.kind),
+            Some((_, Some(_))) => "enum",
+            _ => "declared"
+        }
+    }
+
+    fn add(&mut self, from: &NodeKey, typ: &TypeRef, layouts: &TypeLayouts, field: Option<String>) {
+        let (to, pointed) = match layouts.referenced_type(typ) {
+            Some(target) => target,
+            None => return
+        };
+        if layout::is_unnamed(&to) {
+            return;
+        }
+
+        let kind = Self::type_kind(layouts, typ, &to);
+        self.kinds.entry((false, to.clone())).or_insert(kind);
+
+        let fields = self.edges.entry((from.clone(), to, pointed)).or_default();
+        if let Some(field) = field {
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+    }
+
+    fn add_udt(&mut self, from: &NodeKey, udt: &Udt, prefix: &str, layouts: &TypeLayouts) {
+        for field in layouts.all_fields(udt) {
+            let name = format!("{}{}", prefix, field.name);
+            if let TypeRef::Udt(index) = layout::strip_modifiers(&field.typ) {
+                if let Some(inner) = layouts.udt(*index).filter(|inner| layout::is_unnamed(&inner.name)) {
+                    self.add_udt(from, inner, &format!("{}.", name), layouts);
+                    continue;
+                }
+            }
+            self.add(from, &field.typ, layouts, Some(name));
+        }
+    }
+
+    fn collect(layouts: &TypeLayouts, symbols: &[SymbolUse]) -> References {
+        let mut references = References {
+            edges: BTreeMap::new(),
+            kinds: HashMap::new(),
+        };
+
+        let mut seen = HashSet::new();
+        for index in layouts.definitions() {
+            let name = layouts.name(index);
+            if layout::is_unnamed(name) || !seen.insert(name) {
+                continue;
+            }
+            let key = (false, String::from(name));
+            match (layouts.udt(index), layouts.enumeration(index)) {
+                (Some(udt), _) => {
+                    references.kinds.insert(key.clone(), type_export::udt_kind_name(udt.kind));
+                    references.add_udt(&key, udt, "", layouts);
+                },
+                (_, Some(_)) => {
+                    references.kinds.insert(key, "enum");
+                },
+                _ => {}
+            }
+        }
+
+        for symbol in symbols {
+            let key = (true, symbol.name.clone());
+            references.kinds.insert(key.clone(), match symbol.kind {
+                SymbolKind::Function => "function",
+                _ => "data"
+            });
+            for typ in &symbol.types {
+                references.add(&key, typ, layouts, None);
+            }
+        }
+        references
+    }
+}
+
+/*
+Builds the cross reference graph around a type.
+
+@layouts   => Type layouts of the PDB.
+@root      => Type to start from.
+@symbols   => Global variables / functions to include (empty to leave symbols out).
+@depth     => Hops to go from the root.
+@direction => Follow the references to the root (In), from it (Out), or both.
+
+RETURN
+returns the nodes (root first, then by distance) and the edges between them.
+*/
+pub fn build(layouts: &TypeLayouts, root: TypeIndex, symbols: &[SymbolUse], depth: usize, direction: Direction) -> TypeGraph {
+    let references = References::collect(layouts, symbols);
+    let root: NodeKey = (false, String::from(layouts.name(root)));
+
+    let mut outgoing: HashMap<&NodeKey, Vec<NodeKey>> = HashMap::new();
+    let mut incoming: HashMap<NodeKey, Vec<&NodeKey>> = HashMap::new();
+    for (from, to, _) in references.edges.keys() {
+        outgoing.entry(from).or_default().push((false, to.clone()));
+        incoming.entry((false, to.clone())).or_default().push(from);
+    }
+
+    let mut distances: HashMap<NodeKey, usize> = HashMap::new();
+    let mut order = vec![root.clone()];
+    let mut queue = VecDeque::from(vec![root.clone()]);
+    distances.insert(root.clone(), 0);
+
+    while let Some(key) = queue.pop_front() {
+        let distance = distances[&key];
+        // Symbols only start edges, a symbol reached from one of its types isn't expanded
+        if distance >= depth || key.0 {
+            continue;
+        }
+
+        let mut neighbours: Vec<NodeKey> = Vec::new();
+        if direction != Direction::In {
+            neighbours.extend(outgoing.get(&key).into_iter().flatten().cloned());
+        }
+        if direction != Direction::Out {
+            neighbours.extend(incoming.get(&key).into_iter().flatten().map(|&from| from.clone()));
+        }
+        for neighbour in neighbours {
+            if !distances.contains_key(&neighbour) {
+                distances.insert(neighbour.clone(), distance + 1);
+                order.push(neighbour.clone());
+                queue.push_back(neighbour);
+            }
+        }
+    }
+
+    let nodes: Vec<Node> = order.iter().map(|key| Node {
+        name: key.1.clone(),
+        kind: references.kinds.get(key).copied().unwrap_or("declared"),
+        distance: distances[key],
+    }).collect();
+
+    let edges: Vec<Edge> = references.edges.iter()
+                                           .filter(|((from, to, _), _)| distances.contains_key(from) && distances.contains_key(&(false, to.clone())))
+                                           .map(|((from, to, pointed), fields)| Edge {
+                                               from: from.1.clone(),
+                                               from_symbol: from.0,
+                                               to: to.clone(),
+                                               relation: if *pointed { Relation::Points } else { Relation::Embeds },
+                                               fields: fields.clone(),
+                                           })
+                                           .collect();
+
+    TypeGraph { root: root.1, nodes, edges }
+}
+
+fn edge_label(fields: &[String]) -> String {
+    match fields.len() {
+        0..=3 => fields.join(", "),
+        _ => format!("{}, ... ({} fields)", fields[..3].join(", "), fields.len())
+    }
+}
+
+fn dot_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/*
+DOT node id, symbols prefixed so they stay apart from types named the same.
+*/
+fn dot_id(name: &str, symbol: bool) -> String {
+    match symbol {
+        true => dot_string(&format!("symbol {}", name)),
+        false => dot_string(name)
+    }
+}
+
+/*
+Writes a graph as Graphviz DOT: embedding edges solid, pointer edges dashed, the root bold,
+variables as ellipses and functions as components.
+*/
+pub fn to_dot(graph: &TypeGraph) -> String {
+    let mut lines = vec![
+        format!("digraph {} {{", dot_string(&format!("xrefs of {}", graph.root))),
+        String::from("    rankdir=LR;"),
+        String::from("    node [shape=box, fontname=\"Consolas\"];"),
+        String::from("    edge [fontname=\"Consolas\", fontsize=10];"),
+    ];
+
+    for node in &graph.nodes {
+        let shape = match node.kind {
+            "data" => "shape=ellipse, ",
+            "function" => "shape=component, ",
+            "enum" => "shape=hexagon, ",
+            "declared" => "style=dotted, ",
+            _ => ""
+        };
+        let root = match node.distance {
+            0 => ", style=bold, penwidth=2",
+            _ => ""
+        };
+        lines.push(format!("    {} [{}label={}{}];", dot_id(&node.name, node.is_symbol()), shape, dot_string(&node.name), root));
+    }
+
+    for edge in &graph.edges {
+        let mut attributes = Vec::new();
+        if !edge.fields.is_empty() {
+            attributes.push(format!("label={}", dot_string(&edge_label(&edge.fields))));
+        }
+        if edge.relation == Relation::Points {
+            attributes.push(String::from("style=dashed, arrowhead=empty"));
+        }
+        let attributes = match attributes.is_empty() {
+            true => String::new(),
+            false => format!(" [{}]", attributes.join(", "))
+        };
+        lines.push(format!("    {} -> {}{};", dot_id(&edge.from, edge.from_symbol), dot_id(&edge.to, false), attributes));
+    }
+
+    lines.push(String::from("}"));
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn mermaid_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "#quot;").replace('<', "#lt;").replace('>', "#gt;"))
+}
+
+/*
+Writes a graph as a Mermaid flowchart: embedding edges -->, pointer edges -.->, the root with a
+thick border, variables as stadiums and functions as subroutines.
+*/
+pub fn to_mermaid(graph: &TypeGraph) -> String {
+    let mut lines = vec![String::from("graph LR")];
+
+    let mut ids: HashMap<(bool, &str), String> = HashMap::new();
+    for (position, node) in graph.nodes.iter().enumerate() {
+        let id = format!("n{}", position);
+        let label = mermaid_string(&node.name);
+        let shape = match node.kind {
+            "data" => format!("([{}])", label),
+            "function" => format!("[[{}]]", label),
+            "enum" => format!("{{{{{}}}}}", label),
+            _ => format!("[{}]", label)
+        };
+        lines.push(format!("    {}{}", id, shape));
+        ids.insert((node.is_symbol(), node.name.as_str()), id);
+    }
+
+    for edge in &graph.edges {
+        let (from, to) = match (ids.get(&(edge.from_symbol, edge.from.as_str())), ids.get(&(false, edge.to.as_str()))) {
+            (Some(from), Some(to)) => (from, to),
+            _ => continue
+        };
+        let arrow = match edge.relation {
+            Relation::Embeds => "-->",
+            Relation::Points => "-.->",
+        };
+        match edge.fields.is_empty() {
+            true => lines.push(format!("    {} {} {}", from, arrow, to)),
+            false => lines.push(format!("    {} {}|{}| {}", from, arrow, mermaid_string(&edge_label(&edge.fields)), to))
+        }
+    }
+
+    lines.push(String::from("    style n0 stroke-width:3px"));
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{kernel_layouts, kernel_types};
+
+    fn graph(root: &str, depth: usize, direction: Direction, with_symbols: bool) -> TypeGraph {
+        let layouts = kernel_layouts();
+        let list_entry = kernel_types(&layouts, &["_LIST_ENTRY"])[0];
+        let symbols = match with_symbols {
+            true => vec![SymbolUse { name: String::from("PsActiveProcessHead"), kind: SymbolKind::Data, types: vec![TypeRef::Udt(list_entry)] }],
+            false => Vec::new()
+        };
+        build(&layouts, kernel_types(&layouts, &[root])[0], &symbols, depth, direction)
+    }
+
+    fn nodes(graph: &TypeGraph) -> Vec<(&str, &str, usize)> {
+        graph.nodes.iter().map(|node| (node.name.as_str(), node.kind, node.distance)).collect()
+    }
+
+    #[test]
+    fn follows_references_to_the_root() {
+        let graph = graph("_LIST_ENTRY", 1, Direction::In, true);
+        assert_eq!(nodes(&graph), vec![("_LIST_ENTRY", "struct", 0), ("_EPROCESS", "struct", 1), ("_ETHREAD", "struct", 1), ("_KPROCESS", "struct", 1),
+                                       ("PsActiveProcessHead", "data", 1)]);
+
+        let kprocess = graph.edges.iter().find(|edge| edge.from == "_KPROCESS").unwrap();
+        assert_eq!((kprocess.relation, kprocess.fields.clone()), (Relation::Embeds, vec![String::from("ProfileListHead"), String::from("ThreadListHead")]));
+        // Edges between nodes other than the root are kept
+        assert!(graph.edges.iter().any(|edge| edge.from == "_ETHREAD" && edge.to == "_EPROCESS" && edge.relation == Relation::Points));
+        assert!(graph.edges.iter().any(|edge| edge.from_symbol && edge.from == "PsActiveProcessHead" && edge.fields.is_empty()));
+    }
+
+    #[test]
+    fn follows_references_from_the_root() {
+        let graph = graph("_ETHREAD", 2, Direction::Out, false);
+        assert_eq!(nodes(&graph), vec![("_ETHREAD", "struct", 0), ("_EPROCESS", "struct", 1), ("_LIST_ENTRY", "struct", 1), ("_EX_FAST_REF", "union", 2),
+                                       ("_KPROCESS", "struct", 2), ("_PEB", "declared", 2), ("_PS_STATE", "enum", 2)]);
+        assert_eq!(nodes(&self::graph("_ETHREAD", 0, Direction::Both, false)), vec![("_ETHREAD", "struct", 0)]);
+    }
+
+    #[test]
+    fn exports_dot_and_mermaid() {
+        let graph = graph("_LIST_ENTRY", 1, Direction::In, true);
+        let dot = to_dot(&graph);
+        assert!(dot.starts_with("digraph \"xrefs of _LIST_ENTRY\" {\n"));
+        assert!(dot.contains("\n    \"_LIST_ENTRY\" [label=\"_LIST_ENTRY\", style=bold, penwidth=2];\n"));
+        assert!(dot.contains("\n    \"symbol PsActiveProcessHead\" [shape=ellipse, label=\"PsActiveProcessHead\"];\n"));
+        assert!(dot.contains("\n    \"_LIST_ENTRY\" -> \"_LIST_ENTRY\" [label=\"Flink, Blink\", style=dashed, arrowhead=empty];\n"));
+        assert!(dot.contains("\n    \"symbol PsActiveProcessHead\" -> \"_LIST_ENTRY\";\n"));
+
+        let mermaid = to_mermaid(&graph);
+        assert!(mermaid.starts_with("graph LR\n    n0[\"_LIST_ENTRY\"]\n"));
+        assert!(mermaid.contains("\n    n4([\"PsActiveProcessHead\"])\n"));
+        assert!(mermaid.contains("\n    n0 -.->|\"Flink, Blink\"| n0\n"));
+        assert!(mermaid.ends_with("    style n0 stroke-width:3px\n"));
+    }
+
+    #[test]
+    fn shortens_long_edge_labels() {
+        let fields: Vec<String> = (0..5).map(|count| format!("Field{}", count)).collect();
+        assert_eq!(edge_label(&fields), "Field0, Field1, Field2, ... (5 fields)");
+        assert_eq!(mermaid_string("a<b>\"c\""), "\"a#lt;b#gt;#quot;c#quot;\"");
+    }
+}
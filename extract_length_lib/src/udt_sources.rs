@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::fs::File;
+use fallible_iterator::FallibleIterator;
+use pdb::TypeIndex;
+
+/*
+ *
+ * UDT SOURCE LINES
+ *
+ * The IPI stream maps every struct / union / enum definition to the header it was defined in:
+ *      LF_UDT_SRC_LINE         file name is a LF_STRING_ID in the IPI stream
+ *      LF_UDT_MOD_SRC_LINE     file name is an offset into the /names string table (the pdb
+ *                              crate hands it over as an IdIndex anyway)
+ *
+ */
+
+/*
+Name of the file a UDT source record points at.
+*/
+fn source_file_name(source: &pdb::UserDefinedTypeSourceId, id_finder: &pdb::IdFinder,
+                    string_table: Option<&pdb::StringTable>) -> Option<String> {
+    match source.module {
+        Some(_) => {
+            let name = pdb::StringRef(source.source_file.0).to_string_lossy(string_table?).ok()?;
+            Some(name.into_owned())
+        },
+        None => match id_finder.find(source.source_file).and_then(|id| id.parse()).ok()? {
+            pdb::IdData::String(string) => Some(string.name.to_string().into_owned()),
+            _ => None
+        }
+    }
+}
+
+/*
+Maps UDT definitions to the source line they were defined at.
+
+@pdb => PDB file object.
+
+RETURN
+returns "<file>:<line>" (or only "<file>" when the line is unknown) per UDT type index.
+Empty for PDBs without an IPI stream, or without UDT source records (which older compilers
+didn't emit).
+*/
+pub fn collect_udt_sources(pdb: &mut pdb::PDB<File>) -> HashMap<TypeIndex, String> {
+    let mut sources = HashMap::new();
+
+    let id_information = match pdb.id_information() {
+        Ok(id_information) => id_information,
+        Err(_) => return sources
+    };
+    let string_table = pdb.string_table().ok();
+
+    let mut id_finder = id_information.finder();
+    let mut records = Vec::new();
+    let mut iter = id_information.iter();
+    while let Ok(Some(id)) = iter.next() {
+        id_finder.update(&iter);
+        if let Ok(pdb::IdData::UserDefinedTypeSource(source)) = id.parse() {
+            records.push(source);
+        }
+    }
+
+    // File names may be LF_STRING_IDs further down the stream, so only resolve once all ids are known
+    for source in records {
+        if let Some(file_name) = source_file_name(&source, &id_finder, string_table.as_ref()) {
+            let origin = match source.line {
+                0 => file_name,
+                line => format!("{}:{}", file_name, line)
+            };
+            sources.insert(source.udt, origin);
+        }
+    }
+    sources
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fixture;
+
+    #[test]
+    fn maps_definitions_to_their_source() {
+        let mut pdb = crate::open_pdb_and_parse(&fixture("symbols.pdb")).unwrap();
+        let sources = collect_udt_sources(&mut pdb);
+
+        let mut indexes: Vec<u32> = sources.keys().map(|index| index.0).collect();
+        indexes.sort_unstable();
+        assert_eq!(indexes, vec![0x1009, 0x100c, 0x100e]);
+        // rustc records neither the file nor the line, LF_STRING_ID names are resolved anyway
+        assert_eq!(sources[&TypeIndex(0x1009)], "\\<unknown>");
+
+        let mut pdb = crate::open_pdb_and_parse(&fixture("kernel.pdb")).unwrap();
+        assert!(collect_udt_sources(&mut pdb).is_empty());
+    }
+}
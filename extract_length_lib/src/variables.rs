@@ -0,0 +1,387 @@
+use fallible_iterator::FallibleIterator;
+use pdb::{PdbInternalSectionOffset, TypeFinder, TypeIndex};
+use pyo3::exceptions::*;
+use pyo3::{PyErr, PyResult};
+use crate::bytes::{read_u16, read_u32};
+use crate::symbols;
+
+/*
+ *
+ * FUNCTION PARAMETERS AND LOCALS
+ *
+ * Variables of a function are the records nested inside its S_GPROC32 / S_LPROC32 scope:
+ *      S_REGREL32 / S_BPREL32      register (or frame pointer) relative stack variables
+ *      S_REGISTER                  variables living in a register for the whole function
+ *      S_LOCAL + S_DEFRANGE_*      optimized code, every def-range says where the variable
+ *                                  lives for a range of instructions
+ * The pdb crate doesn't parse S_BPREL32, S_FRAMEPROC nor the def-ranges, so they're read here.
+ *
+ */
+
+const S_FRAMEPROC: u16 = 0x1012;
+const S_BPREL32: u16 = 0x110b;
+const S_DEFRANGE: u16 = 0x113f;
+const S_DEFRANGE_SUBFIELD: u16 = 0x1140;
+const S_DEFRANGE_REGISTER: u16 = 0x1141;
+const S_DEFRANGE_FRAMEPOINTER_REL: u16 = 0x1142;
+const S_DEFRANGE_SUBFIELD_REGISTER: u16 = 0x1143;
+const S_DEFRANGE_FRAMEPOINTER_REL_FULL_SCOPE: u16 = 0x1144;
+const S_DEFRANGE_REGISTER_REL: u16 = 0x1145;
+
+#[derive(Debug, Clone)]
+pub struct Location {
+    // e.g "RCX", "[RSP+0x28]"
+    pub description: String,
+    // Code range the location is valid in, None when it's valid for the whole function
+    pub rva: Option<u32>,
+    pub length: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub name: String,
+    pub type_name: Option<String>,
+    pub is_parameter: bool,
+    pub locations: Vec<Location>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionVariables {
+    pub function: String,
+    pub rva: Option<u32>,
+    pub parameters: Vec<Variable>,
+    pub locals: Vec<Variable>,
+}
+
+pub enum FunctionQuery<'a> {
+    Name(&'a str),
+    Rva(u32),
+}
+
+fn pdb_error(e: pdb::Error) -> PyErr {
+    Exception::py_err(e.to_string())
+}
+
+/*
+CodeView register names (CV_REG_* / CV_AMD64_*). Only the ones variables actually live in.
+*/
+fn register_name(register: u16) -> String {
+    const X86: [&str; 35] = [
+        "NONE", "AL", "CL", "DL", "BL", "AH", "CH", "DH", "BH", "AX", "CX", "DX", "BX", "SP", "BP", "SI", "DI",
+        "EAX", "ECX", "EDX", "EBX", "ESP", "EBP", "ESI", "EDI", "ES", "CS", "SS", "DS", "FS", "GS", "IP", "FLAGS",
+        "EIP", "EFLAGS",
+    ];
+    const AMD64: [&str; 16] = [
+        "RAX", "RBX", "RCX", "RDX", "RSI", "RDI", "RBP", "RSP", "R8", "R9", "R10", "R11", "R12", "R13", "R14", "R15",
+    ];
+
+    match register {
+        0..=34 => X86[register as usize].to_string(),
+        154..=161 => format!("XMM{}", register - 154),
+        252..=259 => format!("XMM{}", register - 252 + 8),
+        324 => String::from("SIL"),
+        325 => String::from("DIL"),
+        326 => String::from("BPL"),
+        327 => String::from("SPL"),
+        328..=343 => AMD64[(register - 328) as usize].to_string(),
+        344..=351 => format!("R{}B", register - 344 + 8),
+        352..=359 => format!("R{}W", register - 352 + 8),
+        360..=367 => format!("R{}D", register - 360 + 8),
+        30006 => String::from("VFRAME"),
+        _ => format!("REG{}", register)
+    }
+}
+
+/*
+Stack relative location, e.g [RSP+0x28] or [EBP-0x8].
+*/
+fn relative_location(base: &str, offset: i32) -> String {
+    if offset < 0 {
+        format!("[{}-{:#x}]", base, -(offset as i64))
+    } else {
+        format!("[{}+{:#x}]", base, offset)
+    }
+}
+
+fn read_cstring(data: &[u8], offset: usize) -> String {
+    let rest = data.get(offset..).unwrap_or(&[]);
+    let length = rest.iter().position(|&c| c == 0).unwrap_or(rest.len());
+    String::from_utf8_lossy(&rest[..length]).into_owned()
+}
+
+/*
+Frame pointer registers encoded in S_FRAMEPROC flags (bits 14-15 for locals, 16-17 for parameters).
+*/
+fn frame_pointer(encoded: u32, machine: pdb::MachineType) -> &'static str {
+    match (encoded & 3, machine) {
+        (1, pdb::MachineType::Amd64) => "RSP",
+        (1, _) => "VFRAME",
+        (2, pdb::MachineType::Amd64) => "RBP",
+        (2, _) => "EBP",
+        (3, pdb::MachineType::Amd64) => "R13",
+        (3, _) => "EBX",
+        _ => "FRAME"
+    }
+}
+
+/*
+Number of stack / register variables at the start of a scope that are parameters
+(the arguments, plus "this" for member functions).
+*/
+fn parameter_count(type_finder: &TypeFinder, function_type: Option<TypeIndex>) -> usize {
+    let parsed = function_type.and_then(|function_type| type_finder.find(function_type).and_then(|typ| typ.parse()).ok());
+    let (argument_list, this_count) = match parsed {
+        Some(pdb::TypeData::Procedure(procedure)) => (procedure.argument_list, 0),
+        Some(pdb::TypeData::MemberFunction(function)) => (function.argument_list, function.this_pointer_type.is_some() as usize),
+        _ => return 0
+    };
+
+    match type_finder.find(argument_list).and_then(|typ| typ.parse()) {
+        Ok(pdb::TypeData::ArgumentList(list)) => list.arguments.len() + this_count,
+        _ => this_count
+    }
+}
+
+struct ScopeParser<'a, 's> {
+    type_finder: &'a TypeFinder<'a>,
+    address_map: &'a pdb::AddressMap<'s>,
+    machine: pdb::MachineType,
+    // S_FRAMEPROC flags, needed for frame pointer relative def-ranges
+    frame_flags: u32,
+    // Stack and register variables don't say whether they're parameters, but the parameters
+    // always come first
+    unclaimed_parameters: usize,
+    variables: Vec<Variable>,
+}
+
+impl<'a, 's> ScopeParser<'a, 's> {
+    fn push(&mut self, name: String, type_index: TypeIndex, is_parameter: Option<bool>, location: Option<Location>) {
+        let is_parameter = is_parameter.unwrap_or_else(|| {
+            let claimed = self.unclaimed_parameters > 0;
+            self.unclaimed_parameters = self.unclaimed_parameters.saturating_sub(1);
+            claimed
+        });
+
+        self.variables.push(Variable {
+            name,
+            type_name: symbols::type_name(self.type_finder, type_index),
+            is_parameter,
+            locations: location.into_iter().collect(),
+        });
+    }
+
+    /*
+    Reads the address range of a def-range record.
+    */
+    fn range(&self, data: &[u8], offset: usize) -> (Option<u32>, Option<u32>) {
+        let start = match (read_u32(data, offset), read_u16(data, offset + 4)) {
+            (Some(offset), Some(section)) => PdbInternalSectionOffset {offset, section}.to_rva(self.address_map).map(|rva| rva.0),
+            _ => None
+        };
+        (start, read_u16(data, offset + 6).map(u32::from))
+    }
+
+    /*
+    Parses a def-range record into a location of the last S_LOCAL.
+    */
+    fn def_range(&mut self, kind: u16, data: &[u8]) {
+        let frame_base = match self.variables.last() {
+            Some(variable) if variable.is_parameter => frame_pointer(self.frame_flags >> 16, self.machine),
+            _ => frame_pointer(self.frame_flags >> 14, self.machine)
+        };
+
+        let (description, range_offset) = match kind {
+            S_DEFRANGE | S_DEFRANGE_SUBFIELD => (String::from("<program>"), Some(if kind == S_DEFRANGE { 6 } else { 10 })),
+            S_DEFRANGE_REGISTER => (register_name(read_u16(data, 2).unwrap_or(0)), Some(6)),
+            S_DEFRANGE_FRAMEPOINTER_REL => (relative_location(frame_base, read_u32(data, 2).unwrap_or(0) as i32), Some(6)),
+            S_DEFRANGE_SUBFIELD_REGISTER => {
+                let parent_offset = read_u32(data, 6).unwrap_or(0) & 0xfff;
+                (format!("{} (at +{:#x})", register_name(read_u16(data, 2).unwrap_or(0)), parent_offset), Some(10))
+            },
+            S_DEFRANGE_FRAMEPOINTER_REL_FULL_SCOPE => (relative_location(frame_base, read_u32(data, 2).unwrap_or(0) as i32), None),
+            S_DEFRANGE_REGISTER_REL => {
+                let base = register_name(read_u16(data, 2).unwrap_or(0));
+                (relative_location(&base, read_u32(data, 6).unwrap_or(0) as i32), Some(10))
+            },
+            _ => return
+        };
+
+        let (rva, length) = match range_offset {
+            Some(range_offset) => self.range(data, range_offset),
+            None => (None, None)
+        };
+        if let Some(variable) = self.variables.last_mut() {
+            variable.locations.push(Location {description, rva, length});
+        }
+    }
+
+    /*
+    Handles one record of the function's scope.
+    */
+    fn symbol(&mut self, symbol: &pdb::Symbol) {
+        let data = symbol.raw_bytes();
+
+        match symbol.raw_kind() {
+            S_FRAMEPROC => {
+                self.frame_flags = read_u32(data, 24).unwrap_or(0);
+                return;
+            },
+            S_BPREL32 => {
+                let offset = read_u32(data, 2).unwrap_or(0) as i32;
+                let type_index = TypeIndex(read_u32(data, 6).unwrap_or(0));
+                let base = if self.machine == pdb::MachineType::Amd64 { "RBP" } else { "EBP" };
+                self.push(read_cstring(data, 10), type_index, None, Some(Location {
+                    description: relative_location(base, offset),
+                    rva: None,
+                    length: None,
+                }));
+                return;
+            },
+            kind @ S_DEFRANGE..=S_DEFRANGE_REGISTER_REL => {
+                self.def_range(kind, data);
+                return;
+            },
+            _ => {}
+        }
+
+        match symbol.parse() {
+            Ok(pdb::SymbolData::RegisterRelative(variable)) => {
+                let location = relative_location(&register_name(variable.register.0), variable.offset);
+                self.push(variable.name.to_string().into_owned(), variable.type_index, None,
+                          Some(Location {description: location, rva: None, length: None}));
+            },
+            Ok(pdb::SymbolData::RegisterVariable(variable)) => {
+                let location = register_name(variable.register.0);
+                self.push(variable.name.to_string().into_owned(), variable.type_index, None,
+                          Some(Location {description: location, rva: None, length: None}));
+            },
+            Ok(pdb::SymbolData::Local(variable)) => {
+                self.push(variable.name.to_string().into_owned(), variable.type_index, Some(variable.flags.isparam), None);
+            },
+            _ => {}
+        }
+    }
+}
+
+/*
+Lists the parameters and locals of a function.
+
+@pdb_name => Path to the PDB file.
+@query    => Function name (exact), or an RVA inside the function.
+
+RETURN
+returns the function's variables, or None if there's no such function.
+*/
+pub fn function_variables(pdb_name: &str, query: FunctionQuery) -> PyResult<Option<FunctionVariables>> {
+    let mut pdb = crate::open_pdb_and_parse(pdb_name)?;
+
+    let type_information = pdb.type_information().map_err(pdb_error)?;
+    let type_finder = crate::fill_item_finder(&type_information)?;
+    let id_information = pdb.id_information().map_err(pdb_error)?;
+    let id_finder = crate::fill_item_finder(&id_information)?;
+    let address_map = pdb.address_map().map_err(pdb_error)?;
+    let debug_information = pdb.debug_information().map_err(pdb_error)?;
+    let machine = debug_information.machine_type().unwrap_or(pdb::MachineType::Unknown);
+
+    let mut modules = debug_information.modules().map_err(pdb_error)?;
+    while let Some(module) = modules.next().map_err(pdb_error)? {
+        let module_info = match pdb.module_info(&module).map_err(pdb_error)? {
+            Some(module_info) => module_info,
+            None => continue
+        };
+
+        let mut iter = module_info.symbols().map_err(pdb_error)?;
+        while let Some(symbol) = iter.next().map_err(pdb_error)? {
+            let procedure = match symbol.parse() {
+                Ok(pdb::SymbolData::Procedure(procedure)) => procedure,
+                _ => continue
+            };
+            let rva = procedure.offset.to_rva(&address_map).map(|rva| rva.0);
+
+            let matches = match query {
+                FunctionQuery::Name(name) => procedure.name.to_string() == name,
+                FunctionQuery::Rva(wanted) => rva.is_some_and(|rva| wanted >= rva && wanted - rva < procedure.len.max(1))
+            };
+            if !matches {
+                continue;
+            }
+
+            let function_type = symbols::procedure_type(&id_finder, symbol.raw_kind(), procedure.type_index);
+            let mut parser = ScopeParser {
+                type_finder: &type_finder,
+                address_map: &address_map,
+                machine,
+                frame_flags: 0,
+                unclaimed_parameters: parameter_count(&type_finder, function_type),
+                variables: Vec::new(),
+            };
+
+            // Everything up to the procedure's S_END, skipping the variables of inlined functions
+            let mut inline_end: Option<pdb::SymbolIndex> = None;
+            while let Some(symbol) = iter.next().map_err(pdb_error)? {
+                if symbol.index() >= procedure.end {
+                    break;
+                }
+                if let Some(end) = inline_end {
+                    if symbol.index() <= end {
+                        continue;
+                    }
+                    inline_end = None;
+                }
+                if let Ok(pdb::SymbolData::InlineSite(site)) = symbol.parse() {
+                    inline_end = Some(site.end);
+                    continue;
+                }
+                parser.symbol(&symbol);
+            }
+
+            let mut result = FunctionVariables {
+                function: procedure.name.to_string().into_owned(),
+                rva,
+                parameters: Vec::new(),
+                locals: Vec::new(),
+            };
+            for variable in parser.variables {
+                if variable.is_parameter {
+                    result.parameters.push(variable);
+                } else {
+                    result.locals.push(variable);
+                }
+            }
+            return Ok(Some(result));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fixture;
+
+    #[test]
+    fn lists_parameters_and_locals() {
+        let variables = function_variables(&fixture("symbols.pdb"), FunctionQuery::Name("symbols::KeWaitForSingleObject")).unwrap().unwrap();
+        assert_eq!(variables.rva, Some(0x1000));
+        assert!(variables.locals.is_empty());
+
+        let parameters: Vec<(&str, Option<&str>, bool)> = variables.parameters.iter()
+                                                                   .map(|variable| (variable.name.as_str(), variable.type_name.as_deref(), variable.is_parameter))
+                                                                   .collect();
+        assert_eq!(parameters, vec![("_process", Some("struct symbols::Eprocess*"), true), ("count", Some("U32"), true)]);
+
+        // S_DEFRANGE_FRAMEPOINTER_REL, relative to RSP as S_FRAMEPROC says
+        let location = &variables.parameters[1].locations[0];
+        assert_eq!((location.description.as_str(), location.rva, location.length), ("[RSP+0xc]", Some(0x100e), Some(5)));
+    }
+
+    #[test]
+    fn finds_functions_by_rva() {
+        let variables = function_variables(&fixture("symbols.pdb"), FunctionQuery::Rva(0x1024)).unwrap().unwrap();
+        assert_eq!(variables.function, "symbols::_DllMainCRTStartup");
+        assert!(variables.parameters.is_empty());
+
+        assert!(function_variables(&fixture("symbols.pdb"), FunctionQuery::Rva(0x1018)).unwrap().is_none());
+        assert!(function_variables(&fixture("symbols.pdb"), FunctionQuery::Name("KeWaitForSingleObject")).unwrap().is_none());
+    }
+}
@@ -0,0 +1,119 @@
+# Generates kernel.pdb, a types only PDB with NT-like kernel types:
+#   python3 kernel.py kernel.yaml && llvm-pdbutil yaml2pdb -pdb=kernel.pdb kernel.yaml && python3 kernel.py kernel.yaml kernel.pdb
+# The last step sets the packed flag of _PACKED_HEADER, llvm-pdbutil's YAML has no way to.
+# With --new (on both python3 steps) it generates kernel_new.pdb instead, a later build whose
+# _EPROCESS dropped PriorityClass, retyped Flags2, split Spare and grew Flags3.
+import sys
+new = '--new' in sys.argv
+args = [arg for arg in sys.argv[1:] if arg != '--new']
+recs = []
+def add(kind, body):
+    recs.append((kind, body)); return 0x1000 + len(recs) - 1
+PTR = 0x1000C
+VOID, CHAR, UCHAR, SHORT, USHORT, LONG, ULONG, QUAD, UQUAD, PVOID, WCHAR, ULONG64 = 0x3, 0x10, 0x20, 0x11, 0x21, 0x12, 0x22, 0x13, 0x23, 0x603, 0x71, 0x23
+def fwd(kind, name, union=False):
+    return add('LF_UNION' if union else 'LF_STRUCTURE', dict(MemberCount=0, Options='[ None, ForwardReference ]', FieldList=0, Name=name, UniqueName="''", DerivationList=0, VTableShape=0, Size=0))
+def ptr(t): return add('LF_POINTER', dict(ReferentType=t, Attrs=PTR))
+def arr(t, size): return add('LF_ARRAY', dict(ElementType=t, IndexType=0x23, Size=size, Name="''"))
+def bf(t, pos, n): return add('LF_BITFIELD', dict(Type=t, BitSize=n, BitOffset=pos))
+def fields(members):
+    return add('LF_FIELDLIST', members)
+def struct(name, members, size, union=False, packed=False):
+    fl = fields([('LF_MEMBER', dict(Attrs=3, Type=t, FieldOffset=o, Name=n)) for (n, t, o) in members])
+    opts = '[ None ]'
+    if union:
+        return add('LF_UNION', dict(MemberCount=len(members), Options=opts, FieldList=fl, Name=name, UniqueName="''", Size=size))
+    return add('LF_STRUCTURE', dict(MemberCount=len(members), Options=opts, FieldList=fl, Name=name, UniqueName="''", DerivationList=0, VTableShape=0, Size=size))
+
+f_list = fwd('s', '_LIST_ENTRY'); p_list = ptr(f_list)
+f_eproc = fwd('s', '_EPROCESS'); p_eproc = ptr(f_eproc)
+f_ethread = fwd('s', '_ETHREAD'); p_ethread = ptr(f_ethread)
+f_peb = fwd('s', '_PEB'); p_peb = ptr(f_peb)
+list_entry = struct('_LIST_ENTRY', [('Flink', p_list, 0), ('Blink', p_list, 8)], 16)
+fast_ref = struct('_EX_FAST_REF', [('Object', PVOID, 0), ('RefCnt', bf(ULONG64, 0, 4), 0), ('Value', ULONG64, 0)], 8, union=True)
+kprocess = struct('_KPROCESS', [
+    ('ProfileListHead', list_entry, 0), ('DirectoryTableBase', ULONG64, 0x10),
+    ('AutoAlignment', bf(ULONG, 0, 1), 0x18), ('DisableBoost', bf(ULONG, 1, 1), 0x18), ('ReservedFlags', bf(ULONG, 2, 30), 0x18),
+    ('ProcessFlags', LONG, 0x18),
+    ('BasePriority', CHAR, 0x1c), ('ThreadListHead', list_entry, 0x20)], 0x30)
+enum_fl = fields([('LF_ENUMERATE', dict(Attrs=3, Value=v, Name=n)) for n, v in [('PsActive', 0), ('PsExiting', 1), ('PsTerminated', 0x80000000)]])
+ps_state = add('LF_ENUM', dict(NumEnumerators=3, Options='[ None ]', FieldList=enum_fl, Name='_PS_STATE', UniqueName="''", UnderlyingType=0x74))
+unnamed = struct('<unnamed-tag>', [('Value', LONG, 0), ('Low', USHORT, 0), ('High', USHORT, 2)], 4, union=True)
+c_short = add('LF_MODIFIER', dict(ModifiedType=USHORT, Modifiers='[ None, Const ]'))
+row = arr(c_short, 6)
+if new:
+    eprocess = struct('_EPROCESS', [
+        ('Pcb', kprocess, 0), ('UniqueProcessId', PVOID, 0x30), ('ActiveProcessLinks', list_entry, 0x38),
+        ('Token', fast_ref, 0x48), ('ImageFileName', arr(UCHAR, 15), 0x50),
+        ('State', ps_state, 0x60), ('u', unnamed, 0x64), ('Peb', p_peb, 0x68), ('ActiveThread', p_ethread, 0x70),
+        ('Matrix', arr(row, 12), 0x78), ('Flags2', LONG, 0x84),
+        ('CrossThreadFlags', ULONG, 0x88), ('Terminated', bf(ULONG, 0, 1), 0x88), ('Spare', bf(ULONG, 1, 30), 0x88),
+        ('Exiting', bf(ULONG, 31, 1), 0x88), ('Flags3', ULONG, 0x90), ('ExitTime', QUAD, 0x98)], 0xa0)
+else:
+    eprocess = struct('_EPROCESS', [
+        ('Pcb', kprocess, 0), ('UniqueProcessId', PVOID, 0x30), ('ActiveProcessLinks', list_entry, 0x38),
+        ('Token', fast_ref, 0x48), ('ImageFileName', arr(UCHAR, 15), 0x50), ('PriorityClass', UCHAR, 0x5f),
+        ('State', ps_state, 0x60), ('u', unnamed, 0x64), ('Peb', p_peb, 0x68), ('ActiveThread', p_ethread, 0x70),
+        ('Matrix', arr(row, 12), 0x78), ('Flags2', ULONG, 0x84),
+        ('CrossThreadFlags', ULONG, 0x88), ('Terminated', bf(ULONG, 0, 1), 0x88), ('Spare', bf(ULONG, 1, 31), 0x88),
+        ('ExitTime', QUAD, 0x90)], 0x98)
+ethread = struct('_ETHREAD', [('ThreadsProcess', p_eproc, 0), ('ThreadListEntry', list_entry, 8), ('Cid', arr(PVOID, 16), 0x18)], 0x28)
+packed = struct('_PACKED_HEADER', [('Type', UCHAR, 0), ('Length', ULONG, 1), ('Tag', USHORT, 5)], 7, packed=True)
+# MSVC starts a new storage unit whenever the bitfield type changes
+mix = struct('_MIX', [('a', bf(UCHAR, 0, 1), 0), ('b', bf(ULONG, 0, 1), 4), ('c', bf(UCHAR, 0, 1), 8)], 0xc)
+# Pointer to a type that doesn't exist, for code that has to survive corrupt PDBs
+dangling = ptr(0x7fff)
+
+out = []
+w = out.append
+w(('''---
+MSF:
+  SuperBlock:
+    BlockSize:       4096
+    FreeBlockMap:    2
+    NumBlocks:       0
+    NumDirectoryBytes: 0
+    Unknown1:        0
+    BlockMapAddr:    0
+  NumDirectoryBlocks: 0
+  DirectoryBlocks: [ ]
+  NumStreams:      0
+  FileSize:        0
+PdbStream:
+  Age:             %d
+  Guid:            '{11112222-3333-4444-5555-66667777%s}'
+  Signature:       1
+  Features:        [ VC140 ]
+  Version:         VC70
+DbiStream:
+  VerHeader:       V70
+  Age:             %d
+  BuildNumber:     36363
+  PdbDllVersion:   0
+  PdbDllRbld:      0
+  Flags:           0
+  MachineType:     Amd64
+TpiStream:
+  Version:         VC80
+  Records:''') % ((3, '9999', 3) if new else (2, '8888', 2)))
+keys = {'LF_STRUCTURE': 'Class', 'LF_UNION': 'Union', 'LF_POINTER': 'Pointer', 'LF_ARRAY': 'Array', 'LF_BITFIELD': 'BitField', 'LF_FIELDLIST': 'FieldList', 'LF_ENUM': 'Enum', 'LF_MODIFIER': 'Modifier', 'LF_MEMBER': 'DataMember', 'LF_ENUMERATE': 'Enumerator'}
+for kind, body in recs:
+    w('    - Kind:            %s' % kind)
+    if kind == 'LF_FIELDLIST':
+        w('      FieldList:')
+        for mk, mb in body:
+            w('        - Kind:            %s' % mk)
+            w('          %s:' % keys[mk])
+            for k, v in mb.items(): w('            %s: %s' % (k, v))
+    else:
+        w('      %s:' % keys[kind])
+        for k, v in body.items(): w('        %s: %s' % (k, v))
+w('IpiStream:\n  Version:         VC80\n  Records: []\n...')
+open(args[0], 'w').write('\n'.join(out) + '\n')
+
+if len(args) > 1:
+    data = bytearray(open(args[1], 'rb').read())
+    at = data.index(b'_PACKED_HEADER\0')
+    assert data[at - 16:at - 14] == b'\0\0'
+    data[at - 16] = 1
+    open(args[1], 'wb').write(data)
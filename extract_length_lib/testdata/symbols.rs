@@ -0,0 +1,29 @@
+// Source of symbols.pdb / symbols.dll (publics, globals, procedures and their locals). Rebuild with
+//   rustc +nightly --target x86_64-pc-windows-msvc --crate-type=lib --emit=obj -C debuginfo=2 -C panic=abort symbols.rs
+//   rust-lld -flavor link /dll /noentry /debug /nodefaultlib /out:symbols.dll /pdb:symbols.pdb /export:KeWaitForSingleObject symbols.o
+#![feature(no_core, lang_items, auto_traits)]
+#![no_core]
+#![allow(internal_features)]
+#[lang = "pointee_sized"] pub trait PointeeSized {}
+#[lang = "meta_sized"] pub trait MetaSized: PointeeSized {}
+#[lang = "sized"] pub trait Sized: MetaSized {}
+#[lang = "copy"] pub trait Copy {}
+#[lang = "freeze"] pub unsafe auto trait Freeze {}
+impl Copy for u32 {}
+#[repr(C)]
+pub struct ListEntry { pub flink: *mut ListEntry, pub blink: *mut ListEntry }
+#[repr(C)]
+pub struct Inner { pub a: u32, pub b: u64 }
+#[repr(C)]
+pub struct Eprocess { pub pid: u32, pub links: ListEntry, pub inner: Inner, pub name: [u8; 16], pub token: *mut Inner }
+#[no_mangle]
+pub static mut PsActiveProcessHead: ListEntry = ListEntry { flink: 0 as *mut ListEntry, blink: 0 as *mut ListEntry };
+#[no_mangle]
+pub extern "C" fn KeWaitForSingleObject(_process: *mut Eprocess, count: u32) -> u32 {
+    let local = count;
+    local
+}
+#[no_mangle]
+pub extern "C" fn _DllMainCRTStartup() -> u32 { 1 }
+#[lang = "drop_glue"]
+pub unsafe fn drop_in_place<T: PointeeSized>(_p: *mut T) {}
@@ -1,5 +1,5 @@
 from pathlib import Path
-import re, os, pefile, struct, argparse, pyastyle, httplib2
+import re, os, struct, argparse, pyastyle
 from enum import IntEnum
 from collections import deque, namedtuple
 from os.path import exists
@@ -32,6 +32,9 @@ variable_capture_regex = r"[A-Za-z_]([A-Za-z0-9_]{0,254})$"
 union_capture_regex = r"union[ ]{0,}(\[\[[a-zA-Z0-9_]+\]\][ ]{1,}){0,}(\[\[[a-zA-Z0-9_]+\]\]){0,1}[a-zA-Z_]{0,1}[a-zA-Z_0-9]{0,}[ ]{0,}{"
 struct_capture_regex = r"^struct[ ]{1,}[a-zA-Z_]{0,1}[a-zA-Z_0-9]{0,}[ ]{0,}{"
 
+# Symbol path PDB files are pulled with (symstore layout under resources)
+resources_symbol_path = "srv*resources*https://msdl.microsoft.com/download/symbols"
+
 # Dictionary for 64 bit compiler options
 basic_types_64_bit = {
 	"LARGE_INTEGER": 8,
@@ -668,46 +671,6 @@ def add_length_to_type(prev_length: int, type_str: str, type: type_enum, options
 	return '**Could not find length**', prev_length
 
 
-def get_guid(path):
-	try:
-		dll = pefile.PE(path)
-		sigs = []
-		for i in range(len(dll.DIRECTORY_ENTRY_DEBUG)):
-			if (dll.DIRECTORY_ENTRY_DEBUG[i].struct.Type == 2):
-				rva = dll.DIRECTORY_ENTRY_DEBUG[i].struct.AddressOfRawData
-				tmp = ''
-				tmp += '%0.*X' % (8, dll.get_dword_at_rva(rva+4))
-				tmp += '%0.*X' % (4, dll.get_word_at_rva(rva+4+4))
-				tmp += '%0.*X' % (4, dll.get_word_at_rva(rva+4+4+2))
-				x = dll.get_word_at_rva(rva+4+4+2+2)
-				tmp += '%0.*X' % (4, struct.unpack('<H',struct.pack('>H',x))[0])
-				x = dll.get_word_at_rva(rva+4+4+2+2+2)
-				tmp += '%0.*X' % (4, struct.unpack('<H',struct.pack('>H',x))[0])
-				x = dll.get_word_at_rva(rva+4+4+2+2+2+2)
-				tmp += '%0.*X' % (4, struct.unpack('<H',struct.pack('>H',x))[0])
-				x = dll.get_word_at_rva(rva+4+4+2+2+2+2+2)
-				tmp += '%0.*X' % (4, struct.unpack('<H',struct.pack('>H',x))[0])
-				tmp += '%0.*X' % (1, dll.get_word_at_rva(rva+4+4+2+2+2+2+2+2))
-
-				sigs.append(tmp)
-		return sigs
-	except AttributeError as e:
-		print('Error appends during %s parsing' % dll_path)
-	
-def download_pdb(guid, dll_name_no_ext):
-	url = "http://msdl.microsoft.com/download/symbols/%s.pdb/%s/%s.pdb" % (dll_name_no_ext, guid, dll_name_no_ext)
-	conn = httplib2.Http()
-	headers = {"User-Agent": "Microsoft-Symbol-Server/6.12.0002.633"}
-	response, content = conn.request(url, "GET", headers=headers)
-
-	if response.status == 200:
-		with open(f"resources/{dll_name_no_ext + '_' + guid}.pdb", 'wb') as pdb_file:
-			pdb_file.write(content)
-		return True
-	else:
-		print(f"Couldn't download {dll_name_no_ext}.pdb!")
-		return False
-
 def pull_pdb_files(dll_file_name: str, bitness: int):
 	print("")
 	for dll in dll_file_name:
@@ -716,19 +679,21 @@ def pull_pdb_files(dll_file_name: str, bitness: int):
 		else:
 			download_from_path = f"c:\\Windows\\SysWOW64\\{dll}"
 
-		dll_no_ext = dll[:dll.find(".")]
-		# download_cmd = f"symchk.exe /r {download_from_path} /s SRV*{download_to_path}*https://msdl.microsoft.com/download/symbols"
-		guids = get_guid(download_from_path)
-		for guid in guids:
-			download_to_path = Path.cwd() / Path("resources") / Path(dll_no_ext + "_" + guid + ".pdb")
-			print(f"{dll} GUID: {guid}")
-			print("Downloading PDB file, this could take some time. Please hold on...")
-			if exists(download_to_path):
-				print(f"PDB File {download_to_path} already exists, not download again and contiuing...\n")
-				continue
-			if download_pdb(guid, dll_no_ext):
-				print(f"\"{download_from_path}\" => \"{download_to_path}\"")
+		# Always into resources, the rest of the script only looks for PDBs there
+		# (whatever _NT_SYMBOL_PATH says)
+		print(f"Looking for {dll}'s PDB file (downloading it if needed), this could take some time. Please hold on...")
+		try:
+			download_to_path = pdb.fetch_pdb_for_image(download_from_path, resources_symbol_path)
+		except (ValueError, IOError) as e:
+			print(f"Couldn't get {dll}'s PDB file: {e}")
 			print("")
+			continue
+
+		if download_to_path is not None:
+			print(f"\"{download_from_path}\" => \"{download_to_path}\"")
+		else:
+			print(f"Couldn't find {dll}'s PDB file!")
+		print("")
 
 	print("Finished pulling pdb files.")
 
@@ -779,6 +744,37 @@ def stringify_dict(struct_name, pdb_dict):
 	return f"struct {struct_name} {{ {nl}{'{}'.format(nl).join(['{} {}// {}: 0x{}'.format(put_only_one_semicolon(y), tab_format, str(x).zfill(4), hex(x)[2:].zfill(4)) for x,y in pdb_dict.items()])} {nl}}}"
 
 		
+def dt_struct(struct_name: str, fields, depth: int):
+	"""
+	This function prints the struct from every PDB that has it, like WinDbg's dt.
+
+	Args:
+		struct_name (str): struct's name
+		fields (list): field name wildcards (None for every field)
+		depth (int): levels of embedded structs to expand
+	"""
+	outputs = []
+	for pdb_file in get_current_file_gen(Path("resources")):
+		try:
+			outputs.append(pdb.Session(str(pdb_file)).dt(struct_name, fields, depth))
+		except KeyError:
+			continue
+	return "\n".join(outputs)
+
+
+def struct_history(struct_name: str, table_format: str):
+	"""
+	Builds a table of the struct's field offsets across the downloaded PDB files.
+
+	Args:
+		struct_name (str): struct's name
+		table_format (str): csv, markdown or html
+	"""
+	try:
+		return pdb.offset_history_table(struct_name, ["resources"], table_format)
+	except KeyError:
+		return None
+
 def automatically_resolve_struct(struct_name: str, length_type: bool):
 	dll_list = get_current_file_gen(Path("resources"))
 	struct_dict = {}
@@ -828,7 +824,7 @@ def automatically_resolve_struct(struct_name: str, length_type: bool):
 		if length_type:
 			print(f" * Struct: {key}, Size: {val}")
 		else:
-			print(f" * Struct: {key}")
+			print(f" * Struct: {key}" + (f" ({val['source']})" if "source" in val else ""))
 
 	print(" [++] Choose struct (if you don't want any of these, don't write anything): ")
 	choice = input()
@@ -869,6 +865,10 @@ def main():
 	parser.add_argument("--unicode", "-U", help="Set this value to support unicode (TCHAR, TBYTE = WCHAR). Default is true.", action='store_true', default=True)
 	parser.add_argument("--quiet", "-q", help="Set this value to get only the output, no prints.", action='store_true')
 	parser.add_argument('--dlls', '-d', help='A list of DLL files from C:/Windows/System32 separated by a comma', type=str)
+	parser.add_argument("--dt", help="Print the struct like WinDbg's dt instead of as C.", action='store_true')
+	parser.add_argument("--recurse", "-r", type=int, help="With --dt, levels of embedded structs to expand (dt -r).", default=0)
+	parser.add_argument("--fields", "-f", type=str, help="With --dt, field name wildcards separated by a comma (dt nt!_EPROCESS Image*).")
+	parser.add_argument("--history", type=str, choices=["csv", "markdown", "html"], help="Print a table of the struct's field offsets in every PDB instead of the struct.")
 	
 
 	options = parser.parse_args()
@@ -908,6 +908,14 @@ def main():
 
 	print("Enter the struct's name: ")
 	struct_name = input()
+
+	if options.dt:
+		fields = None if options.fields is None else [field.strip() for field in options.fields.split(',')]
+		print(dt_struct(struct_name, fields, options.recurse) or f" [--] Couldn't find {struct_name} in the PDB files...")
+		return
+	if options.history is not None:
+		print(struct_history(struct_name, options.history) or f" [--] Couldn't find {struct_name} in the PDB files...")
+		return
 	conditional_print(" [++] Attempting to automatically get structure...", quiet_mode_set)
 
 	sleep(0.5)